@@ -0,0 +1,3 @@
+pub mod mempool;
+pub mod transaction;
+pub mod wallet;
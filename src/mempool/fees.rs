@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::mempool::pool::Txid;
+use crate::transaction::encoding::{write_atomically, ByteReader};
+
+pub const MAX_CONFIRM_TARGET: usize = 48;
+// Per-block decay of the history, a half-life of about 144 blocks.
+const DECAY: f64 = 0.9952;
+const MIN_BUCKET_FEERATE: f64 = 1.0;
+const MAX_BUCKET_FEERATE: f64 = 10_000.0;
+const BUCKET_SPACING: f64 = 1.05;
+// A range of buckets needs this many transactions per block of history to be judged.
+const SUFFICIENT_TXS_PER_BLOCK: f64 = 0.1;
+const ECONOMICAL_SUCCESS_RATE: f64 = 0.85;
+const CONSERVATIVE_SUCCESS_RATE: f64 = 0.95;
+
+const FEE_FILE_MAGIC: &[u8; 8] = b"RCFEEEST";
+const FEE_FILE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EstimateMode {
+    // Lowest feerate that confirmed within the target 85% of the time.
+    Economical,
+    // Needs 95% success and never comes in below the economical estimate.
+    Conservative,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TrackedTx {
+    // Height of the first block the transaction could have confirmed in.
+    height: u32,
+    bucket: usize,
+    feerate: f64,
+}
+
+// Learns from the mempool how long transactions at each feerate take to confirm. Every
+// tracked transaction lands in an exponentially spaced feerate bucket; when it confirms,
+// it counts as a success for every target at or beyond the blocks it took, and when it
+// leaves the mempool unconfirmed it counts as a failure for the blocks it sat through.
+// All counts decay each block so the estimates follow the current fee market.
+#[derive(Debug, Clone)]
+pub struct FeeEstimator {
+    bounds: Vec<f64>,
+    tx_count: Vec<f64>,
+    feerate_sum: Vec<f64>,
+    confirmed: Vec<Vec<f64>>,
+    failed: Vec<Vec<f64>>,
+    tracked: HashMap<Txid, TrackedTx>,
+    best_height: Option<u32>,
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        FeeEstimator::new()
+    }
+}
+
+impl FeeEstimator {
+    pub fn new() -> Self {
+        let mut bounds = Vec::new();
+        let mut bound = MIN_BUCKET_FEERATE;
+        while bound <= MAX_BUCKET_FEERATE {
+            bounds.push(bound);
+            bound *= BUCKET_SPACING;
+        }
+        bounds.push(f64::INFINITY);
+        let buckets = bounds.len();
+        FeeEstimator {
+            bounds,
+            tx_count: vec![0.0; buckets],
+            feerate_sum: vec![0.0; buckets],
+            confirmed: vec![vec![0.0; buckets]; MAX_CONFIRM_TARGET],
+            failed: vec![vec![0.0; buckets]; MAX_CONFIRM_TARGET],
+            tracked: HashMap::new(),
+            best_height: None,
+        }
+    }
+
+    pub fn tracked_count(&self) -> usize {
+        self.tracked.len()
+    }
+
+    pub fn best_height(&self) -> Option<u32> {
+        self.best_height
+    }
+
+    // Starts watching a transaction that entered the mempool while the next block to be
+    // connected is `height`.
+    pub fn track(&mut self, txid: Txid, fee: u64, vsize: u64, height: u32) {
+        if vsize == 0 {
+            return;
+        }
+        let feerate = fee as f64 / vsize as f64;
+        let bucket = self.bounds.partition_point(|bound| *bound < feerate);
+        self.tracked.entry(txid).or_insert(TrackedTx { height, bucket, feerate });
+    }
+
+    // Records the block at `height`. `confirmed` are the txids it contains and
+    // `in_mempool` tells which of the remaining tracked transactions are still waiting;
+    // the rest were evicted, replaced or conflicted away.
+    pub fn process_block(&mut self, height: u32, confirmed: &[Txid], in_mempool: impl Fn(&Txid) -> bool) {
+        for counts in [&mut self.tx_count, &mut self.feerate_sum].into_iter()
+            .chain(self.confirmed.iter_mut())
+            .chain(self.failed.iter_mut())
+        {
+            counts.iter_mut().for_each(|count| *count *= DECAY);
+        }
+
+        for txid in confirmed {
+            if let Some(tx) = self.tracked.remove(txid) {
+                let blocks = (height.saturating_sub(tx.height) as usize + 1).min(MAX_CONFIRM_TARGET);
+                self.tx_count[tx.bucket] += 1.0;
+                self.feerate_sum[tx.bucket] += tx.feerate;
+                for target in blocks..=MAX_CONFIRM_TARGET {
+                    self.confirmed[target - 1][tx.bucket] += 1.0;
+                }
+            }
+        }
+
+        let failed = &mut self.failed;
+        self.tracked.retain(|txid, tx| {
+            if in_mempool(txid) {
+                return true;
+            }
+            let waited = (height.saturating_sub(tx.height) as usize).min(MAX_CONFIRM_TARGET);
+            for target in 1..=waited {
+                failed[target - 1][tx.bucket] += 1.0;
+            }
+            false
+        });
+        self.best_height = Some(height);
+    }
+
+    // The feerate in sat/vB needed to confirm within `target_blocks`, or None without
+    // enough history. Targets beyond MAX_CONFIRM_TARGET are answered for the maximum.
+    pub fn estimate_fee(&self, target_blocks: usize, mode: EstimateMode) -> Option<f64> {
+        if target_blocks == 0 {
+            return None;
+        }
+        let target = target_blocks.min(MAX_CONFIRM_TARGET);
+        match mode {
+            EstimateMode::Economical => self.estimate(target, ECONOMICAL_SUCCESS_RATE),
+            EstimateMode::Conservative => {
+                let conservative = self.estimate(target, CONSERVATIVE_SUCCESS_RATE)?;
+                Some(self.estimate(target, ECONOMICAL_SUCCESS_RATE).map_or(conservative, |economical| conservative.max(economical)))
+            }
+        }
+    }
+
+    // Walks the buckets from the highest feerate down, grouping them into ranges with
+    // enough data, and stops at the first range that misses `success_rate`. The answer
+    // is the average feerate of the last range that passed.
+    fn estimate(&self, target: usize, success_rate: f64) -> Option<f64> {
+        let mut waiting = vec![0.0; self.bounds.len()];
+        if let Some(best_height) = self.best_height {
+            for tx in self.tracked.values() {
+                if (best_height + 1).saturating_sub(tx.height) as usize >= target {
+                    waiting[tx.bucket] += 1.0;
+                }
+            }
+        }
+
+        let sufficient = SUFFICIENT_TXS_PER_BLOCK / (1.0 - DECAY);
+        let mut passing = None;
+        let mut range_end = self.bounds.len() - 1;
+        let (mut successes, mut total) = (0.0, 0.0);
+        for bucket in (0..self.bounds.len()).rev() {
+            successes += self.confirmed[target - 1][bucket];
+            total += self.tx_count[bucket] + self.failed[target - 1][bucket] + waiting[bucket];
+            if total < sufficient {
+                continue;
+            }
+            if successes / total < success_rate {
+                break;
+            }
+            passing = Some((bucket, range_end));
+            (successes, total) = (0.0, 0.0);
+            range_end = bucket.saturating_sub(1);
+        }
+
+        let (start, end) = passing?;
+        let count: f64 = self.tx_count[start..=end].iter().sum();
+        if count == 0.0 {
+            return None;
+        }
+        Some(self.feerate_sum[start..=end].iter().sum::<f64>() / count)
+    }
+
+    // Saves the decayed history. Transactions still being tracked are not saved; after a
+    // restart only transactions seen from then on are followed.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(FEE_FILE_MAGIC);
+        bytes.extend_from_slice(&FEE_FILE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.best_height.map_or(u32::MAX, |height| height).to_le_bytes());
+        bytes.extend_from_slice(&(self.bounds.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(MAX_CONFIRM_TARGET as u32).to_le_bytes());
+        for counts in [&self.tx_count, &self.feerate_sum].into_iter()
+            .chain(self.confirmed.iter())
+            .chain(self.failed.iter())
+        {
+            for count in counts {
+                bytes.extend_from_slice(&count.to_le_bytes());
+            }
+        }
+
+        write_atomically(path, &bytes)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        let mut reader = ByteReader::new(&bytes);
+        if reader.read_bytes(FEE_FILE_MAGIC.len())? != FEE_FILE_MAGIC {
+            return Err("Not a fee estimates file".to_string());
+        }
+        let version = reader.read_u32()?;
+        if version != FEE_FILE_VERSION {
+            return Err(format!("Unsupported fee estimates file version {version}"));
+        }
+
+        let mut estimator = FeeEstimator::new();
+        let best_height = reader.read_u32()?;
+        estimator.best_height = (best_height != u32::MAX).then_some(best_height);
+        let buckets = reader.read_u32()? as usize;
+        let targets = reader.read_u32()? as usize;
+        if buckets != estimator.bounds.len() || targets != MAX_CONFIRM_TARGET {
+            return Err(format!("Fee estimates file has {buckets} buckets and {targets} targets, expected {} and {MAX_CONFIRM_TARGET}", estimator.bounds.len()));
+        }
+        for counts in [&mut estimator.tx_count, &mut estimator.feerate_sum].into_iter()
+            .chain(estimator.confirmed.iter_mut())
+            .chain(estimator.failed.iter_mut())
+        {
+            for count in counts.iter_mut() {
+                *count = f64::from_le_bytes(reader.read_array()?);
+                if !count.is_finite() || *count < 0.0 {
+                    return Err("Fee estimates file holds an invalid count".to_string());
+                }
+            }
+        }
+        if !reader.is_empty() {
+            return Err("Trailing bytes in fee estimates file".to_string());
+        }
+        Ok(estimator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds `blocks` blocks in which 20 transactions at 50 sat/vB confirm in the next
+    // block and 20 at 5 sat/vB take four blocks.
+    fn trained() -> FeeEstimator {
+        let mut estimator = FeeEstimator::new();
+        let mut next_id = 0u32;
+        let mut txid = || {
+            next_id += 1;
+            let mut txid = [0u8; 32];
+            txid[..4].copy_from_slice(&next_id.to_le_bytes());
+            txid
+        };
+        let mut slow: Vec<(u32, Vec<Txid>)> = Vec::new();
+        for height in 0..200 {
+            let fast: Vec<Txid> = (0..20).map(|_| txid()).collect();
+            for id in &fast {
+                estimator.track(*id, 5_000, 100, height);
+            }
+            let cheap: Vec<Txid> = (0..20).map(|_| txid()).collect();
+            for id in &cheap {
+                estimator.track(*id, 500, 100, height);
+            }
+            slow.push((height, cheap));
+
+            let mut confirmed = fast;
+            if let Some(index) = slow.iter().position(|(entered, _)| entered + 3 == height) {
+                confirmed.extend(slow.remove(index).1);
+            }
+            let waiting: Vec<Txid> = slow.iter().flat_map(|(_, txids)| txids.iter().copied()).collect();
+            estimator.process_block(height, &confirmed, |txid| waiting.contains(txid));
+        }
+        estimator
+    }
+
+    #[test]
+    fn test_estimates_follow_confirmation_history() {
+        let estimator = trained();
+        assert_eq!(estimator.best_height(), Some(199));
+        let fast = estimator.estimate_fee(1, EstimateMode::Economical).unwrap();
+        assert!((fast - 50.0).abs() < 1e-6, "{fast}");
+        let relaxed = estimator.estimate_fee(6, EstimateMode::Economical).unwrap();
+        assert!((relaxed - 5.0).abs() < 1e-6, "{relaxed}");
+        assert!(estimator.estimate_fee(6, EstimateMode::Conservative).unwrap() >= relaxed);
+        assert_eq!(estimator.estimate_fee(0, EstimateMode::Economical), None);
+        assert_eq!(FeeEstimator::new().estimate_fee(3, EstimateMode::Economical), None);
+    }
+
+    #[test]
+    fn test_history_survives_save_and_load() {
+        let estimator = trained();
+        let path = std::env::temp_dir().join(format!("rust-coin-fee-estimates-{}.dat", std::process::id()));
+        estimator.save(&path).unwrap();
+        let loaded = FeeEstimator::load(&path).unwrap();
+        assert_eq!(loaded.best_height(), Some(199));
+        assert_eq!(loaded.tracked_count(), 0);
+        assert_eq!(
+            loaded.estimate_fee(6, EstimateMode::Economical),
+            FeeEstimator { tracked: HashMap::new(), ..estimator }.estimate_fee(6, EstimateMode::Economical)
+        );
+
+        fs::write(&path, b"RCFEEEST").unwrap();
+        assert!(FeeEstimator::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
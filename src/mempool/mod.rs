@@ -0,0 +1,8 @@
+pub mod fees;
+pub mod orphan;
+pub mod package;
+pub mod persist;
+pub mod policy;
+pub mod pool;
+pub mod rbf;
+pub mod view;
@@ -0,0 +1,187 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::Rng;
+
+use crate::mempool::pool::Txid;
+use crate::transaction::encoding::hex_encode;
+use crate::transaction::transaction::{generate_txid, Transaction, UtxoRef};
+
+pub const DEFAULT_MAX_ORPHAN_COUNT: usize = 100;
+pub const DEFAULT_MAX_ORPHAN_SIZE: u64 = 5_000_000;
+// Larger orphans are refused outright; they would crowd out everything else.
+pub const MAX_ORPHAN_TX_VSIZE: u64 = 100_000;
+pub const ORPHAN_EXPIRY: u64 = 20 * 60;
+
+#[derive(Debug, Clone)]
+pub struct Orphan {
+    pub tx: Transaction,
+    pub txid: Txid,
+    pub vsize: u64,
+    pub time: u64,
+}
+
+// Transactions whose inputs are not known yet, kept until a parent shows up. Once a
+// limit is hit a random orphan is evicted, so a peer flooding the pool cannot predict
+// which orphans survive.
+pub struct OrphanPool {
+    orphans: HashMap<Txid, Orphan>,
+    by_parent: HashMap<Txid, HashSet<Txid>>,
+    max_count: usize,
+    max_size: u64,
+    total_vsize: u64,
+}
+
+impl Default for OrphanPool {
+    fn default() -> Self {
+        OrphanPool::new()
+    }
+}
+
+impl OrphanPool {
+    pub fn new() -> Self {
+        OrphanPool::with_limits(DEFAULT_MAX_ORPHAN_COUNT, DEFAULT_MAX_ORPHAN_SIZE)
+    }
+
+    pub fn with_limits(max_count: usize, max_size: u64) -> Self {
+        OrphanPool {
+            orphans: HashMap::new(),
+            by_parent: HashMap::new(),
+            max_count,
+            max_size,
+            total_vsize: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.orphans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orphans.is_empty()
+    }
+
+    pub fn total_vsize(&self) -> u64 {
+        self.total_vsize
+    }
+
+    pub fn contains(&self, txid: &Txid) -> bool {
+        self.orphans.contains_key(txid)
+    }
+
+    pub fn get(&self, txid: &Txid) -> Option<&Orphan> {
+        self.orphans.get(txid)
+    }
+
+    // Returns the txids evicted to make room, which may include the new orphan itself.
+    pub fn add(&mut self, tx: Transaction, time: u64) -> Result<Vec<Txid>, String> {
+        let txid = generate_txid(&tx);
+        let vsize = tx.vsize() as u64;
+        if vsize > MAX_ORPHAN_TX_VSIZE {
+            return Err(format!(
+                "Orphan {} is {vsize} vbytes, more than the limit of {MAX_ORPHAN_TX_VSIZE}",
+                hex_encode(&txid)
+            ));
+        }
+        if self.orphans.contains_key(&txid) {
+            return Ok(Vec::new());
+        }
+
+        for input in &tx.inputs {
+            self.by_parent.entry(input.utxo_ref.txid).or_default().insert(txid);
+        }
+        self.total_vsize += vsize;
+        self.orphans.insert(txid, Orphan { tx, txid, vsize, time });
+
+        let mut evicted = Vec::new();
+        let mut rng = rand::rng();
+        while self.orphans.len() > self.max_count || self.total_vsize > self.max_size {
+            let index = rng.random_range(0..self.orphans.len());
+            let victim = *self.orphans.keys().nth(index).unwrap();
+            self.remove(&victim);
+            evicted.push(victim);
+        }
+        Ok(evicted)
+    }
+
+    pub fn remove(&mut self, txid: &Txid) -> Option<Orphan> {
+        let orphan = self.orphans.remove(txid)?;
+        for input in &orphan.tx.inputs {
+            let parent = input.utxo_ref.txid;
+            if let Some(children) = self.by_parent.get_mut(&parent) {
+                children.remove(txid);
+                if children.is_empty() {
+                    self.by_parent.remove(&parent);
+                }
+            }
+        }
+        self.total_vsize -= orphan.vsize;
+        Some(orphan)
+    }
+
+    // Orphans spending an output of `parent`.
+    pub fn children_of(&self, parent: &Txid) -> Vec<Txid> {
+        self.by_parent.get(parent).map_or_else(Vec::new, |children| children.iter().copied().collect())
+    }
+
+    // Removes orphans that arrived before `cutoff`.
+    pub fn expire(&mut self, cutoff: u64) -> Vec<Txid> {
+        let expired: Vec<Txid> = self.orphans.values()
+            .filter(|orphan| orphan.time < cutoff)
+            .map(|orphan| orphan.txid)
+            .collect();
+        for txid in &expired {
+            self.remove(txid);
+        }
+        expired
+    }
+
+    // Drops orphans a block made obsolete: those it confirmed and those spending coins it
+    // spent.
+    pub fn remove_for_block(&mut self, confirmed: &[Txid], spent: &HashSet<UtxoRef>) {
+        let stale: Vec<Txid> = self.orphans.values()
+            .filter(|orphan| {
+                confirmed.contains(&orphan.txid)
+                    || orphan.tx.inputs.iter().any(|input| spent.contains(&input.utxo_ref))
+            })
+            .map(|orphan| orphan.txid)
+            .collect();
+        for txid in &stale {
+            self.remove(txid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::transaction::testing::pay;
+
+    fn orphan(parent: u8) -> Transaction {
+        pay(UtxoRef { txid: [parent; 32], vout: 0 }, 1_000)
+    }
+
+    #[test]
+    fn test_orphan_pool_bounds_and_lookup() {
+        let mut pool = OrphanPool::with_limits(3, DEFAULT_MAX_ORPHAN_SIZE);
+        for parent in 1..=3 {
+            assert!(pool.add(orphan(parent), 10).unwrap().is_empty());
+        }
+        assert_eq!(pool.children_of(&[2u8; 32]), vec![generate_txid(&orphan(2))]);
+
+        let evicted = pool.add(orphan(4), 20).unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(pool.len(), 3);
+        assert!(!pool.contains(&evicted[0]));
+        assert_eq!(pool.total_vsize(), 3 * orphan(1).vsize() as u64);
+
+        let expired = pool.expire(15);
+        assert!(expired.iter().all(|txid| pool.get(txid).is_none()));
+        assert_eq!(pool.len() + expired.len(), 3);
+        let survivor = generate_txid(&orphan(4));
+        if pool.contains(&survivor) {
+            pool.remove_for_block(&[], &HashSet::from([UtxoRef { txid: [4u8; 32], vout: 0 }]));
+        }
+        assert!(pool.is_empty());
+        assert!(pool.children_of(&[4u8; 32]).is_empty());
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+
+use crate::mempool::pool::Txid;
+use crate::transaction::encoding::hex_encode;
+use crate::transaction::transaction::{generate_txid, Transaction};
+
+pub const MAX_PACKAGE_COUNT: usize = 25;
+pub const MAX_PACKAGE_VSIZE: u64 = 101_000;
+
+// Context-free checks on a package before any of it is looked up: size limits, no
+// duplicates, no two members spending the same output, and every member listed after the
+// members it spends from. Packages must be one child with its unconfirmed parents, so
+// every member but the last has to be spent by the last; otherwise a fee-paying
+// transaction could carry any unrelated one into the mempool.
+pub fn check_package(txs: &[Transaction]) -> Result<Vec<Txid>, String> {
+    if txs.is_empty() {
+        return Err("Package is empty".to_string());
+    }
+    if txs.len() > MAX_PACKAGE_COUNT {
+        return Err(format!("Package has {} transactions, more than the limit of {MAX_PACKAGE_COUNT}", txs.len()));
+    }
+    let vsize: u64 = txs.iter().map(|tx| tx.vsize() as u64).sum();
+    if vsize > MAX_PACKAGE_VSIZE {
+        return Err(format!("Package is {vsize} vbytes, more than the limit of {MAX_PACKAGE_VSIZE}"));
+    }
+
+    let txids: Vec<Txid> = txs.iter().map(generate_txid).collect();
+    let members: HashSet<Txid> = txids.iter().copied().collect();
+    if members.len() != txids.len() {
+        return Err("Package contains duplicate transactions".to_string());
+    }
+
+    let mut seen = HashSet::new();
+    let mut spent = HashSet::new();
+    for (index, tx) in txs.iter().enumerate() {
+        for input in &tx.inputs {
+            let parent = input.utxo_ref.txid;
+            if members.contains(&parent) && !seen.contains(&parent) {
+                return Err(format!(
+                    "Package member {index} ({}) is listed before its parent {}",
+                    hex_encode(&txids[index]), hex_encode(&parent)
+                ));
+            }
+            if !spent.insert(input.utxo_ref) {
+                return Err(format!(
+                    "Package member {index} ({}) spends {:?}, which another member also spends",
+                    hex_encode(&txids[index]), input.utxo_ref
+                ));
+            }
+        }
+        seen.insert(txids[index]);
+    }
+
+    let (child, parents) = txs.split_last().unwrap();
+    let spent_by_child: HashSet<Txid> = child.inputs.iter().map(|input| input.utxo_ref.txid).collect();
+    if let Some(index) = (0..parents.len()).find(|index| !spent_by_child.contains(&txids[*index])) {
+        return Err(format!(
+            "Package member {index} ({}) is not a parent of the last member; packages must be a child with its parents",
+            hex_encode(&txids[index])
+        ));
+    }
+    Ok(txids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::transaction::testing::spend;
+    use crate::transaction::transaction::UtxoRef;
+
+    #[test]
+    fn test_package_shape() {
+        let parent = spend(&[UtxoRef { txid: [1u8; 32], vout: 0 }], &[1_000]);
+        let child = spend(&[UtxoRef { txid: generate_txid(&parent), vout: 0 }], &[1_000]);
+        assert_eq!(check_package(&[parent.clone(), child.clone()]).unwrap().len(), 2);
+
+        let err = check_package(&[child.clone(), parent.clone()]).unwrap_err();
+        assert!(err.contains("member 0") && err.contains("before its parent"), "{err}");
+        assert!(check_package(&[parent.clone(), parent.clone()]).unwrap_err().contains("duplicate"));
+
+        let double_spend = spend(&[UtxoRef { txid: [1u8; 32], vout: 0 }, UtxoRef { txid: [2u8; 32], vout: 0 }], &[1_000]);
+        assert!(check_package(&[parent.clone(), double_spend]).unwrap_err().contains("member 1"));
+        assert!(check_package(&[]).is_err());
+
+        let unrelated = spend(&[UtxoRef { txid: [3u8; 32], vout: 0 }], &[1_000]);
+        let err = check_package(&[unrelated.clone(), parent.clone(), child]).unwrap_err();
+        assert!(err.contains("member 0") && err.contains("not a parent"), "{err}");
+        assert!(check_package(&[parent, unrelated]).unwrap_err().contains("not a parent"));
+    }
+}
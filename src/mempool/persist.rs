@@ -0,0 +1,123 @@
+use std::fs;
+use std::path::Path;
+
+use crate::mempool::pool::{Mempool, Txid};
+use crate::transaction::encoding::{write_atomically, write_var_bytes, ByteReader};
+use crate::transaction::transaction::Transaction;
+
+const MEMPOOL_FILE_MAGIC: &[u8; 8] = b"RCMEMPOL";
+const MEMPOOL_FILE_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedTransaction {
+    pub tx: Transaction,
+    pub time: u64,
+    pub fee_delta: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SavedMempool {
+    pub transactions: Vec<SavedTransaction>,
+    // Prioritisation of transactions that were not in the mempool when it was saved.
+    pub fee_deltas: Vec<(Txid, i64)>,
+}
+
+// What came back from a mempool file: the transactions accepted again, and those dropped
+// with the reason they no longer validate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MempoolLoadReport {
+    pub accepted: Vec<Txid>,
+    pub dropped: Vec<(Txid, String)>,
+}
+
+// Layout: magic, version, transaction count, then per transaction the serialized
+// transaction, its arrival time and its fee delta, then the count and (txid, delta)
+// pairs of fee deltas for transactions not in the mempool. Version 1 files end after the
+// transactions. Parents are written before their children so the file can be replayed
+// in order.
+pub fn dump_mempool(mempool: &Mempool, path: &Path) -> Result<usize, String> {
+    let mut entries: Vec<_> = mempool.entries().collect();
+    entries.sort_by_key(|entry| (entry.ancestor_count, entry.time, entry.txid));
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MEMPOOL_FILE_MAGIC);
+    bytes.extend_from_slice(&MEMPOOL_FILE_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+    for entry in &entries {
+        write_var_bytes(&mut bytes, &entry.tx.serialize());
+        bytes.extend_from_slice(&entry.time.to_le_bytes());
+        bytes.extend_from_slice(&entry.fee_delta.to_le_bytes());
+    }
+    let mut pending: Vec<(&Txid, &i64)> = mempool.fee_deltas().iter()
+        .filter(|(txid, _)| !mempool.contains(txid))
+        .collect();
+    pending.sort();
+    bytes.extend_from_slice(&(pending.len() as u64).to_le_bytes());
+    for (txid, fee_delta) in pending {
+        bytes.extend_from_slice(txid);
+        bytes.extend_from_slice(&fee_delta.to_le_bytes());
+    }
+
+    write_atomically(path, &bytes)?;
+    Ok(entries.len())
+}
+
+pub fn read_mempool_file(path: &Path) -> Result<SavedMempool, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    let mut reader = ByteReader::new(&bytes);
+    if reader.read_bytes(MEMPOOL_FILE_MAGIC.len())? != MEMPOOL_FILE_MAGIC {
+        return Err("Not a mempool file".to_string());
+    }
+    let version = reader.read_u32()?;
+    if version != 1 && version != MEMPOOL_FILE_VERSION {
+        return Err(format!("Unsupported mempool file version {version}"));
+    }
+
+    let mut saved = SavedMempool::default();
+    for _ in 0..reader.read_u64()? {
+        let tx = Transaction::deserialize(reader.read_var_bytes()?)?;
+        let time = reader.read_u64()?;
+        let fee_delta = reader.read_u64()? as i64;
+        saved.transactions.push(SavedTransaction { tx, time, fee_delta });
+    }
+    if version >= 2 {
+        for _ in 0..reader.read_u64()? {
+            let txid = reader.read_array()?;
+            saved.fee_deltas.push((txid, reader.read_u64()? as i64));
+        }
+    }
+    if !reader.is_empty() {
+        return Err("Trailing bytes in mempool file".to_string());
+    }
+    Ok(saved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::transaction::testing::pay;
+    use crate::transaction::transaction::UtxoRef;
+
+    #[test]
+    fn test_dump_writes_parents_first() {
+        let mut mempool = Mempool::new();
+        let parent = mempool.add(pay(UtxoRef { txid: [1u8; 32], vout: 0 }, 1_000), 500, 20).unwrap();
+        let child = mempool.add(pay(UtxoRef { txid: parent, vout: 0 }, 1_000), 500, 10).unwrap();
+        mempool.prioritise_transaction(&child, -300);
+        mempool.prioritise_transaction(&[7u8; 32], 1_000);
+
+        let path = std::env::temp_dir().join(format!("rust-coin-mempool-{}.dat", std::process::id()));
+        assert_eq!(dump_mempool(&mempool, &path).unwrap(), 2);
+        let saved = read_mempool_file(&path).unwrap();
+        let transactions = &saved.transactions;
+        assert_eq!(transactions[0], SavedTransaction { tx: mempool.get(&parent).unwrap().tx.clone(), time: 20, fee_delta: 0 });
+        assert_eq!((transactions[1].time, transactions[1].fee_delta), (10, -300));
+        assert_eq!(saved.fee_deltas, vec![([7u8; 32], 1_000)]);
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.push(0);
+        fs::write(&path, bytes).unwrap();
+        assert!(read_mempool_file(&path).unwrap_err().contains("Trailing"));
+        fs::remove_file(&path).unwrap();
+    }
+}
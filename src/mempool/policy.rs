@@ -0,0 +1,322 @@
+use std::fmt;
+
+use crate::mempool::pool::feerate;
+use crate::transaction::coin::Coin;
+use crate::transaction::script::{self, OP_0, OP_1, OP_16};
+use crate::transaction::stats::ScriptType;
+use crate::transaction::transaction::{Transaction, TxOutput};
+
+// Feerates are in satoshis per 1000 virtual bytes.
+pub const DEFAULT_MIN_RELAY_FEE: u64 = 100;
+pub const DEFAULT_DUST_RELAY_FEE: u64 = 3_000;
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+pub const DEFAULT_MAX_DATACARRIER_BYTES: usize = 83;
+pub const MAX_STANDARD_TX_SIGOPS_COST: usize = 16_000;
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1_650;
+pub const MAX_P2SH_SIGOPS: usize = 15;
+const WITNESS_SCALE_FACTOR: usize = 4;
+
+// Why a transaction was refused relay. These are never consensus failures: a block may
+// contain transactions that break any of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PolicyCode {
+    Version,
+    TxSize,
+    ScriptSigSize,
+    ScriptSigNotPushOnly,
+    ScriptPubKey,
+    NonstandardInputs,
+    Dust,
+    MultiOpReturn,
+    DataCarrier,
+    Sigops,
+    MinRelayFee,
+}
+
+impl PolicyCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PolicyCode::Version => "version",
+            PolicyCode::TxSize => "tx-size",
+            PolicyCode::ScriptSigSize => "scriptsig-size",
+            PolicyCode::ScriptSigNotPushOnly => "scriptsig-not-pushonly",
+            PolicyCode::ScriptPubKey => "scriptpubkey",
+            PolicyCode::NonstandardInputs => "bad-txns-nonstandard-inputs",
+            PolicyCode::Dust => "dust",
+            PolicyCode::MultiOpReturn => "multi-op-return",
+            PolicyCode::DataCarrier => "datacarrier",
+            PolicyCode::Sigops => "bad-txns-too-many-sigops",
+            PolicyCode::MinRelayFee => "min-relay-fee-not-met",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyError {
+    pub code: PolicyCode,
+    pub message: String,
+}
+
+impl PolicyError {
+    fn new(code: PolicyCode, message: String) -> Self {
+        PolicyError { code, message }
+    }
+}
+
+// Rendered with a fixed "Non-standard transaction" prefix so logged rejections can
+// still be told apart from consensus failures.
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Non-standard transaction ({}): {}", self.code.as_str(), self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicyConfig {
+    // When unset only the minimum relay fee is enforced.
+    pub require_standard: bool,
+    pub min_relay_fee: u64,
+    pub dust_relay_fee: u64,
+    pub max_standard_weight: usize,
+    // Largest null-data output script, OP_RETURN included.
+    pub max_datacarrier_bytes: usize,
+    pub max_sigops_cost: usize,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        PolicyConfig {
+            require_standard: true,
+            min_relay_fee: DEFAULT_MIN_RELAY_FEE,
+            dust_relay_fee: DEFAULT_DUST_RELAY_FEE,
+            max_standard_weight: MAX_STANDARD_TX_WEIGHT,
+            max_datacarrier_bytes: DEFAULT_MAX_DATACARRIER_BYTES,
+            max_sigops_cost: MAX_STANDARD_TX_SIGOPS_COST,
+        }
+    }
+}
+
+// Witness version 0 with a 20 or 32 byte program, or any later version with 2 to 40 bytes.
+fn is_witness_program(script_pubkey: &[u8]) -> bool {
+    let (Some(&version), Some(&length)) = (script_pubkey.first(), script_pubkey.get(1)) else {
+        return false;
+    };
+    let length = length as usize;
+    if script_pubkey.len() != length + 2 || !(2..=40).contains(&length) {
+        return false;
+    }
+    match version {
+        OP_0 => length == 20 || length == 32,
+        OP_1..=OP_16 => true,
+        _ => false,
+    }
+}
+
+// An output is dust when spending it would cost more than a third of its value at the
+// dust relay fee; the threshold covers the output plus a typical input spending it.
+pub fn dust_threshold(output: &TxOutput, dust_relay_fee: u64) -> u64 {
+    if script::is_unspendable(&output.script_pubkey) {
+        return 0;
+    }
+    let mut size = output.serialize().len() as u64;
+    size += if is_witness_program(&output.script_pubkey) {
+        32 + 4 + 1 + 107 / WITNESS_SCALE_FACTOR as u64 + 4
+    } else {
+        32 + 4 + 1 + 107 + 4
+    };
+    dust_relay_fee * size / 1_000
+}
+
+pub fn is_dust(output: &TxOutput, dust_relay_fee: u64) -> bool {
+    output.amount < dust_threshold(output, dust_relay_fee)
+}
+
+// Signature operation cost: legacy sigops in every scriptSig and output count four times,
+// as do those in the redeem scripts of P2SH inputs, while a P2WPKH input costs one.
+// `spent` holds the coins the inputs spend, in input order.
+pub fn sigop_cost(tx: &Transaction, spent: &[Coin]) -> usize {
+    let legacy: usize = tx.inputs.iter().map(|input| script::sigop_count(&input.script_sig, false)).sum::<usize>()
+        + tx.outputs.iter().map(|output| script::sigop_count(&output.script_pubkey, false)).sum::<usize>();
+    let mut cost = legacy * WITNESS_SCALE_FACTOR;
+    for (input, coin) in tx.inputs.iter().zip(spent) {
+        match ScriptType::classify(&coin.output.script_pubkey) {
+            ScriptType::P2sh => cost += redeem_script(&input.script_sig)
+                .map_or(0, |redeem| script::sigop_count(redeem, true) * WITNESS_SCALE_FACTOR),
+            ScriptType::P2wpkh => cost += 1,
+            _ => {}
+        }
+    }
+    cost
+}
+
+fn redeem_script(script_sig: &[u8]) -> Option<&[u8]> {
+    match script::instructions(script_sig).last()? {
+        Ok((_, data)) => Some(data),
+        Err(_) => None,
+    }
+}
+
+// The relay checks that do not depend on the fee. `spent` holds the coins the inputs
+// spend, in input order; they have already been checked to exist.
+pub fn check_standard(tx: &Transaction, spent: &[Coin], config: &PolicyConfig) -> Result<(), PolicyError> {
+    if !config.require_standard {
+        return Ok(());
+    }
+    if !(1..=2).contains(&tx.version) {
+        return Err(PolicyError::new(PolicyCode::Version, format!("version {} is not relayed", tx.version)));
+    }
+    let weight = tx.weight();
+    if weight > config.max_standard_weight {
+        return Err(PolicyError::new(
+            PolicyCode::TxSize,
+            format!("weight {weight} exceeds the limit of {}", config.max_standard_weight),
+        ));
+    }
+
+    for (index, input) in tx.inputs.iter().enumerate() {
+        if input.script_sig.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return Err(PolicyError::new(
+                PolicyCode::ScriptSigSize,
+                format!("input {index} scriptSig is {} bytes, over {MAX_STANDARD_SCRIPTSIG_SIZE}", input.script_sig.len()),
+            ));
+        }
+        if !script::is_push_only(&input.script_sig) {
+            return Err(PolicyError::new(PolicyCode::ScriptSigNotPushOnly, format!("input {index} scriptSig is not push-only")));
+        }
+    }
+
+    let mut null_data_outputs = 0;
+    for (index, output) in tx.outputs.iter().enumerate() {
+        match ScriptType::classify(&output.script_pubkey) {
+            ScriptType::NullData => {
+                null_data_outputs += 1;
+                if output.script_pubkey.len() > config.max_datacarrier_bytes
+                    || !script::is_push_only(&output.script_pubkey[1..])
+                {
+                    return Err(PolicyError::new(
+                        PolicyCode::DataCarrier,
+                        format!("output {index} carries more than {} bytes or is not pushes only", config.max_datacarrier_bytes),
+                    ));
+                }
+            }
+            ScriptType::Other if !is_witness_program(&output.script_pubkey) => {
+                return Err(PolicyError::new(PolicyCode::ScriptPubKey, format!("output {index} has a non-standard script")));
+            }
+            _ => {
+                if is_dust(output, config.dust_relay_fee) {
+                    return Err(PolicyError::new(
+                        PolicyCode::Dust,
+                        format!(
+                            "output {index} of {} is below the dust threshold of {}",
+                            output.amount, dust_threshold(output, config.dust_relay_fee)
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+    if null_data_outputs > 1 {
+        return Err(PolicyError::new(PolicyCode::MultiOpReturn, format!("{null_data_outputs} OP_RETURN outputs")));
+    }
+
+    for (index, (input, coin)) in tx.inputs.iter().zip(spent).enumerate() {
+        let script_pubkey = &coin.output.script_pubkey;
+        let standard = match ScriptType::classify(script_pubkey) {
+            ScriptType::P2pkh | ScriptType::P2wpkh => true,
+            ScriptType::P2sh => redeem_script(&input.script_sig)
+                .is_some_and(|redeem| script::sigop_count(redeem, true) <= MAX_P2SH_SIGOPS),
+            ScriptType::NullData => false,
+            ScriptType::Other => is_witness_program(script_pubkey),
+        };
+        if !standard {
+            return Err(PolicyError::new(PolicyCode::NonstandardInputs, format!("input {index} spends a non-standard output")));
+        }
+    }
+
+    let cost = sigop_cost(tx, spent);
+    if cost > config.max_sigops_cost {
+        return Err(PolicyError::new(
+            PolicyCode::Sigops,
+            format!("sigop cost {cost} exceeds the limit of {}", config.max_sigops_cost),
+        ));
+    }
+    Ok(())
+}
+
+// `fee` is the modified fee, so prioritisation can lift a transaction over the minimum.
+pub fn check_min_relay_fee(fee: u64, vsize: u64, config: &PolicyConfig) -> Result<(), PolicyError> {
+    let rate = feerate(fee, vsize);
+    if rate < config.min_relay_fee {
+        return Err(PolicyError::new(
+            PolicyCode::MinRelayFee,
+            format!("{rate} sat/kvB is below the minimum relay fee of {} sat/kvB", config.min_relay_fee),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::script::OP_RETURN;
+    use crate::transaction::transaction::{TxInput, UtxoRef};
+
+    fn spending(outputs: Vec<TxOutput>) -> (Transaction, Vec<Coin>) {
+        let tx = Transaction {
+            version: 2,
+            inputs: vec![TxInput::new(UtxoRef { txid: [1u8; 32], vout: 0 })],
+            outputs,
+            locktime: 0,
+        };
+        (tx, vec![Coin::new(TxOutput::p2wpkh(&[1u8; 20], 100_000), 1, false)])
+    }
+
+    fn code(outputs: Vec<TxOutput>) -> Option<PolicyCode> {
+        let (tx, spent) = spending(outputs);
+        check_standard(&tx, &spent, &PolicyConfig::default()).err().map(|e| e.code)
+    }
+
+    #[test]
+    fn test_standard_outputs() {
+        let config = PolicyConfig::default();
+        assert_eq!(dust_threshold(&TxOutput::p2wpkh(&[2u8; 20], 0), config.dust_relay_fee), 294);
+        assert_eq!(dust_threshold(&TxOutput::new(script::p2pkh(&[2u8; 20]), 0), config.dust_relay_fee), 546);
+
+        assert_eq!(code(vec![TxOutput::p2wpkh(&[2u8; 20], 294)]), None);
+        assert_eq!(code(vec![TxOutput::p2wpkh(&[2u8; 20], 293)]), Some(PolicyCode::Dust));
+        let mut taproot = vec![OP_1, 32];
+        taproot.extend_from_slice(&[3u8; 32]);
+        assert_eq!(code(vec![TxOutput::new(taproot, 1_000)]), None);
+        assert_eq!(code(vec![TxOutput::new(vec![OP_1], 1_000)]), Some(PolicyCode::ScriptPubKey));
+
+        let mut memo = vec![OP_RETURN];
+        script::push_data(&mut memo, &[0u8; 80]);
+        assert_eq!(code(vec![TxOutput::new(memo.clone(), 0)]), None);
+        assert_eq!(code(vec![TxOutput::new(memo.clone(), 0), TxOutput::new(vec![OP_RETURN], 0)]), Some(PolicyCode::MultiOpReturn));
+        script::push_data(&mut memo, &[0u8; 1]);
+        assert_eq!(code(vec![TxOutput::new(memo, 0)]), Some(PolicyCode::DataCarrier));
+    }
+
+    #[test]
+    fn test_inputs_version_and_fee() {
+        let (mut tx, spent) = spending(vec![TxOutput::p2wpkh(&[2u8; 20], 1_000)]);
+        let config = PolicyConfig::default();
+        assert_eq!(sigop_cost(&tx, &spent), 1);
+        tx.inputs[0].script_sig = vec![script::OP_CHECKSIG];
+        let err = check_standard(&tx, &spent, &config).unwrap_err();
+        assert_eq!(err.code, PolicyCode::ScriptSigNotPushOnly);
+        assert!(err.to_string().starts_with("Non-standard transaction (scriptsig-not-pushonly)"), "{err}");
+        assert_eq!(sigop_cost(&tx, &spent), 5);
+
+        tx.inputs[0].script_sig.clear();
+        tx.version = 3;
+        assert_eq!(check_standard(&tx, &spent, &config).unwrap_err().code, PolicyCode::Version);
+        let relaxed = PolicyConfig { require_standard: false, ..config };
+        assert!(check_standard(&tx, &spent, &relaxed).is_ok());
+
+        let vsize = tx.vsize() as u64;
+        let fee = (vsize * DEFAULT_MIN_RELAY_FEE).div_ceil(1_000);
+        assert!(check_min_relay_fee(fee, vsize, &config).is_ok());
+        assert_eq!(check_min_relay_fee(fee - 1, vsize, &config).unwrap_err().code, PolicyCode::MinRelayFee);
+    }
+}
@@ -0,0 +1,601 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::transaction::encoding::hex_encode;
+use crate::transaction::transaction::{generate_txid, Transaction, UtxoRef};
+
+pub type Txid = [u8; 32];
+
+pub const DEFAULT_ANCESTOR_LIMIT: usize = 25;
+pub const DEFAULT_ANCESTOR_SIZE_LIMIT: u64 = 101_000;
+pub const DEFAULT_DESCENDANT_LIMIT: usize = 25;
+pub const DEFAULT_DESCENDANT_SIZE_LIMIT: u64 = 101_000;
+pub const DEFAULT_MAX_MEMPOOL_SIZE: u64 = 300_000_000;
+// Seconds a transaction may wait in the mempool before it is dropped.
+pub const DEFAULT_MEMPOOL_EXPIRY: u64 = 14 * 24 * 60 * 60;
+
+// Feerates are in satoshis per 1000 virtual bytes.
+pub const INCREMENTAL_RELAY_FEE: u64 = 1_000;
+pub const ROLLING_FEE_HALFLIFE: u64 = 12 * 60 * 60;
+
+// Counts include the transaction itself; sizes are in virtual bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolLimits {
+    pub ancestor_count: usize,
+    pub ancestor_size: u64,
+    pub descendant_count: usize,
+    pub descendant_size: u64,
+    pub max_size: u64,
+}
+
+impl Default for MempoolLimits {
+    fn default() -> Self {
+        MempoolLimits {
+            ancestor_count: DEFAULT_ANCESTOR_LIMIT,
+            ancestor_size: DEFAULT_ANCESTOR_SIZE_LIMIT,
+            descendant_count: DEFAULT_DESCENDANT_LIMIT,
+            descendant_size: DEFAULT_DESCENDANT_SIZE_LIMIT,
+            max_size: DEFAULT_MAX_MEMPOOL_SIZE,
+        }
+    }
+}
+
+// The ancestor and descendant aggregates include the entry itself, so a transaction with
+// no unconfirmed relatives has counts of 1 and totals equal to its own fee and size. Fee
+// aggregates use modified fees, so prioritisation carries over to relatives.
+#[derive(Debug, Clone)]
+pub struct MempoolEntry {
+    pub tx: Transaction,
+    pub txid: Txid,
+    pub fee: u64,
+    pub vsize: u64,
+    pub time: u64,
+    // Operator adjustment from `Mempool::prioritise_transaction`.
+    pub fee_delta: i64,
+    pub parents: HashSet<Txid>,
+    pub children: HashSet<Txid>,
+    pub ancestor_count: usize,
+    pub ancestor_size: u64,
+    pub ancestor_fees: u64,
+    pub descendant_count: usize,
+    pub descendant_size: u64,
+    pub descendant_fees: u64,
+}
+
+impl MempoolEntry {
+    // The fee the mempool ranks this entry by.
+    pub fn modified_fee(&self) -> u64 {
+        apply_fee_delta(self.fee, self.fee_delta)
+    }
+}
+
+pub fn apply_fee_delta(fee: u64, fee_delta: i64) -> u64 {
+    (fee as i128 + fee_delta as i128).clamp(0, u64::MAX as i128) as u64
+}
+
+pub fn feerate(fee: u64, vsize: u64) -> u64 {
+    (fee as u128 * 1_000 / vsize.max(1) as u128) as u64
+}
+
+// Orders fee/size pairs by feerate without dividing.
+pub fn compare_feerates(fee_a: u64, size_a: u64, fee_b: u64, size_b: u64) -> Ordering {
+    (fee_a as u128 * size_b as u128).cmp(&(fee_b as u128 * size_a as u128))
+}
+
+#[derive(Debug, Default)]
+pub struct Mempool {
+    entries: HashMap<Txid, MempoolEntry>,
+    spent_by: HashMap<UtxoRef, Txid>,
+    fee_deltas: HashMap<Txid, i64>,
+    limits: MempoolLimits,
+    total_vsize: u64,
+    rolling_min_fee: f64,
+    last_rolling_fee_update: u64,
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Mempool::default()
+    }
+
+    pub fn with_limits(limits: MempoolLimits) -> Self {
+        Mempool { limits, ..Mempool::default() }
+    }
+
+    pub fn limits(&self) -> MempoolLimits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: MempoolLimits) {
+        self.limits = limits;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn total_vsize(&self) -> u64 {
+        self.total_vsize
+    }
+
+    pub fn contains(&self, txid: &Txid) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    pub fn get(&self, txid: &Txid) -> Option<&MempoolEntry> {
+        self.entries.get(txid)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &MempoolEntry> {
+        self.entries.values()
+    }
+
+    // Adds `fee_delta` to the fee a transaction is ranked by, for ordering, eviction and
+    // mining. Deltas add up, and are kept for transactions that have not arrived yet so
+    // they apply the moment they do. They stay until the transaction confirms or the
+    // delta is cleared.
+    pub fn prioritise_transaction(&mut self, txid: &Txid, fee_delta: i64) {
+        let total = self.fee_deltas.get(txid).copied().unwrap_or(0).saturating_add(fee_delta);
+        if total == 0 {
+            self.fee_deltas.remove(txid);
+        } else {
+            self.fee_deltas.insert(*txid, total);
+        }
+        self.set_entry_fee_delta(txid, total);
+    }
+
+    pub fn fee_delta(&self, txid: &Txid) -> i64 {
+        self.fee_deltas.get(txid).copied().unwrap_or(0)
+    }
+
+    pub fn fee_deltas(&self) -> &HashMap<Txid, i64> {
+        &self.fee_deltas
+    }
+
+    pub fn clear_fee_delta(&mut self, txid: &Txid) {
+        if self.fee_deltas.remove(txid).is_some() {
+            self.set_entry_fee_delta(txid, 0);
+        }
+    }
+
+    pub fn clear_fee_deltas(&mut self) {
+        for txid in std::mem::take(&mut self.fee_deltas).keys() {
+            self.set_entry_fee_delta(txid, 0);
+        }
+    }
+
+    fn set_entry_fee_delta(&mut self, txid: &Txid, fee_delta: i64) {
+        let Some(entry) = self.entries.get_mut(txid) else { return };
+        let old_fee = entry.modified_fee();
+        entry.fee_delta = fee_delta;
+        let new_fee = entry.modified_fee();
+        let update = |fees: &mut u64| *fees = *fees - old_fee + new_fee;
+        update(&mut entry.ancestor_fees);
+        update(&mut entry.descendant_fees);
+        for ancestor in self.ancestors(txid) {
+            update(&mut self.entries.get_mut(&ancestor).unwrap().descendant_fees);
+        }
+        for descendant in self.descendants(txid) {
+            update(&mut self.entries.get_mut(&descendant).unwrap().ancestor_fees);
+        }
+    }
+
+    // The mempool transaction spending `utxo_ref`, if any.
+    pub fn spender(&self, utxo_ref: &UtxoRef) -> Option<&MempoolEntry> {
+        self.spent_by.get(utxo_ref).and_then(|txid| self.entries.get(txid))
+    }
+
+    pub fn ancestors(&self, txid: &Txid) -> HashSet<Txid> {
+        self.walk(txid, |entry| &entry.parents)
+    }
+
+    pub fn descendants(&self, txid: &Txid) -> HashSet<Txid> {
+        self.walk(txid, |entry| &entry.children)
+    }
+
+    fn walk(&self, txid: &Txid, next: impl Fn(&MempoolEntry) -> &HashSet<Txid>) -> HashSet<Txid> {
+        let mut found = HashSet::new();
+        let mut queue: VecDeque<Txid> = self.entries.get(txid).map(&next).into_iter().flatten().copied().collect();
+        while let Some(relative) = queue.pop_front() {
+            if found.insert(relative)
+                && let Some(entry) = self.entries.get(&relative)
+            {
+                queue.extend(next(entry).iter().copied());
+            }
+        }
+        found
+    }
+
+    // Adds a transaction whose inputs have already been checked against the coin set.
+    // Fails without changing anything if the transaction is already present, spends an
+    // output another mempool transaction spends, or would break the package limits.
+    pub fn add(&mut self, tx: Transaction, fee: u64, time: u64) -> Result<Txid, String> {
+        let txid = generate_txid(&tx);
+        if self.entries.contains_key(&txid) {
+            return Err(format!("Transaction {} is already in the mempool", hex_encode(&txid)));
+        }
+        let mut seen = HashSet::with_capacity(tx.inputs.len());
+        for input in &tx.inputs {
+            if !seen.insert(input.utxo_ref) {
+                return Err(format!("Transaction spends {:?} more than once", input.utxo_ref));
+            }
+            if let Some(spender) = self.spent_by.get(&input.utxo_ref) {
+                return Err(format!(
+                    "Input {:?} is already spent by mempool transaction {}",
+                    input.utxo_ref, hex_encode(spender)
+                ));
+            }
+        }
+
+        let vsize = tx.vsize() as u64;
+        let (parents, ancestors) = self.relatives_of(&tx);
+        self.check_limits(&ancestors, vsize, &HashSet::new())?;
+
+        let fee_delta = self.fee_delta(&txid);
+        let modified_fee = apply_fee_delta(fee, fee_delta);
+        let mut entry = MempoolEntry {
+            tx,
+            txid,
+            fee,
+            vsize,
+            time,
+            fee_delta,
+            parents,
+            children: HashSet::new(),
+            ancestor_count: 1,
+            ancestor_size: vsize,
+            ancestor_fees: modified_fee,
+            descendant_count: 1,
+            descendant_size: vsize,
+            descendant_fees: modified_fee,
+        };
+        for ancestor_txid in &ancestors {
+            let ancestor = self.entries.get_mut(ancestor_txid).unwrap();
+            ancestor.descendant_count += 1;
+            ancestor.descendant_size += vsize;
+            ancestor.descendant_fees += modified_fee;
+            entry.ancestor_count += 1;
+            entry.ancestor_size += ancestor.vsize;
+            entry.ancestor_fees += ancestor.modified_fee();
+        }
+        for parent in &entry.parents {
+            self.entries.get_mut(parent).unwrap().children.insert(txid);
+        }
+        for input in &entry.tx.inputs {
+            self.spent_by.insert(input.utxo_ref, txid);
+        }
+        self.total_vsize += vsize;
+        self.entries.insert(txid, entry);
+        Ok(txid)
+    }
+
+    // The package limit checks `add` runs, judged as if `replaced` had already left the
+    // mempool, so a replacement can be vetted before anything is evicted for it.
+    pub fn check_replacement_limits(&self, tx: &Transaction, replaced: &HashSet<Txid>) -> Result<(), String> {
+        let (_, ancestors) = self.relatives_of(tx);
+        self.check_limits(&ancestors, tx.vsize() as u64, replaced)
+    }
+
+    // Mempool parents of `tx` and all of their mempool ancestors.
+    fn relatives_of(&self, tx: &Transaction) -> (HashSet<Txid>, HashSet<Txid>) {
+        let parents: HashSet<Txid> = tx.inputs.iter()
+            .map(|input| input.utxo_ref.txid)
+            .filter(|parent| self.entries.contains_key(parent))
+            .collect();
+        let mut ancestors = HashSet::new();
+        for parent in &parents {
+            ancestors.insert(*parent);
+            ancestors.extend(self.ancestors(parent));
+        }
+        (parents, ancestors)
+    }
+
+    // Descendants in `leaving` no longer count against an ancestor's limits.
+    fn check_limits(&self, ancestors: &HashSet<Txid>, vsize: u64, leaving: &HashSet<Txid>) -> Result<(), String> {
+        let ancestor_size: u64 = ancestors.iter().map(|txid| self.entries[txid].vsize).sum::<u64>() + vsize;
+        if ancestors.len() + 1 > self.limits.ancestor_count {
+            return Err(format!(
+                "Too many unconfirmed ancestors: {} exceeds the limit of {}",
+                ancestors.len() + 1, self.limits.ancestor_count
+            ));
+        }
+        if ancestor_size > self.limits.ancestor_size {
+            return Err(format!(
+                "Unconfirmed ancestors total {ancestor_size} vbytes, over the limit of {}",
+                self.limits.ancestor_size
+            ));
+        }
+        for txid in ancestors {
+            let ancestor = &self.entries[txid];
+            let (mut descendant_count, mut descendant_size) = (ancestor.descendant_count, ancestor.descendant_size);
+            if !leaving.is_empty() {
+                for descendant in self.descendants(txid).intersection(leaving) {
+                    descendant_count -= 1;
+                    descendant_size -= self.entries[descendant].vsize;
+                }
+            }
+            if descendant_count + 1 > self.limits.descendant_count {
+                return Err(format!(
+                    "Ancestor {} would exceed the limit of {} descendants",
+                    hex_encode(txid), self.limits.descendant_count
+                ));
+            }
+            if descendant_size + vsize > self.limits.descendant_size {
+                return Err(format!(
+                    "Ancestor {} would exceed the descendant size limit of {} vbytes",
+                    hex_encode(txid), self.limits.descendant_size
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Removes a transaction and everything that spends its outputs, parents first.
+    pub fn remove_with_descendants(&mut self, txid: &Txid) -> Vec<MempoolEntry> {
+        if !self.entries.contains_key(txid) {
+            return Vec::new();
+        }
+        let mut removed = self.descendants(txid);
+        removed.insert(*txid);
+        self.remove_set(&removed)
+    }
+
+    // Removes transactions that arrived before `cutoff`, along with their descendants.
+    pub fn expire(&mut self, cutoff: u64) -> Vec<MempoolEntry> {
+        let mut expired = HashSet::new();
+        for entry in self.entries.values().filter(|entry| entry.time < cutoff) {
+            expired.insert(entry.txid);
+            expired.extend(self.descendants(&entry.txid));
+        }
+        self.remove_set(&expired)
+    }
+
+    // Removes exactly the given transactions, keeping the aggregates of the relatives left
+    // behind consistent. Entries come back parents first.
+    pub fn remove_set(&mut self, txids: &HashSet<Txid>) -> Vec<MempoolEntry> {
+        let txids: HashSet<Txid> = txids.iter().filter(|txid| self.entries.contains_key(*txid)).copied().collect();
+        let relatives: Vec<(Txid, HashSet<Txid>, HashSet<Txid>)> = txids.iter()
+            .map(|txid| (*txid, self.ancestors(txid), self.descendants(txid)))
+            .collect();
+
+        for (txid, ancestors, descendants) in &relatives {
+            let (fee, vsize) = {
+                let entry = &self.entries[txid];
+                (entry.modified_fee(), entry.vsize)
+            };
+            for ancestor in ancestors.difference(&txids) {
+                let ancestor = self.entries.get_mut(ancestor).unwrap();
+                ancestor.descendant_count -= 1;
+                ancestor.descendant_size -= vsize;
+                ancestor.descendant_fees -= fee;
+            }
+            for descendant in descendants.difference(&txids) {
+                let descendant = self.entries.get_mut(descendant).unwrap();
+                descendant.ancestor_count -= 1;
+                descendant.ancestor_size -= vsize;
+                descendant.ancestor_fees -= fee;
+            }
+        }
+
+        let mut removed = Vec::with_capacity(txids.len());
+        for txid in &txids {
+            let entry = self.entries.remove(txid).unwrap();
+            for parent in &entry.parents {
+                if let Some(parent) = self.entries.get_mut(parent) {
+                    parent.children.remove(txid);
+                }
+            }
+            for child in &entry.children {
+                if let Some(child) = self.entries.get_mut(child) {
+                    child.parents.remove(txid);
+                }
+            }
+            for input in &entry.tx.inputs {
+                self.spent_by.remove(&input.utxo_ref);
+            }
+            self.total_vsize -= entry.vsize;
+            removed.push(entry);
+        }
+        removed.sort_by_key(|entry| entry.ancestor_count);
+        removed
+    }
+
+    // The feerate a new transaction must pay. It jumps above the feerate of whatever was
+    // last evicted for space and then halves every half-life, faster while the pool is
+    // well below its limit.
+    pub fn min_fee_rate(&self, now: u64) -> u64 {
+        if self.rolling_min_fee == 0.0 {
+            return 0;
+        }
+        let mut halflife = ROLLING_FEE_HALFLIFE as f64;
+        if self.total_vsize < self.limits.max_size / 4 {
+            halflife /= 4.0;
+        } else if self.total_vsize < self.limits.max_size / 2 {
+            halflife /= 2.0;
+        }
+        let elapsed = now.saturating_sub(self.last_rolling_fee_update) as f64;
+        let decayed = self.rolling_min_fee / 2f64.powf(elapsed / halflife);
+        if decayed < INCREMENTAL_RELAY_FEE as f64 / 2.0 {
+            return 0;
+        }
+        (decayed.round() as u64).max(INCREMENTAL_RELAY_FEE)
+    }
+
+    fn bump_min_fee(&mut self, rate: u64, now: u64) {
+        let current = self.min_fee_rate(now);
+        self.rolling_min_fee = current.max(rate) as f64;
+        self.last_rolling_fee_update = now;
+    }
+
+    // Evicts the packages with the lowest descendant feerate until the pool fits within
+    // `max_size`, returning everything removed.
+    pub fn trim_to_size(&mut self, now: u64) -> Vec<MempoolEntry> {
+        let mut evicted = Vec::new();
+        while self.total_vsize > self.limits.max_size {
+            let Some(worst) = self.entries.values()
+                .min_by(|a, b| {
+                    compare_feerates(a.descendant_fees, a.descendant_size, b.descendant_fees, b.descendant_size)
+                        .then_with(|| b.txid.cmp(&a.txid))
+                })
+                .map(|entry| (entry.txid, feerate(entry.descendant_fees, entry.descendant_size)))
+            else {
+                break;
+            };
+            self.bump_min_fee(worst.1 + INCREMENTAL_RELAY_FEE, now);
+            evicted.extend(self.remove_with_descendants(&worst.0));
+        }
+        evicted
+    }
+
+    // Best first: the order a miner would consider transactions in, each one bringing its
+    // unconfirmed ancestors along.
+    pub fn sorted_by_ancestor_feerate(&self) -> Vec<Txid> {
+        let mut entries: Vec<&MempoolEntry> = self.entries.values().collect();
+        entries.sort_by(|a, b| {
+            compare_feerates(b.ancestor_fees, b.ancestor_size, a.ancestor_fees, a.ancestor_size)
+                .then_with(|| a.ancestor_count.cmp(&b.ancestor_count))
+                .then_with(|| a.txid.cmp(&b.txid))
+        });
+        entries.into_iter().map(|entry| entry.txid).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::transaction::testing::spend;
+
+    fn out(txid: Txid, vout: u32) -> UtxoRef {
+        UtxoRef { txid, vout }
+    }
+
+    #[test]
+    fn test_aggregates_follow_chain() {
+        let mut mempool = Mempool::new();
+        let parent = mempool.add(spend(&[out([1u8; 32], 0)], &[1_000; 2]), 1_000, 1).unwrap();
+        let child = mempool.add(spend(&[out(parent, 0)], &[1_000]), 5_000, 2).unwrap();
+        let grandchild = mempool.add(spend(&[out(child, 0), out(parent, 1)], &[1_000]), 300, 3).unwrap();
+
+        let vsizes: Vec<u64> = [parent, child, grandchild].iter().map(|txid| mempool.get(txid).unwrap().vsize).collect();
+        let entry = mempool.get(&grandchild).unwrap();
+        assert_eq!((entry.ancestor_count, entry.ancestor_fees), (3, 6_300));
+        assert_eq!(entry.ancestor_size, vsizes.iter().sum::<u64>());
+        assert_eq!(entry.parents, HashSet::from([parent, child]));
+        let entry = mempool.get(&parent).unwrap();
+        assert_eq!((entry.descendant_count, entry.descendant_fees), (3, 6_300));
+        assert_eq!(mempool.spender(&out(parent, 1)).unwrap().txid, grandchild);
+        assert!(mempool.add(spend(&[out(parent, 0)], &[1_000; 2]), 9_000, 4).unwrap_err().contains("already spent"));
+        let doubled = spend(&[out([2u8; 32], 0), out([2u8; 32], 0)], &[1_000]);
+        assert!(mempool.add(doubled, 9_000, 4).unwrap_err().contains("more than once"));
+
+        let removed = mempool.remove_with_descendants(&child);
+        assert_eq!(removed.iter().map(|entry| entry.txid).collect::<Vec<_>>(), vec![child, grandchild]);
+        let entry = mempool.get(&parent).unwrap();
+        assert_eq!((entry.descendant_count, entry.descendant_size, entry.descendant_fees), (1, vsizes[0], 1_000));
+        assert!(entry.children.is_empty());
+        assert_eq!(mempool.total_vsize(), vsizes[0]);
+        assert!(mempool.spender(&out(parent, 1)).is_none());
+    }
+
+    #[test]
+    fn test_removing_parent_alone_updates_children() {
+        let mut mempool = Mempool::new();
+        let parent = mempool.add(spend(&[out([1u8; 32], 0)], &[1_000]), 1_000, 1).unwrap();
+        let child = mempool.add(spend(&[out(parent, 0)], &[1_000]), 2_000, 2).unwrap();
+
+        mempool.remove_set(&HashSet::from([parent]));
+        let entry = mempool.get(&child).unwrap();
+        assert_eq!((entry.ancestor_count, entry.ancestor_fees, entry.ancestor_size), (1, 2_000, entry.vsize));
+        assert!(entry.parents.is_empty());
+    }
+
+    #[test]
+    fn test_limits_are_enforced() {
+        let mut mempool = Mempool::with_limits(MempoolLimits {
+            ancestor_count: 3,
+            descendant_count: 2,
+            ..MempoolLimits::default()
+        });
+        let root = mempool.add(spend(&[out([1u8; 32], 0)], &[1_000; 3]), 1_000, 1).unwrap();
+        mempool.add(spend(&[out(root, 0)], &[1_000]), 1_000, 1).unwrap();
+        let err = mempool.add(spend(&[out(root, 1)], &[1_000]), 1_000, 1).unwrap_err();
+        assert!(err.contains("descendants"), "{err}");
+        assert_eq!(mempool.len(), 2);
+        assert_eq!(mempool.get(&root).unwrap().descendant_count, 2);
+        // Replacing the existing child frees its slot.
+        let child = generate_txid(&spend(&[out(root, 0)], &[1_000]));
+        let replacement = spend(&[out(root, 0)], &[900]);
+        assert!(mempool.check_replacement_limits(&replacement, &HashSet::new()).is_err());
+        mempool.check_replacement_limits(&replacement, &HashSet::from([child])).unwrap();
+
+        let mut mempool = Mempool::with_limits(MempoolLimits { ancestor_count: 2, ..MempoolLimits::default() });
+        let a = mempool.add(spend(&[out([1u8; 32], 0)], &[1_000]), 1_000, 1).unwrap();
+        let b = mempool.add(spend(&[out(a, 0)], &[1_000]), 1_000, 1).unwrap();
+        assert!(mempool.add(spend(&[out(b, 0)], &[1_000]), 1_000, 1).unwrap_err().contains("ancestors"));
+    }
+
+    #[test]
+    fn test_prioritisation_reorders_and_protects() {
+        let mut mempool = Mempool::new();
+        let rich = mempool.add(spend(&[out([1u8; 32], 0)], &[1_000]), 5_000, 1).unwrap();
+        let poor = mempool.add(spend(&[out([2u8; 32], 0)], &[1_000]), 100, 1).unwrap();
+        let late = spend(&[out(poor, 0)], &[1_000]);
+        let late_txid = generate_txid(&late);
+        mempool.prioritise_transaction(&late_txid, 4_000);
+        mempool.prioritise_transaction(&late_txid, 2_000);
+        assert_eq!(mempool.fee_deltas(), &HashMap::from([(late_txid, 6_000)]));
+
+        mempool.add(late, 100, 2).unwrap();
+        assert_eq!(mempool.get(&late_txid).unwrap().modified_fee(), 6_100);
+        assert_eq!(mempool.get(&poor).unwrap().descendant_fees, 6_200);
+        mempool.prioritise_transaction(&poor, 10_000);
+        assert_eq!(mempool.get(&late_txid).unwrap().ancestor_fees, 16_200);
+        assert_eq!(mempool.sorted_by_ancestor_feerate()[0], poor);
+
+        let vsize = mempool.get(&rich).unwrap().vsize;
+        mempool.set_limits(MempoolLimits { max_size: vsize * 2, ..MempoolLimits::default() });
+        let evicted = mempool.trim_to_size(10);
+        assert_eq!(evicted.iter().map(|entry| entry.txid).collect::<Vec<_>>(), vec![rich]);
+
+        mempool.clear_fee_deltas();
+        assert!(mempool.fee_deltas().is_empty());
+        let entry = mempool.get(&poor).unwrap();
+        assert_eq!((entry.fee_delta, entry.descendant_fees), (0, 200));
+        assert_eq!(mempool.get(&late_txid).unwrap().ancestor_fees, 200);
+    }
+
+    #[test]
+    fn test_trim_evicts_cheapest_package_and_raises_min_fee() {
+        let mut mempool = Mempool::new();
+        let cheap = mempool.add(spend(&[out([1u8; 32], 0)], &[1_000]), 100, 1).unwrap();
+        let cheap_child = mempool.add(spend(&[out(cheap, 0)], &[1_000]), 200, 1).unwrap();
+        let rich = mempool.add(spend(&[out([2u8; 32], 0)], &[1_000]), 50_000, 1).unwrap();
+        let vsize = mempool.get(&rich).unwrap().vsize;
+
+        mempool.set_limits(MempoolLimits { max_size: vsize * 2, ..MempoolLimits::default() });
+        assert_eq!(mempool.min_fee_rate(0), 0);
+        let evicted: Vec<Txid> = mempool.trim_to_size(1_000).into_iter().map(|entry| entry.txid).collect();
+        assert_eq!(evicted, vec![cheap, cheap_child]);
+        assert_eq!(mempool.len(), 1);
+
+        // The package paid 300 sats for two transactions; the floor sits one increment above.
+        let floor = feerate(300, vsize * 2) + INCREMENTAL_RELAY_FEE;
+        assert_eq!(mempool.min_fee_rate(1_000), floor);
+        assert_eq!(mempool.min_fee_rate(1_000 + ROLLING_FEE_HALFLIFE), (floor as f64 / 2.0).round() as u64);
+        assert_eq!(mempool.min_fee_rate(1_000 + ROLLING_FEE_HALFLIFE * 4), 0);
+    }
+
+    #[test]
+    fn test_ancestor_feerate_order() {
+        let mut mempool = Mempool::new();
+        let low = mempool.add(spend(&[out([1u8; 32], 0)], &[1_000]), 100, 1).unwrap();
+        let mid = mempool.add(spend(&[out([2u8; 32], 0)], &[1_000]), 2_000, 1).unwrap();
+        // The child's package pays more per vbyte than `mid`; taking it pulls `low` along.
+        let child = mempool.add(spend(&[out(low, 0)], &[1_000]), 10_000, 1).unwrap();
+        assert_eq!(mempool.sorted_by_ancestor_feerate(), vec![child, mid, low]);
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashSet;
+
+use crate::mempool::pool::{compare_feerates, Mempool, Txid, INCREMENTAL_RELAY_FEE};
+use crate::transaction::encoding::hex_encode;
+use crate::transaction::transaction::Transaction;
+
+pub const MAX_BIP125_RBF_SEQUENCE: u32 = 0xffff_fffd;
+pub const DEFAULT_MAX_REPLACEMENT_CANDIDATES: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RbfConfig {
+    // Allow replacing transactions that did not signal BIP125.
+    pub full_rbf: bool,
+    pub max_replacement_candidates: usize,
+}
+
+impl Default for RbfConfig {
+    fn default() -> Self {
+        RbfConfig { full_rbf: false, max_replacement_candidates: DEFAULT_MAX_REPLACEMENT_CANDIDATES }
+    }
+}
+
+pub fn signals_rbf(tx: &Transaction) -> bool {
+    tx.inputs.iter().any(|input| input.sequence <= MAX_BIP125_RBF_SEQUENCE)
+}
+
+// Checks a transaction against the BIP125 rules for replacing `conflicts`, the mempool
+// transactions spending the same outputs. Fees on both sides are modified fees, so
+// prioritisation counts. Returns every transaction the replacement would evict: the
+// conflicts and all their descendants.
+pub fn check_replacement(
+    mempool: &Mempool,
+    tx: &Transaction,
+    fee: u64,
+    conflicts: &HashSet<Txid>,
+    config: &RbfConfig,
+) -> Result<HashSet<Txid>, String> {
+    let vsize = tx.vsize() as u64;
+    for txid in conflicts {
+        let original = mempool.get(txid)
+            .ok_or_else(|| format!("Conflicting transaction {} is not in the mempool", hex_encode(txid)))?;
+        if !config.full_rbf && !signals_rbf(&original.tx) {
+            return Err(format!("Transaction {} is not replaceable", hex_encode(txid)));
+        }
+        if compare_feerates(fee, vsize, original.modified_fee(), original.vsize).is_le() {
+            return Err(format!(
+                "Replacement feerate does not beat the feerate of {}",
+                hex_encode(txid)
+            ));
+        }
+    }
+
+    let mut replaced = HashSet::new();
+    for txid in conflicts {
+        replaced.insert(*txid);
+        replaced.extend(mempool.descendants(txid));
+    }
+    if replaced.len() > config.max_replacement_candidates {
+        return Err(format!(
+            "Replacement would evict {} transactions, more than the limit of {}",
+            replaced.len(), config.max_replacement_candidates
+        ));
+    }
+
+    // A replacement may only spend unconfirmed outputs the originals already depended on,
+    // and can never spend outputs of the transactions it replaces.
+    let original_parents: HashSet<Txid> = conflicts.iter()
+        .flat_map(|txid| mempool.get(txid).into_iter().flat_map(|entry| entry.parents.iter().copied()))
+        .collect();
+    for input in &tx.inputs {
+        let parent = input.utxo_ref.txid;
+        if replaced.contains(&parent) {
+            return Err(format!("Replacement spends an output of replaced transaction {}", hex_encode(&parent)));
+        }
+        if mempool.contains(&parent) && !original_parents.contains(&parent) {
+            return Err(format!("Replacement adds new unconfirmed input from {}", hex_encode(&parent)));
+        }
+    }
+
+    let replaced_fees: u64 = replaced.iter().filter_map(|txid| mempool.get(txid)).map(|entry| entry.modified_fee()).sum();
+    if fee < replaced_fees {
+        return Err(format!("Replacement fee {fee} is less than the {replaced_fees} paid by the replaced transactions"));
+    }
+    let required = INCREMENTAL_RELAY_FEE * vsize / 1_000;
+    if fee - replaced_fees < required {
+        return Err(format!(
+            "Replacement adds {} in fees but must add at least {required} to pay for its own relay",
+            fee - replaced_fees
+        ));
+    }
+    Ok(replaced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::transaction::testing::pay;
+    use crate::transaction::transaction::UtxoRef;
+
+    fn spend(input: UtxoRef, amount: u64, sequence: u32) -> Transaction {
+        let mut tx = pay(input, amount);
+        tx.inputs[0].sequence = sequence;
+        tx
+    }
+
+    #[test]
+    fn test_bip125_rules() {
+        let funding = UtxoRef { txid: [1u8; 32], vout: 0 };
+        let mut mempool = Mempool::new();
+        let original = mempool.add(spend(funding, 90_000, MAX_BIP125_RBF_SEQUENCE), 1_000, 1).unwrap();
+        let child = mempool.add(spend(UtxoRef { txid: original, vout: 0 }, 89_000, u32::MAX), 1_000, 1).unwrap();
+        let conflicts = HashSet::from([original]);
+        let config = RbfConfig::default();
+
+        let replacement = spend(funding, 80_000, u32::MAX);
+        let replaced = check_replacement(&mempool, &replacement, 10_000, &conflicts, &config).unwrap();
+        assert_eq!(replaced, HashSet::from([original, child]));
+
+        // Beats the original's feerate but not the fees of the whole evicted set.
+        let err = check_replacement(&mempool, &replacement, 1_500, &conflicts, &config).unwrap_err();
+        assert!(err.contains("less than"), "{err}");
+        // Covers the evicted fees but not its own relay.
+        let err = check_replacement(&mempool, &replacement, 2_010, &conflicts, &config).unwrap_err();
+        assert!(err.contains("relay"), "{err}");
+
+        let limited = RbfConfig { max_replacement_candidates: 1, ..config };
+        assert!(check_replacement(&mempool, &replacement, 10_000, &conflicts, &limited).unwrap_err().contains("limit"));
+    }
+
+    #[test]
+    fn test_non_signalling_needs_full_rbf() {
+        let funding = UtxoRef { txid: [1u8; 32], vout: 0 };
+        let mut mempool = Mempool::new();
+        let original = mempool.add(spend(funding, 90_000, u32::MAX), 1_000, 1).unwrap();
+        let conflicts = HashSet::from([original]);
+        let replacement = spend(funding, 80_000, u32::MAX);
+
+        let err = check_replacement(&mempool, &replacement, 10_000, &conflicts, &RbfConfig::default()).unwrap_err();
+        assert!(err.contains("not replaceable"), "{err}");
+        let full = RbfConfig { full_rbf: true, ..RbfConfig::default() };
+        assert!(check_replacement(&mempool, &replacement, 10_000, &conflicts, &full).is_ok());
+    }
+}
@@ -0,0 +1,142 @@
+use crate::mempool::pool::Mempool;
+use crate::transaction::coin::{Coin, MEMPOOL_HEIGHT};
+use crate::transaction::script;
+use crate::transaction::store::{UtxoBatch, UtxoStore};
+use crate::transaction::transaction::UtxoRef;
+
+// The confirmed coin set as the mempool sees it: coins spent by mempool transactions are
+// hidden, and, when `include_unconfirmed` is set, mempool outputs appear as coins at
+// MEMPOOL_HEIGHT. The view is read-only; coins only change through the processor.
+pub struct MempoolCoinView<'a, S: UtxoStore> {
+    utxo_db: &'a S,
+    mempool: &'a Mempool,
+    include_unconfirmed: bool,
+}
+
+impl<'a, S: UtxoStore> MempoolCoinView<'a, S> {
+    pub fn new(utxo_db: &'a S, mempool: &'a Mempool, include_unconfirmed: bool) -> Self {
+        MempoolCoinView { utxo_db, mempool, include_unconfirmed }
+    }
+
+    pub fn is_unconfirmed(&self, utxo_ref: &UtxoRef) -> bool {
+        self.mempool.contains(&utxo_ref.txid)
+    }
+
+    // The coin at `utxo_ref` whether or not a mempool transaction already spends it.
+    pub fn coin(&self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        if let Some(entry) = self.mempool.get(&utxo_ref.txid) {
+            if !self.include_unconfirmed {
+                return Ok(None);
+            }
+            return Ok(entry.tx.outputs.get(utxo_ref.vout as usize)
+                .filter(|output| !script::is_unspendable(&output.script_pubkey))
+                .map(|output| Coin::new(output.clone(), MEMPOOL_HEIGHT, false)));
+        }
+        self.utxo_db.get(utxo_ref)
+    }
+
+    fn unconfirmed_coins(&self) -> Vec<(UtxoRef, Coin)> {
+        if !self.include_unconfirmed {
+            return Vec::new();
+        }
+        self.mempool.entries()
+            .flat_map(|entry| {
+                entry.tx.outputs.iter().enumerate()
+                    .filter(|(_, output)| !script::is_unspendable(&output.script_pubkey))
+                    .map(|(vout, output)| {
+                        (UtxoRef { txid: entry.txid, vout: vout as u32 }, Coin::new(output.clone(), MEMPOOL_HEIGHT, false))
+                    })
+            })
+            .filter(|(utxo_ref, _)| self.mempool.spender(utxo_ref).is_none())
+            .collect()
+    }
+
+    fn read_only() -> Result<(), String> {
+        Err("The mempool coin view is read-only".to_string())
+    }
+}
+
+impl<S: UtxoStore> UtxoStore for MempoolCoinView<'_, S> {
+    fn get(&self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        if self.mempool.spender(utxo_ref).is_some() {
+            return Ok(None);
+        }
+        self.coin(utxo_ref)
+    }
+
+    fn add(&mut self, _utxo_ref: UtxoRef, _coin: Coin) -> Result<(), String> {
+        Self::read_only()
+    }
+
+    fn remove(&mut self, _utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        Self::read_only().map(|_| None)
+    }
+
+    fn apply_batch(&mut self, _batch: UtxoBatch) -> Result<(), String> {
+        Self::read_only()
+    }
+
+    fn utxos(&self) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        let mut utxos: Vec<(UtxoRef, Coin)> = self.utxo_db.utxos()?
+            .into_iter()
+            .filter(|(utxo_ref, _)| self.mempool.spender(utxo_ref).is_none())
+            .collect();
+        utxos.extend(self.unconfirmed_coins());
+        Ok(utxos)
+    }
+
+    fn utxos_for_script(&self, script_pubkey: &[u8]) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        let mut utxos: Vec<(UtxoRef, Coin)> = self.utxo_db.utxos_for_script(script_pubkey)?
+            .into_iter()
+            .filter(|(utxo_ref, _)| self.mempool.spender(utxo_ref).is_none())
+            .collect();
+        utxos.extend(self.unconfirmed_coins()
+            .into_iter()
+            .filter(|(_, coin)| coin.output.script_pubkey == script_pubkey));
+        Ok(utxos)
+    }
+
+    fn best_block(&self) -> Result<Option<[u8; 32]>, String> {
+        self.utxo_db.best_block()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::database::UtxoDatabase;
+    use crate::transaction::transaction::{Transaction, TxInput, TxOutput, Wallet};
+
+    #[test]
+    fn test_view_layers_mempool_over_confirmed_coins() {
+        let wallet = Wallet::new([1u8; 20]);
+        let confirmed = [UtxoRef { txid: [1u8; 32], vout: 0 }, UtxoRef { txid: [2u8; 32], vout: 0 }];
+        let mut utxo_db = UtxoDatabase::new();
+        for utxo_ref in confirmed {
+            utxo_db.add_utxo(utxo_ref, Coin::new(TxOutput::new(wallet.script_pubkey(), 5_000), 3, false));
+        }
+        let mut mempool = Mempool::new();
+        let tx = Transaction {
+            version: 2,
+            inputs: vec![TxInput::new(confirmed[0])],
+            outputs: vec![TxOutput::new(wallet.script_pubkey(), 4_000), TxOutput::new(vec![script::OP_RETURN], 0)],
+            locktime: 0,
+        };
+        let txid = mempool.add(tx, 1_000, 1).unwrap();
+        let change = UtxoRef { txid, vout: 0 };
+
+        let view = MempoolCoinView::new(&utxo_db, &mempool, true);
+        assert_eq!(view.get(&confirmed[0]).unwrap(), None);
+        assert_eq!(view.coin(&confirmed[0]).unwrap().unwrap().height, 3);
+        assert!(view.get(&change).unwrap().unwrap().is_unconfirmed());
+        assert!(view.is_unconfirmed(&change));
+        assert_eq!(view.get(&UtxoRef { txid, vout: 1 }).unwrap(), None);
+        let spendable = wallet.spendable_utxos(&view).unwrap();
+        assert_eq!(spendable.len(), 2);
+        assert!(spendable.contains(&(change, 4_000)));
+
+        let confirmed_only = MempoolCoinView::new(&utxo_db, &mempool, false);
+        assert_eq!(wallet.spendable_utxos(&confirmed_only).unwrap(), vec![(confirmed[1], 5_000)]);
+        assert_eq!(confirmed_only.get(&change).unwrap(), None);
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::HashSet;
+
+use crate::mempool::policy::{dust_threshold, is_dust, DEFAULT_DUST_RELAY_FEE, MAX_STANDARD_TX_WEIGHT};
+use crate::transaction::encoding::compact_size_len;
+use crate::transaction::script;
+use crate::transaction::store::UtxoStore;
+use crate::transaction::transaction::{Transaction, TxInput, TxOutput, UtxoRef, Wallet};
+
+// Placeholder witness used to price inputs before they are signed: a DER signature with
+// sighash byte and a compressed public key.
+const P2WPKH_DUMMY_SIGNATURE_LEN: usize = 72;
+const P2WPKH_DUMMY_PUBKEY_LEN: usize = 33;
+
+pub struct Payment {
+    pub script_pubkey: Vec<u8>,
+    pub amount: u64,
+}
+
+impl Payment {
+    pub fn new(script_pubkey: Vec<u8>, amount: u64) -> Self {
+        Payment { script_pubkey, amount }
+    }
+
+    pub fn to_address(address: &[u8; 20], amount: u64) -> Self {
+        Payment::new(script::p2wpkh(address), amount)
+    }
+}
+
+// Bytes an output with this script adds to a transaction, not counting any growth of the
+// output count prefix.
+pub fn output_vsize(script_pubkey: &[u8]) -> usize {
+    8 + compact_size_len(script_pubkey.len() as u64) + script_pubkey.len()
+}
+
+pub fn estimated_vsize(tx: &Transaction) -> usize {
+    let mut signed = tx.clone();
+    for input in &mut signed.inputs {
+        input.witness = vec![
+            vec![0u8; P2WPKH_DUMMY_SIGNATURE_LEN],
+            vec![0u8; P2WPKH_DUMMY_PUBKEY_LEN],
+        ];
+    }
+    signed.vsize()
+}
+
+impl Wallet {
+    pub fn create_batch_transaction<S: UtxoStore>(
+        &self,
+        utxo_db: &S,
+        payments: &[Payment],
+        fee_per_vbyte: u64,
+    ) -> Result<Transaction, String> {
+        validate_payments(payments)?;
+        let coins = self.coins_largest_first(utxo_db)?;
+        let tx = self.build_batch(&coins, payments, fee_per_vbyte)?;
+        let vsize = estimated_vsize(&tx);
+        let max_vsize = MAX_STANDARD_TX_WEIGHT / 4;
+        if vsize > max_vsize {
+            return Err(format!(
+                "Batch transaction is {} vbytes, above the limit of {}; use create_batch_transactions to split it",
+                vsize, max_vsize
+            ));
+        }
+        Ok(tx)
+    }
+
+    pub fn create_batch_transactions<S: UtxoStore>(
+        &self,
+        utxo_db: &S,
+        payments: &[Payment],
+        fee_per_vbyte: u64,
+        max_tx_size: usize,
+    ) -> Result<Vec<Transaction>, String> {
+        validate_payments(payments)?;
+        let mut coins = self.coins_largest_first(utxo_db)?;
+        let mut transactions = Vec::new();
+        let mut start = 0;
+
+        while start < payments.len() {
+            // Grow the chunk one payment at a time until the next one would push the
+            // transaction over the size limit. Each payment adds a known number of bytes,
+            // so the chunk is only rebuilt when its inputs stop covering it.
+            let mut end = start + 1;
+            let first = self.build_batch(&coins, &payments[start..end], fee_per_vbyte)?;
+            if estimated_vsize(&first) > max_tx_size {
+                return Err(format!(
+                    "Payment to {:?} cannot fit in a transaction of {} vbytes",
+                    payments[start].script_pubkey, max_tx_size
+                ));
+            }
+            let (mut vsize, mut selected) = self.priced_chunk(&coins, &first, 1);
+            let mut total = payments[start].amount;
+            while end < payments.len() {
+                let payment = &payments[end];
+                // Outputs in the priced transaction: the chunk plus the change output.
+                let outputs = (end - start + 1) as u64;
+                let mut next_vsize = vsize + output_vsize(&payment.script_pubkey);
+                next_vsize += compact_size_len(outputs + 1) - compact_size_len(outputs);
+                if next_vsize > max_tx_size {
+                    break;
+                }
+                let next_total = total.checked_add(payment.amount)
+                    .ok_or_else(|| "Batch total overflows".to_string())?;
+                let needed = (next_vsize as u64).checked_mul(fee_per_vbyte)
+                    .and_then(|fee| fee.checked_add(next_total))
+                    .ok_or_else(|| "Batch fee overflows".to_string())?;
+                if selected < needed {
+                    let candidate = self.build_batch(&coins, &payments[start..=end], fee_per_vbyte)?;
+                    (next_vsize, selected) = self.priced_chunk(&coins, &candidate, end - start + 1);
+                    if next_vsize > max_tx_size {
+                        break;
+                    }
+                }
+                vsize = next_vsize;
+                total = next_total;
+                end += 1;
+            }
+            let tx = self.build_batch(&coins, &payments[start..end], fee_per_vbyte)?;
+
+            let spent: HashSet<UtxoRef> = tx.inputs.iter()
+                .map(|input| input.utxo_ref)
+                .collect();
+            coins.retain(|(utxo_ref, _)| !spent.contains(utxo_ref));
+            transactions.push(tx);
+            start = end;
+        }
+
+        Ok(transactions)
+    }
+
+    fn coins_largest_first<S: UtxoStore>(&self, utxo_db: &S) -> Result<Vec<(UtxoRef, u64)>, String> {
+        let mut coins = self.spendable_utxos(utxo_db)?;
+        sort_largest_first(&mut coins);
+        Ok(coins)
+    }
+
+    // Size of a built chunk priced with a change output, the way build_funded_transaction
+    // prices it, and the value of the coins it spends. Inputs are always a prefix of
+    // `coins`.
+    fn priced_chunk(&self, coins: &[(UtxoRef, u64)], tx: &Transaction, payment_count: usize) -> (usize, u64) {
+        let mut priced = tx.clone();
+        if priced.outputs.len() == payment_count {
+            priced.outputs.push(TxOutput::new(self.script_pubkey(), 0));
+        }
+        let selected = coins[..tx.inputs.len()].iter().map(|(_, amount)| amount).sum();
+        (estimated_vsize(&priced), selected)
+    }
+
+    fn build_batch(
+        &self,
+        coins: &[(UtxoRef, u64)],
+        payments: &[Payment],
+        fee_per_vbyte: u64,
+    ) -> Result<Transaction, String> {
+        build_funded_transaction(coins, payments, &self.script_pubkey(), fee_per_vbyte)
+    }
+}
+
+pub fn sort_largest_first(coins: &mut [(UtxoRef, u64)]) {
+    coins.sort_by(|(ref_a, amount_a), (ref_b, amount_b)| {
+        amount_b.cmp(amount_a)
+            .then(ref_a.txid.cmp(&ref_b.txid))
+            .then(ref_a.vout.cmp(&ref_b.vout))
+    });
+}
+
+// Spends `coins` in order until the payments and fee are covered, adding a single change
+// output to `change_script` unless it would be dust.
+pub fn build_funded_transaction(
+    coins: &[(UtxoRef, u64)],
+    payments: &[Payment],
+    change_script: &[u8],
+    fee_per_vbyte: u64,
+) -> Result<Transaction, String> {
+    let total_payments = payments.iter()
+        .try_fold(0u64, |total, payment| total.checked_add(payment.amount))
+        .ok_or_else(|| "Batch total overflows".to_string())?;
+
+    let mut outputs: Vec<TxOutput> = payments.iter()
+        .map(|payment| TxOutput::new(payment.script_pubkey.clone(), payment.amount))
+        .collect();
+    let mut tx = Transaction {
+        version: 1,
+        inputs: Vec::new(),
+        outputs: Vec::new(),
+        locktime: 0,
+    };
+
+    let mut selected_amount = 0u64;
+    for (utxo_ref, amount) in coins {
+        tx.inputs.push(TxInput::new(*utxo_ref));
+        selected_amount = selected_amount.checked_add(*amount)
+            .ok_or_else(|| "Selected coin total overflows".to_string())?;
+
+        // Price the transaction as if it carried a change output; drop the change
+        // afterwards if it would only be dust.
+        tx.outputs = outputs.clone();
+        tx.outputs.push(TxOutput::new(change_script.to_vec(), 0));
+        let required = (estimated_vsize(&tx) as u64).checked_mul(fee_per_vbyte)
+            .and_then(|fee| fee.checked_add(total_payments))
+            .ok_or_else(|| "Transaction fee overflows".to_string())?;
+        if selected_amount < required {
+            continue;
+        }
+
+        let change = TxOutput::new(change_script.to_vec(), selected_amount - required);
+        if !is_dust(&change, DEFAULT_DUST_RELAY_FEE) {
+            outputs.push(change);
+        }
+        tx.outputs = outputs;
+        return Ok(tx);
+    }
+
+    let fee = (estimated_vsize(&tx) as u64).saturating_mul(fee_per_vbyte);
+    Err(format!(
+        "Insufficient funds: available={}, required={}",
+        selected_amount, total_payments.saturating_add(fee)
+    ))
+}
+
+pub fn validate_payments(payments: &[Payment]) -> Result<(), String> {
+    if payments.is_empty() {
+        return Err("Batch contains no payments".to_string());
+    }
+
+    let mut seen = HashSet::new();
+    let mut total: u64 = 0;
+    for payment in payments {
+        if !seen.insert(&payment.script_pubkey) {
+            return Err(format!("Duplicate recipient in batch: {:?}", payment.script_pubkey));
+        }
+        let output = TxOutput::new(payment.script_pubkey.clone(), payment.amount);
+        if is_dust(&output, DEFAULT_DUST_RELAY_FEE) {
+            return Err(format!(
+                "Payment of {} to {:?} is below the dust threshold of {}",
+                payment.amount, payment.script_pubkey, dust_threshold(&output, DEFAULT_DUST_RELAY_FEE)
+            ));
+        }
+        total = total.checked_add(payment.amount)
+            .ok_or_else(|| "Batch total overflows".to_string())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::coin::Coin;
+    use crate::transaction::database::UtxoDatabase;
+
+    fn funded_wallet(amounts: &[u64]) -> (Wallet, UtxoDatabase) {
+        let wallet = Wallet::new([1u8; 20]);
+        let mut utxo_db = UtxoDatabase::new();
+        for (i, amount) in amounts.iter().enumerate() {
+            let utxo_ref = UtxoRef { txid: [i as u8; 32], vout: 0 };
+            utxo_db.add_utxo(utxo_ref, Coin::new(TxOutput::new(wallet.script_pubkey(), *amount), 1, false));
+        }
+        (wallet, utxo_db)
+    }
+
+    fn recipient(i: u16) -> Vec<u8> {
+        let mut address = [0u8; 20];
+        address[..2].copy_from_slice(&i.to_le_bytes());
+        address[19] = 0xaa;
+        script::p2wpkh(&address)
+    }
+
+    #[test]
+    fn test_batch_single_change_output() {
+        let (wallet, utxo_db) = funded_wallet(&[50_000, 30_000, 20_000]);
+        let payments = vec![
+            Payment::new(recipient(1), 10_000),
+            Payment::new(recipient(2), 15_000),
+            Payment::new(recipient(3), 20_000),
+        ];
+
+        let tx = wallet.create_batch_transaction(&utxo_db, &payments, 1).unwrap();
+
+        assert_eq!(tx.inputs.len(), 1);
+        assert_eq!(tx.outputs.len(), 4);
+        let change: Vec<&TxOutput> = tx.outputs.iter()
+            .filter(|output| output.script_pubkey == wallet.script_pubkey())
+            .collect();
+        assert_eq!(change.len(), 1);
+        let fee = 50_000 - tx.outputs.iter().map(|output| output.amount).sum::<u64>();
+        assert_eq!(fee, estimated_vsize(&tx) as u64);
+    }
+
+    #[test]
+    fn test_batch_rejects_duplicates_and_dust() {
+        let (wallet, utxo_db) = funded_wallet(&[100_000]);
+
+        let duplicate = vec![
+            Payment::new(recipient(1), 10_000),
+            Payment::new(recipient(1), 12_000),
+        ];
+        let result = wallet.create_batch_transaction(&utxo_db, &duplicate, 1);
+        assert!(result.unwrap_err().contains("Duplicate recipient"));
+
+        let threshold = dust_threshold(&TxOutput::new(recipient(1), 0), DEFAULT_DUST_RELAY_FEE);
+        let dust = vec![Payment::new(recipient(1), threshold - 1)];
+        let result = wallet.create_batch_transaction(&utxo_db, &dust, 1);
+        assert!(result.unwrap_err().contains("dust threshold"));
+
+        let result = wallet.create_batch_transaction(&utxo_db, &[], 1);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_batch_insufficient_funds() {
+        let (wallet, utxo_db) = funded_wallet(&[10_000, 5_000]);
+        let payments = vec![Payment::new(recipient(1), 15_000)];
+
+        let result = wallet.create_batch_transaction(&utxo_db, &payments, 1);
+
+        assert!(result.unwrap_err().contains("Insufficient funds"));
+    }
+
+    #[test]
+    fn test_batch_rejects_overflowing_coins() {
+        let coins = [
+            (UtxoRef { txid: [1u8; 32], vout: 0 }, u64::MAX),
+            (UtxoRef { txid: [2u8; 32], vout: 0 }, u64::MAX),
+        ];
+        let payments = vec![Payment::new(recipient(1), u64::MAX - 1)];
+        let err = build_funded_transaction(&coins, &payments, &recipient(2), 1).unwrap_err();
+        assert!(err.contains("overflows"), "{err}");
+    }
+
+    #[test]
+    fn test_batch_drops_dust_change() {
+        let (wallet, utxo_db) = funded_wallet(&[10_000]);
+        let payments = vec![Payment::new(recipient(1), 9_700)];
+
+        let tx = wallet.create_batch_transaction(&utxo_db, &payments, 1).unwrap();
+
+        assert_eq!(tx.outputs.len(), 1);
+        assert_eq!(tx.outputs[0].amount, 9_700);
+    }
+
+    #[test]
+    fn test_batch_splits_at_size_limit() {
+        let (wallet, utxo_db) = funded_wallet(&[400_000; 5]);
+        let payments: Vec<Payment> = (0..100)
+            .map(|i| Payment::new(recipient(i), 10_000))
+            .collect();
+
+        let transactions = wallet
+            .create_batch_transactions(&utxo_db, &payments, 1, 1_000)
+            .unwrap();
+
+        assert!(transactions.len() > 1);
+        let mut paid = 0;
+        let mut spent = HashSet::new();
+        for (index, tx) in transactions.iter().enumerate() {
+            assert!(estimated_vsize(tx) <= 1_000);
+            // Every chunk but the last is full: one more payment would not fit.
+            if index + 1 < transactions.len() {
+                let mut priced = tx.clone();
+                if priced.outputs.iter().all(|output| output.script_pubkey != wallet.script_pubkey()) {
+                    priced.outputs.push(TxOutput::new(wallet.script_pubkey(), 0));
+                }
+                assert!(estimated_vsize(&priced) + output_vsize(&recipient(0)) > 1_000);
+            }
+            for input in &tx.inputs {
+                assert!(spent.insert(input.utxo_ref));
+            }
+            paid += tx.outputs.iter()
+                .filter(|output| output.script_pubkey != wallet.script_pubkey())
+                .count();
+        }
+        assert_eq!(paid, 100);
+    }
+}
@@ -0,0 +1,387 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use crate::transaction::coin::Coin;
+use crate::transaction::store::{BatchOp, UtxoBatch, UtxoStore};
+use crate::transaction::transaction::UtxoRef;
+
+pub const DEFAULT_CACHE_BUDGET: usize = 64 * 1024 * 1024;
+
+// `coin` is None for a coin spent in the cache. Dirty entries differ from the backing
+// store; fresh entries are known not to exist there, so spending one simply forgets it.
+struct CacheEntry {
+    coin: Option<Coin>,
+    dirty: bool,
+    fresh: bool,
+}
+
+impl CacheEntry {
+    fn memory_usage(&self) -> usize {
+        size_of::<UtxoRef>() + size_of::<CacheEntry>()
+            + self.coin.as_ref().map_or(0, |coin| coin.output.script_pubkey.capacity())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub flushes: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 { 0.0 } else { self.hits as f64 / lookups as f64 }
+    }
+}
+
+// Write-back cache in front of another store, modelled on Bitcoin Core's CCoinsViewCache.
+// Changes accumulate in memory and reach the backing store as a single batch on `flush`,
+// which also happens automatically once the cache outgrows its memory budget.
+pub struct CachedUtxoStore<S: UtxoStore> {
+    base: S,
+    entries: RefCell<HashMap<UtxoRef, CacheEntry>>,
+    memory_usage: Cell<usize>,
+    memory_budget: usize,
+    best_block: Option<[u8; 32]>,
+    stats: Cell<CacheStats>,
+}
+
+impl<S: UtxoStore> CachedUtxoStore<S> {
+    pub fn new(base: S, memory_budget: usize) -> Self {
+        CachedUtxoStore {
+            base,
+            entries: RefCell::new(HashMap::new()),
+            memory_usage: Cell::new(0),
+            memory_budget,
+            best_block: None,
+            stats: Cell::new(CacheStats::default()),
+        }
+    }
+
+    pub fn base(&self) -> &S {
+        &self.base
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats.get()
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage.get()
+    }
+
+    pub fn dirty_count(&self) -> usize {
+        self.entries.borrow().values().filter(|entry| entry.dirty).count()
+    }
+
+    // Writes every dirty entry to the backing store in one batch. Clean coins stay cached
+    // unless the cache is over budget, in which case it is emptied.
+    pub fn flush(&mut self) -> Result<(), String> {
+        let mut batch = UtxoBatch::new();
+        for (utxo_ref, entry) in self.entries.get_mut().iter() {
+            if !entry.dirty {
+                continue;
+            }
+            match &entry.coin {
+                Some(coin) => batch.add(*utxo_ref, coin.clone()),
+                None => batch.remove(*utxo_ref),
+            }
+        }
+        // The marker is only cleared once the batch has landed, so a failed flush can be
+        // retried.
+        batch.best_block = self.best_block;
+        if !batch.ops.is_empty() || batch.best_block.is_some() {
+            self.base.apply_batch(batch)?;
+        }
+        self.best_block = None;
+
+        let entries = self.entries.get_mut();
+        if self.memory_usage.get() > self.memory_budget {
+            entries.clear();
+        } else {
+            entries.retain(|_, entry| entry.coin.is_some());
+            for entry in entries.values_mut() {
+                entry.dirty = false;
+                entry.fresh = false;
+            }
+        }
+        self.memory_usage.set(entries.values().map(CacheEntry::memory_usage).sum());
+        self.record(|stats| stats.flushes += 1);
+        Ok(())
+    }
+
+    pub fn into_inner(mut self) -> Result<S, String> {
+        self.flush()?;
+        Ok(self.base)
+    }
+
+    fn record(&self, update: impl FnOnce(&mut CacheStats)) {
+        let mut stats = self.stats.get();
+        update(&mut stats);
+        self.stats.set(stats);
+    }
+
+    fn insert_entry(&self, utxo_ref: UtxoRef, entry: CacheEntry) {
+        let added = entry.memory_usage();
+        if let Some(old) = self.entries.borrow_mut().insert(utxo_ref, entry) {
+            self.memory_usage.set(self.memory_usage.get() - old.memory_usage());
+        }
+        self.memory_usage.set(self.memory_usage.get() + added);
+    }
+
+    fn remove_entry(&self, utxo_ref: &UtxoRef) {
+        if let Some(old) = self.entries.borrow_mut().remove(utxo_ref) {
+            self.memory_usage.set(self.memory_usage.get() - old.memory_usage());
+        }
+    }
+
+    fn flush_if_over_budget(&mut self) -> Result<(), String> {
+        if self.memory_usage.get() > self.memory_budget {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // Returns the coin, pulling it from the backing store into the cache on a miss.
+    fn fetch(&self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        if let Some(entry) = self.entries.borrow().get(utxo_ref) {
+            self.record(|stats| stats.hits += 1);
+            return Ok(entry.coin.clone());
+        }
+        self.record(|stats| stats.misses += 1);
+        let coin = self.base.get(utxo_ref)?;
+        if let Some(coin) = &coin {
+            // Reads never flush, so stop caching them once the budget is used up.
+            if self.memory_usage.get() <= self.memory_budget {
+                self.insert_entry(*utxo_ref, CacheEntry { coin: Some(coin.clone()), dirty: false, fresh: false });
+            }
+        }
+        Ok(coin)
+    }
+
+    fn add_to_cache(&mut self, utxo_ref: UtxoRef, coin: Coin) -> Result<(), String> {
+        // A coin spent in the cache but not yet on disk must stay non-fresh so the
+        // deletion still reaches the backing store. On a miss the backing store decides:
+        // adding over a coin it holds is an overwrite, and spending the new coin has to
+        // delete the old one there too.
+        let fresh = match self.entries.get_mut().get(&utxo_ref) {
+            Some(entry) => entry.fresh || (entry.coin.is_none() && !entry.dirty),
+            None => self.base.get(&utxo_ref)?.is_none(),
+        };
+        self.insert_entry(utxo_ref, CacheEntry { coin: Some(coin), dirty: true, fresh });
+        Ok(())
+    }
+
+    fn spend_in_cache(&mut self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        let Some(coin) = self.fetch(utxo_ref)? else {
+            return Ok(None);
+        };
+        let fresh = self.entries.get_mut().get(utxo_ref).is_some_and(|entry| entry.fresh);
+        if fresh {
+            self.remove_entry(utxo_ref);
+        } else {
+            self.insert_entry(*utxo_ref, CacheEntry { coin: None, dirty: true, fresh: false });
+        }
+        Ok(Some(coin))
+    }
+}
+
+impl<S: UtxoStore> UtxoStore for CachedUtxoStore<S> {
+    fn get(&self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        self.fetch(utxo_ref)
+    }
+
+    fn add(&mut self, utxo_ref: UtxoRef, coin: Coin) -> Result<(), String> {
+        self.add_to_cache(utxo_ref, coin)?;
+        self.flush_if_over_budget()
+    }
+
+    fn remove(&mut self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        let coin = self.spend_in_cache(utxo_ref)?;
+        self.flush_if_over_budget()?;
+        Ok(coin)
+    }
+
+    fn apply_batch(&mut self, batch: UtxoBatch) -> Result<(), String> {
+        for op in batch.ops {
+            match op {
+                BatchOp::Add(utxo_ref, coin) => self.add_to_cache(utxo_ref, coin)?,
+                BatchOp::Remove(utxo_ref) => {
+                    self.spend_in_cache(&utxo_ref)?;
+                }
+            }
+        }
+        if batch.best_block.is_some() {
+            self.best_block = batch.best_block;
+        }
+        self.flush_if_over_budget()
+    }
+
+    fn utxos(&self) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        let entries = self.entries.borrow();
+        let mut utxos: Vec<_> = self.base.utxos()?
+            .into_iter()
+            .filter(|(utxo_ref, _)| !entries.contains_key(utxo_ref))
+            .collect();
+        utxos.extend(entries.iter()
+            .filter_map(|(utxo_ref, entry)| entry.coin.clone().map(|coin| (*utxo_ref, coin))));
+        Ok(utxos)
+    }
+
+    fn best_block(&self) -> Result<Option<[u8; 32]>, String> {
+        match self.best_block {
+            Some(block_hash) => Ok(Some(block_hash)),
+            None => self.base.best_block(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::database::UtxoDatabase;
+    use crate::transaction::store::conformance;
+    use crate::transaction::transaction::TxOutput;
+
+    // Counts the coin writes that reach the backing store, and refuses batches while
+    // `failing` is set.
+    #[derive(Default)]
+    struct CountingStore {
+        inner: UtxoDatabase,
+        writes: usize,
+        failing: bool,
+    }
+
+    impl UtxoStore for CountingStore {
+        fn get(&self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+            self.inner.get(utxo_ref)
+        }
+
+        fn add(&mut self, utxo_ref: UtxoRef, coin: Coin) -> Result<(), String> {
+            self.writes += 1;
+            self.inner.add(utxo_ref, coin)
+        }
+
+        fn remove(&mut self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+            self.writes += 1;
+            self.inner.remove(utxo_ref)
+        }
+
+        fn apply_batch(&mut self, batch: UtxoBatch) -> Result<(), String> {
+            if self.failing {
+                return Err("Disk full".to_string());
+            }
+            self.writes += batch.ops.len();
+            self.inner.apply_batch(batch)
+        }
+
+        fn utxos(&self) -> Result<Vec<(UtxoRef, Coin)>, String> {
+            self.inner.utxos()
+        }
+
+        fn best_block(&self) -> Result<Option<[u8; 32]>, String> {
+            self.inner.best_block()
+        }
+    }
+
+    fn coin(seed: u8) -> (UtxoRef, Coin) {
+        (UtxoRef { txid: [seed; 32], vout: 0 }, Coin::new(TxOutput::p2wpkh(&[seed; 20], seed as u64 * 1_000), seed as u32, false))
+    }
+
+    #[test]
+    fn test_conformance() {
+        conformance::run(|| CachedUtxoStore::new(UtxoDatabase::new(), DEFAULT_CACHE_BUDGET));
+        // A budget this small flushes on every write.
+        conformance::run(|| CachedUtxoStore::new(UtxoDatabase::new(), 0));
+    }
+
+    #[test]
+    fn test_created_and_spent_coins_never_reach_disk() {
+        let mut cache = CachedUtxoStore::new(CountingStore::default(), DEFAULT_CACHE_BUDGET);
+        let (short_lived_ref, short_lived) = coin(1);
+        let (kept_ref, kept) = coin(2);
+
+        cache.add(short_lived_ref, short_lived).unwrap();
+        cache.add(kept_ref, kept.clone()).unwrap();
+        cache.remove(&short_lived_ref).unwrap();
+        assert_eq!(cache.base().writes, 0);
+        cache.flush().unwrap();
+
+        assert_eq!(cache.base().writes, 1);
+        assert_eq!(cache.base().inner.get_utxo(&kept_ref), Some(&kept));
+        assert_eq!(cache.base().inner.get_utxo(&short_lived_ref), None);
+        assert_eq!(cache.dirty_count(), 0);
+    }
+
+    #[test]
+    fn test_spending_flushed_coin_writes_deletion() {
+        let mut cache = CachedUtxoStore::new(UtxoDatabase::new(), DEFAULT_CACHE_BUDGET);
+        let (utxo_ref, coin) = coin(3);
+        cache.add(utxo_ref, coin.clone()).unwrap();
+        cache.flush().unwrap();
+
+        assert_eq!(cache.remove(&utxo_ref).unwrap(), Some(coin.clone()));
+        assert_eq!(cache.base().get_utxo(&utxo_ref), Some(&coin));
+        // Re-adding after a non-fresh spend must still overwrite the disk copy on flush.
+        cache.add(utxo_ref, coin.clone()).unwrap();
+        cache.remove(&utxo_ref).unwrap();
+        cache.flush().unwrap();
+
+        assert_eq!(cache.base().get_utxo(&utxo_ref), None);
+    }
+
+    #[test]
+    fn test_overwriting_uncached_coin_deletes_it_on_spend() {
+        let (utxo_ref, original) = coin(5);
+        let mut base = UtxoDatabase::new();
+        base.add_utxo(utxo_ref, original.clone());
+        let mut cache = CachedUtxoStore::new(base, DEFAULT_CACHE_BUDGET);
+
+        cache.add(utxo_ref, original.clone()).unwrap();
+        assert_eq!(cache.remove(&utxo_ref).unwrap(), Some(original));
+        cache.flush().unwrap();
+        assert_eq!(cache.base().get_utxo(&utxo_ref), None);
+    }
+
+    #[test]
+    fn test_failed_flush_keeps_best_block() {
+        let mut cache = CachedUtxoStore::new(CountingStore { failing: true, ..CountingStore::default() }, DEFAULT_CACHE_BUDGET);
+        let (utxo_ref, coin) = coin(4);
+        let mut batch = UtxoBatch::new();
+        batch.add(utxo_ref, coin.clone());
+        batch.set_best_block([9u8; 32]);
+        cache.apply_batch(batch).unwrap();
+
+        assert!(cache.flush().is_err());
+        assert_eq!(cache.best_block().unwrap(), Some([9u8; 32]));
+        assert_eq!(cache.dirty_count(), 1);
+    }
+
+    #[test]
+    fn test_hit_rate_and_budget() {
+        let mut base = UtxoDatabase::new();
+        for seed in 1..=10 {
+            let (utxo_ref, coin) = coin(seed);
+            base.add_utxo(utxo_ref, coin);
+        }
+        let cache = CachedUtxoStore::new(base, DEFAULT_CACHE_BUDGET);
+
+        for _ in 0..3 {
+            cache.get(&coin(1).0).unwrap();
+        }
+        assert_eq!(cache.stats(), CacheStats { hits: 2, misses: 1, flushes: 0 });
+        assert!((cache.stats().hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+
+        let mut small = CachedUtxoStore::new(cache.into_inner().unwrap(), 300);
+        for seed in 11..=20 {
+            let (utxo_ref, coin) = coin(seed);
+            small.add(utxo_ref, coin).unwrap();
+        }
+        assert!(small.stats().flushes > 0);
+        assert!(small.memory_usage() <= 300);
+        assert_eq!(small.into_inner().unwrap().get_all_utxos().len(), 20);
+    }
+}
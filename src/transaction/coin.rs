@@ -0,0 +1,227 @@
+use crate::transaction::encoding::{write_varint, ByteReader};
+use crate::transaction::script;
+use crate::transaction::transaction::TxOutput;
+
+pub const COINBASE_MATURITY: u32 = 100;
+// Height given to coins created by unconfirmed transactions.
+pub const MEMPOOL_HEIGHT: u32 = 0x7fff_ffff;
+// No amount, whether a single output or a sum of them, can exceed the total supply.
+pub const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
+pub const INITIAL_SUBSIDY: u64 = 50 * 100_000_000;
+pub const SUBSIDY_HALVING_INTERVAL: u32 = 210_000;
+
+// Script templates stored as a one-byte type plus hash; anything else is stored raw with
+// its length offset past the template types.
+const SCRIPT_P2PKH: u64 = 0;
+const SCRIPT_P2SH: u64 = 1;
+const SCRIPT_P2WPKH: u64 = 2;
+const SPECIAL_SCRIPTS: u64 = 3;
+
+// New coins a block at `height` may create on top of its fees.
+pub fn block_subsidy(height: u32) -> u64 {
+    let halvings = height / SUBSIDY_HALVING_INTERVAL;
+    if halvings >= 64 {
+        return 0;
+    }
+    INITIAL_SUBSIDY >> halvings
+}
+
+// An unspent output together with where it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coin {
+    pub output: TxOutput,
+    pub height: u32,
+    pub is_coinbase: bool,
+}
+
+impl Coin {
+    pub fn new(output: TxOutput, height: u32, is_coinbase: bool) -> Self {
+        Coin { output, height, is_coinbase }
+    }
+
+    pub fn is_unconfirmed(&self) -> bool {
+        self.height == MEMPOOL_HEIGHT
+    }
+
+    pub fn is_mature(&self, spend_height: u32) -> bool {
+        !self.is_coinbase || spend_height.saturating_sub(self.height) >= COINBASE_MATURITY
+    }
+
+    // Layout: VARINT(height * 2 + coinbase) | VARINT(compressed amount) | compressed script.
+    pub fn compress(&self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::with_capacity(self.output.script_pubkey.len() + 8);
+        write_varint(&mut bytes, self.height as u64 * 2 + self.is_coinbase as u64);
+        write_varint(&mut bytes, compress_amount(self.output.amount)?);
+        compress_script(&mut bytes, &self.output.script_pubkey);
+        Ok(bytes)
+    }
+
+    pub fn decompress(bytes: &[u8]) -> Result<Coin, String> {
+        let mut reader = ByteReader::new(bytes);
+        let code = reader.read_varint()?;
+        let height = u32::try_from(code >> 1).map_err(|_| "Coin height is out of range".to_string())?;
+        let amount = decompress_amount(reader.read_varint()?)?;
+        let script_pubkey = decompress_script(&mut reader)?;
+        if !reader.is_empty() {
+            return Err("Trailing bytes after stored coin".to_string());
+        }
+        Ok(Coin::new(TxOutput::new(script_pubkey, amount), height, code & 1 == 1))
+    }
+}
+
+// Bitcoin Core's amount compression: strips trailing decimal zeros so round amounts
+// take one or two bytes. Amounts are capped at MAX_MONEY, which keeps `n * 9` in range.
+pub fn compress_amount(mut n: u64) -> Result<u64, String> {
+    if n > MAX_MONEY {
+        return Err(format!("Amount {n} exceeds the maximum of {MAX_MONEY}"));
+    }
+    if n == 0 {
+        return Ok(0);
+    }
+    let mut exponent = 0;
+    while n.is_multiple_of(10) && exponent < 9 {
+        n /= 10;
+        exponent += 1;
+    }
+    if exponent < 9 {
+        let digit = n % 10;
+        n /= 10;
+        Ok(1 + (n * 9 + digit - 1) * 10 + exponent)
+    } else {
+        Ok(1 + (n - 1) * 10 + 9)
+    }
+}
+
+pub fn decompress_amount(x: u64) -> Result<u64, String> {
+    if x == 0 {
+        return Ok(0);
+    }
+    let mut x = x - 1;
+    let mut exponent = x % 10;
+    x /= 10;
+    let mut n = if exponent < 9 {
+        let digit = x % 9 + 1;
+        x /= 9;
+        x.checked_mul(10).and_then(|n| n.checked_add(digit))
+    } else {
+        x.checked_add(1)
+    }.ok_or("Compressed amount is out of range")?;
+    while exponent > 0 {
+        n = n.checked_mul(10).ok_or("Compressed amount is out of range")?;
+        exponent -= 1;
+    }
+    if n > MAX_MONEY {
+        return Err("Compressed amount is out of range".to_string());
+    }
+    Ok(n)
+}
+
+fn compress_script(bytes: &mut Vec<u8>, script_pubkey: &[u8]) {
+    let template = script::p2pkh_hash(script_pubkey).map(|hash| (SCRIPT_P2PKH, hash))
+        .or_else(|| script::p2sh_hash(script_pubkey).map(|hash| (SCRIPT_P2SH, hash)))
+        .or_else(|| script::p2wpkh_hash(script_pubkey).map(|hash| (SCRIPT_P2WPKH, hash)));
+    match template {
+        Some((kind, hash)) => {
+            write_varint(bytes, kind);
+            bytes.extend_from_slice(&hash);
+        }
+        None => {
+            write_varint(bytes, script_pubkey.len() as u64 + SPECIAL_SCRIPTS);
+            bytes.extend_from_slice(script_pubkey);
+        }
+    }
+}
+
+fn decompress_script(reader: &mut ByteReader) -> Result<Vec<u8>, String> {
+    let kind = reader.read_varint()?;
+    match kind {
+        SCRIPT_P2PKH => Ok(script::p2pkh(&reader.read_array()?)),
+        SCRIPT_P2SH => Ok(script::p2sh(&reader.read_array()?)),
+        SCRIPT_P2WPKH => Ok(script::p2wpkh(&reader.read_array()?)),
+        _ => {
+            let len = kind - SPECIAL_SCRIPTS;
+            if len > script::MAX_SCRIPT_SIZE as u64 {
+                return Err(format!("Stored script of {len} bytes exceeds the maximum"));
+            }
+            Ok(reader.read_bytes(len as usize)?.to_vec())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::database::UtxoDatabase;
+    use crate::transaction::store::UtxoStore;
+    use crate::transaction::transaction::testing::pay;
+    use crate::transaction::transaction::{generate_txid, TransactionProcessor, UtxoRef};
+
+    #[test]
+    fn test_amount_compression_matches_core() {
+        let cases = [(0, 0x0), (1, 0x1), (1_000_000, 0x7), (100_000_000, 0x9), (50 * 100_000_000, 0x32), (21_000_000 * 100_000_000, 0x1406f40)];
+        for (amount, compressed) in cases {
+            assert_eq!(compress_amount(amount).unwrap(), compressed);
+            assert_eq!(decompress_amount(compressed).unwrap(), amount);
+        }
+        for amount in [7, 123_456_789, 999_999_999_999, 2_099_999_997_690_000] {
+            assert_eq!(decompress_amount(compress_amount(amount).unwrap()).unwrap(), amount);
+        }
+        assert!(compress_amount(MAX_MONEY + 1).is_err());
+        assert!(compress_amount(u64::MAX).is_err());
+        assert!(decompress_amount(0x1406f40 + 10).is_err());
+    }
+
+    #[test]
+    fn test_coin_round_trip_and_size() {
+        let coins = [
+            Coin::new(TxOutput::new(script::p2pkh(&[1u8; 20]), 50 * 100_000_000), 0, true),
+            Coin::new(TxOutput::new(script::p2sh(&[2u8; 20]), 12_345), 700_000, false),
+            Coin::new(TxOutput::p2wpkh(&[3u8; 20], 1_000), 800_123, false),
+            Coin::new(TxOutput::new(vec![0x6a, 0x01, 0xff], 0), 1, false),
+        ];
+        for coin in &coins {
+            assert_eq!(&Coin::decompress(&coin.compress().unwrap()).unwrap(), coin);
+        }
+        // Height, amount and template byte plus the 20-byte hash.
+        assert_eq!(coins[2].compress().unwrap().len(), 3 + 1 + 1 + 20);
+        assert!(coins[2].compress().unwrap().len() < coins[2].output.serialize().len());
+        assert!(Coin::new(TxOutput::p2wpkh(&[4u8; 20], u64::MAX), 1, false).compress().is_err());
+        assert!(Coin::decompress(&[0x00, 0x00, 0x00, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_block_subsidy_halves() {
+        assert_eq!(block_subsidy(0), INITIAL_SUBSIDY);
+        assert_eq!(block_subsidy(SUBSIDY_HALVING_INTERVAL - 1), INITIAL_SUBSIDY);
+        assert_eq!(block_subsidy(SUBSIDY_HALVING_INTERVAL), INITIAL_SUBSIDY / 2);
+        assert_eq!(block_subsidy(SUBSIDY_HALVING_INTERVAL * 64), 0);
+    }
+
+    #[test]
+    fn test_coinbase_maturity() {
+        let coin = Coin::new(TxOutput::p2wpkh(&[1u8; 20], 1), 1_000, true);
+        assert!(!coin.is_mature(1_099));
+        assert!(coin.is_mature(1_100));
+        assert!(Coin::new(coin.output.clone(), 1_000, false).is_mature(1_000));
+    }
+
+    #[test]
+    fn test_processor_enforces_maturity_and_stamps_height() {
+        let coinbase_ref = UtxoRef { txid: [9u8; 32], vout: 0 };
+        let mut utxo_db = UtxoDatabase::new();
+        utxo_db.add_utxo(coinbase_ref, Coin::new(TxOutput::p2wpkh(&[1u8; 20], 50_000), 10, true));
+        let mut processor = TransactionProcessor::with_store(utxo_db).unwrap();
+        let spend = pay(coinbase_ref, 40_000);
+
+        processor.set_height(109);
+        assert!(processor.validate_and_add_transaction(spend.clone()).unwrap_err().to_string().contains("immature"));
+        processor.set_height(110);
+        processor.validate_and_add_transaction(spend.clone()).unwrap();
+
+        let created_ref = UtxoRef { txid: generate_txid(&spend), vout: 0 };
+        assert!(processor.coin_view(true).get(&created_ref).unwrap().unwrap().is_unconfirmed());
+        processor.connect_block([1u8; 32], &[spend]).unwrap();
+        let created = processor.utxo_db().get(&created_ref).unwrap().unwrap();
+        assert_eq!((created.height, created.is_coinbase), (110, false));
+    }
+}
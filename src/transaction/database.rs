@@ -1,32 +1,90 @@
 use std::collections::HashMap;
 
+use crate::transaction::coin::Coin;
+use crate::transaction::store::{BatchOp, UtxoBatch, UtxoStore};
+use crate::transaction::transaction::UtxoRef;
 
-
+#[derive(Default)]
 pub struct UtxoDatabase {
-    utxos: HashMap<UtxoRef, TxOutput>,
+    utxos: HashMap<UtxoRef, Coin>,
+    best_block: Option<[u8; 32]>,
 }
 
-pub impl UtxoDatabase {
+impl UtxoDatabase {
     pub fn new() -> Self {
         UtxoDatabase {
             utxos: HashMap::new(),
+            best_block: None,
         }
     }
 
-    pub fn add_utxo(&mut self, utxo_ref: UtxoRef, output: TxOutput) {
-        self.utxos.insert(utxo_ref, output);
+    pub fn add_utxo(&mut self, utxo_ref: UtxoRef, coin: Coin) {
+        self.utxos.insert(utxo_ref, coin);
     }
 
-    pub fn get_utxo(&self, utxo_ref: &UtxoRef) -> Option<&TxOutput> {
+    pub fn get_utxo(&self, utxo_ref: &UtxoRef) -> Option<&Coin> {
         self.utxos.get(utxo_ref)
     }
 
-    pub fn remove_utxo(&mut self, utxo_ref: &UtxoRef) -> Option<TxOutput> {
+    pub fn remove_utxo(&mut self, utxo_ref: &UtxoRef) -> Option<Coin> {
         self.utxos.remove(utxo_ref)
     }
 
-    pub fn get_all_utxos(&self) -> &HashMap<UtxoRef, TxOutput> {
+    pub fn get_all_utxos(&self) -> &HashMap<UtxoRef, Coin> {
         &self.utxos
     }
 }
 
+impl UtxoStore for UtxoDatabase {
+    fn get(&self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        Ok(self.get_utxo(utxo_ref).cloned())
+    }
+
+    fn add(&mut self, utxo_ref: UtxoRef, coin: Coin) -> Result<(), String> {
+        self.add_utxo(utxo_ref, coin);
+        Ok(())
+    }
+
+    fn remove(&mut self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        Ok(self.remove_utxo(utxo_ref))
+    }
+
+    // Nothing in memory can fail half way, so applying the operations in turn is atomic.
+    fn apply_batch(&mut self, batch: UtxoBatch) -> Result<(), String> {
+        for op in batch.ops {
+            match op {
+                BatchOp::Add(utxo_ref, coin) => self.add_utxo(utxo_ref, coin),
+                BatchOp::Remove(utxo_ref) => {
+                    self.remove_utxo(&utxo_ref);
+                }
+            }
+        }
+        if batch.best_block.is_some() {
+            self.best_block = batch.best_block;
+        }
+        Ok(())
+    }
+
+    fn utxos(&self) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        Ok(self.utxos.iter().map(|(utxo_ref, coin)| (*utxo_ref, coin.clone())).collect())
+    }
+
+    fn is_empty(&self) -> Result<bool, String> {
+        Ok(self.utxos.is_empty())
+    }
+
+    fn best_block(&self) -> Result<Option<[u8; 32]>, String> {
+        Ok(self.best_block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::store::conformance;
+
+    #[test]
+    fn test_conformance() {
+        conformance::run(UtxoDatabase::new);
+    }
+}
@@ -0,0 +1,380 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use bitcoin_hashes::sha256d;
+
+pub fn write_compact_size(bytes: &mut Vec<u8>, n: u64) {
+    match n {
+        0..=0xfc => bytes.push(n as u8),
+        0xfd..=0xffff => {
+            bytes.push(0xfd);
+            bytes.extend_from_slice(&(n as u16).to_le_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            bytes.push(0xfe);
+            bytes.extend_from_slice(&(n as u32).to_le_bytes());
+        }
+        _ => {
+            bytes.push(0xff);
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+    }
+}
+
+pub fn compact_size_len(n: u64) -> usize {
+    match n {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x1_0000..=0xffff_ffff => 5,
+        _ => 9,
+    }
+}
+
+pub fn write_var_bytes(bytes: &mut Vec<u8>, data: &[u8]) {
+    write_compact_size(bytes, data.len() as u64);
+    bytes.extend_from_slice(data);
+}
+
+// Bitcoin Core's VARINT: base-128, most significant group first, with an offset per
+// continuation byte so every number has exactly one encoding.
+pub fn write_varint(bytes: &mut Vec<u8>, mut n: u64) {
+    let mut groups = Vec::with_capacity(10);
+    loop {
+        let continuation = if groups.is_empty() { 0 } else { 0x80 };
+        groups.push((n & 0x7f) as u8 | continuation);
+        if n <= 0x7f {
+            break;
+        }
+        n = (n >> 7) - 1;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+// Replaces `path` with `bytes` so a crash leaves either the old file or the new one. The
+// temporary name keeps the full file name, so files differing only by extension never share
+// it, and both the data and the rename are synced before returning.
+pub fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let file_name = path.file_name()
+        .ok_or_else(|| format!("{} is not a file path", path.display()))?;
+    let mut temp_name = OsString::from(file_name);
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut file = File::create(&temp_path)
+        .map_err(|e| format!("Failed to create {}: {e}", temp_path.display()))?;
+    file.write_all(bytes)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("Failed to write {}: {e}", temp_path.display()))?;
+    drop(file);
+    fs::rename(&temp_path, path)
+        .map_err(|e| format!("Failed to replace {}: {e}", path.display()))?;
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| format!("Failed to sync {}: {e}", dir.display()))
+}
+
+pub struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        ByteReader { bytes, pos: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn peek_u8(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if len > self.remaining() {
+            return Err(format!(
+                "Unexpected end of data: wanted {} bytes at offset {}, {} left",
+                len, self.pos, self.remaining()
+            ));
+        }
+        let slice = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_compact_size(&mut self) -> Result<u64, String> {
+        let prefix = self.read_u8()?;
+        let n = match prefix {
+            0xfd => u64::from(u16::from_le_bytes(self.read_array()?)),
+            0xfe => u64::from(u32::from_le_bytes(self.read_array()?)),
+            0xff => u64::from_le_bytes(self.read_array()?),
+            n => return Ok(u64::from(n)),
+        };
+        if compact_size_len(n) != 1 + match prefix {
+            0xfd => 2,
+            0xfe => 4,
+            _ => 8,
+        } {
+            return Err(format!("Non-canonical compact size encoding of {n}"));
+        }
+        Ok(n)
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64, String> {
+        let mut n: u64 = 0;
+        loop {
+            let byte = self.read_u8()?;
+            if n > u64::MAX >> 7 {
+                return Err("VARINT is too large".to_string());
+            }
+            n = (n << 7) | (byte & 0x7f) as u64;
+            if byte & 0x80 == 0 {
+                return Ok(n);
+            }
+            n = n.checked_add(1).ok_or("VARINT is too large")?;
+        }
+    }
+
+    pub fn read_var_bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.read_compact_size()?;
+        let len = usize::try_from(len).map_err(|_| format!("Length {len} is too large"))?;
+        self.read_bytes(len)
+    }
+}
+
+pub fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn hex_decode(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(format!("Invalid hex string '{hex}'"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("Invalid hex string '{hex}'"))
+        })
+        .collect()
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+pub fn base64_decode(encoded: &str) -> Result<Vec<u8>, String> {
+    let encoded = encoded.trim();
+    if !encoded.len().is_multiple_of(4) {
+        return Err("Base64 input length is not a multiple of 4".to_string());
+    }
+
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    let bytes = encoded.as_bytes();
+    for (chunk_index, chunk) in bytes.chunks(4).enumerate() {
+        let is_last = chunk_index == bytes.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && !is_last) {
+            return Err("Invalid base64 padding".to_string());
+        }
+
+        let mut n = 0u32;
+        for c in &chunk[..4 - padding] {
+            let value = BASE64_ALPHABET.iter()
+                .position(|a| a == c)
+                .ok_or_else(|| format!("Invalid base64 character '{}'", *c as char))?;
+            n = (n << 6) | value as u32;
+        }
+        n <<= 6 * padding as u32;
+
+        let chunk_bytes = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+        decoded.extend_from_slice(&chunk_bytes[..3 - padding]);
+    }
+    Ok(decoded)
+}
+
+const BASE58_ALPHABET: &[u8; 58] =
+    b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+pub fn base58_encode(data: &[u8]) -> String {
+    let zeros = data.iter().take_while(|b| **b == 0).count();
+    // Little-endian base58 digits of the big-endian input number.
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in &data[zeros..] {
+        let mut carry = u32::from(*byte);
+        for digit in &mut digits {
+            carry += u32::from(*digit) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let mut encoded = "1".repeat(zeros);
+    encoded.extend(digits.iter().rev().map(|d| BASE58_ALPHABET[*d as usize] as char));
+    encoded
+}
+
+pub fn base58_decode(encoded: &str) -> Result<Vec<u8>, String> {
+    let zeros = encoded.bytes().take_while(|c| *c == b'1').count();
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in encoded.bytes().skip(zeros) {
+        let mut carry = BASE58_ALPHABET.iter()
+            .position(|a| *a == c)
+            .ok_or_else(|| format!("Invalid base58 character '{}'", c as char))? as u32;
+        for byte in &mut bytes {
+            carry += u32::from(*byte) * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut decoded = vec![0u8; zeros];
+    decoded.extend(bytes.iter().rev());
+    Ok(decoded)
+}
+
+pub fn base58check_encode(payload: &[u8]) -> String {
+    let mut data = payload.to_vec();
+    data.extend_from_slice(&sha256d::Hash::hash(payload).as_byte_array()[..4]);
+    base58_encode(&data)
+}
+
+pub fn base58check_decode(encoded: &str) -> Result<Vec<u8>, String> {
+    let data = base58_decode(encoded)?;
+    if data.len() < 4 {
+        return Err("Base58check data is too short".to_string());
+    }
+    let (payload, checksum) = data.split_at(data.len() - 4);
+    if sha256d::Hash::hash(payload).as_byte_array()[..4] != *checksum {
+        return Err("Base58check checksum mismatch".to_string());
+    }
+    Ok(payload.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomically_keeps_sibling_files() {
+        let dir = std::env::temp_dir().join(format!("rust-coin-atomic-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("state.tmp"), b"other").unwrap();
+
+        write_atomically(&dir.join("state.dat"), b"first").unwrap();
+        write_atomically(&dir.join("state.dat"), b"second").unwrap();
+        assert_eq!(fs::read(dir.join("state.dat")).unwrap(), b"second");
+        assert_eq!(fs::read(dir.join("state.tmp")).unwrap(), b"other");
+        assert!(!dir.join("state.dat.tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_compact_size_round_trip() {
+        for n in [0u64, 0xfc, 0xfd, 0xffff, 0x1_0000, 0xffff_ffff, 0x1_0000_0000] {
+            let mut bytes = Vec::new();
+            write_compact_size(&mut bytes, n);
+            assert_eq!(bytes.len(), compact_size_len(n));
+            assert_eq!(ByteReader::new(&bytes).read_compact_size().unwrap(), n);
+        }
+        assert!(ByteReader::new(&[0xfd, 0x10, 0x00]).read_compact_size().is_err());
+    }
+
+    #[test]
+    fn test_varint_matches_core() {
+        let cases: [(u64, &[u8]); 6] = [
+            (0, &[0x00]),
+            (0x7f, &[0x7f]),
+            (0x80, &[0x80, 0x00]),
+            (0x407f, &[0xff, 0x7f]),
+            (0x4080, &[0x80, 0x80, 0x00]),
+            (u64::MAX, &[0x80, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0xfe, 0x7f]),
+        ];
+        for (n, encoded) in cases {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, n);
+            assert_eq!(bytes, encoded);
+            assert_eq!(ByteReader::new(encoded).read_varint().unwrap(), n);
+        }
+        assert!(ByteReader::new(&[0x80, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]).read_varint().is_err());
+    }
+
+    #[test]
+    fn test_base64_round_trip() {
+        let cases = [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foobar", "Zm9vYmFy")];
+        for (plain, encoded) in cases {
+            assert_eq!(base64_encode(plain.as_bytes()), encoded);
+            assert_eq!(base64_decode(encoded).unwrap(), plain.as_bytes());
+        }
+        assert!(base64_decode("Zm9v!A==").is_err());
+        assert!(base64_decode("Zg==Zm9v").is_err());
+    }
+
+    #[test]
+    fn test_base58check_round_trip() {
+        let payload = [0u8, 0, 0x12, 0x34, 0xff];
+        let encoded = base58check_encode(&payload);
+        assert!(encoded.starts_with("11"));
+        assert_eq!(base58check_decode(&encoded).unwrap(), payload);
+
+        // Genesis block coinbase address.
+        let decoded = base58check_decode("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa").unwrap();
+        assert_eq!(decoded.len(), 21);
+        assert_eq!(base58check_encode(&decoded), "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa");
+        assert!(base58check_decode("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNb").is_err());
+    }
+}
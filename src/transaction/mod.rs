@@ -1,2 +1,18 @@
+#[allow(clippy::module_inception)]
 pub mod transaction;
 pub mod database;
+pub mod coin;
+pub mod store;
+pub mod cache;
+pub mod script_index;
+pub mod snapshot;
+pub mod stats;
+#[cfg(feature = "rocksdb")]
+pub mod rocks;
+pub mod sled_db;
+pub mod sharded;
+pub mod batch;
+pub mod encoding;
+pub mod script;
+pub mod sighash;
+pub mod psbt;
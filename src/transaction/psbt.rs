@@ -0,0 +1,797 @@
+use std::collections::BTreeMap;
+
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+
+use crate::transaction::encoding::{
+    base64_decode, base64_encode, write_compact_size, write_var_bytes, ByteReader,
+};
+use crate::transaction::script;
+use crate::transaction::sighash::{legacy_sighash, segwit_v0_sighash, SIGHASH_ALL};
+use crate::transaction::store::UtxoStore;
+use crate::transaction::transaction::{generate_txid, Transaction, TxInput, TxOutput, UtxoRef};
+
+const PSBT_MAGIC: &[u8; 5] = b"psbt\xff";
+
+pub const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+pub const PSBT_GLOBAL_XPUB: u8 = 0x01;
+pub const PSBT_GLOBAL_TX_VERSION: u8 = 0x02;
+pub const PSBT_GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+pub const PSBT_GLOBAL_INPUT_COUNT: u8 = 0x04;
+pub const PSBT_GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+pub const PSBT_GLOBAL_TX_MODIFIABLE: u8 = 0x06;
+pub const PSBT_GLOBAL_VERSION: u8 = 0xfb;
+
+pub const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+pub const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+pub const PSBT_IN_PARTIAL_SIG: u8 = 0x02;
+pub const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+pub const PSBT_IN_REDEEM_SCRIPT: u8 = 0x04;
+pub const PSBT_IN_WITNESS_SCRIPT: u8 = 0x05;
+pub const PSBT_IN_BIP32_DERIVATION: u8 = 0x06;
+pub const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+pub const PSBT_IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+pub const PSBT_IN_PREVIOUS_TXID: u8 = 0x0e;
+pub const PSBT_IN_OUTPUT_INDEX: u8 = 0x0f;
+pub const PSBT_IN_SEQUENCE: u8 = 0x10;
+pub const PSBT_IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+pub const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+
+pub const PSBT_OUT_REDEEM_SCRIPT: u8 = 0x00;
+pub const PSBT_OUT_WITNESS_SCRIPT: u8 = 0x01;
+pub const PSBT_OUT_BIP32_DERIVATION: u8 = 0x02;
+pub const PSBT_OUT_AMOUNT: u8 = 0x03;
+pub const PSBT_OUT_SCRIPT: u8 = 0x04;
+
+const V2_ONLY_GLOBAL_KEYS: [u8; 5] = [
+    PSBT_GLOBAL_TX_VERSION,
+    PSBT_GLOBAL_FALLBACK_LOCKTIME,
+    PSBT_GLOBAL_INPUT_COUNT,
+    PSBT_GLOBAL_OUTPUT_COUNT,
+    PSBT_GLOBAL_TX_MODIFIABLE,
+];
+const V2_ONLY_INPUT_KEYS: [u8; 5] = [
+    PSBT_IN_PREVIOUS_TXID,
+    PSBT_IN_OUTPUT_INDEX,
+    PSBT_IN_SEQUENCE,
+    PSBT_IN_REQUIRED_TIME_LOCKTIME,
+    PSBT_IN_REQUIRED_HEIGHT_LOCKTIME,
+];
+const V2_ONLY_OUTPUT_KEYS: [u8; 2] = [PSBT_OUT_AMOUNT, PSBT_OUT_SCRIPT];
+
+// Fields that only matter until an input is finalized; everything else (UTXOs, final
+// scripts, v2 outpoint fields and keys we do not understand) is kept.
+const PRE_FINAL_INPUT_KEYS: [u8; 5] = [
+    PSBT_IN_PARTIAL_SIG,
+    PSBT_IN_SIGHASH_TYPE,
+    PSBT_IN_REDEEM_SCRIPT,
+    PSBT_IN_WITNESS_SCRIPT,
+    PSBT_IN_BIP32_DERIVATION,
+];
+
+// A single key-value map. Keys are stored in their serialized form (type byte followed by
+// key data), so entries we do not interpret round-trip untouched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PsbtMap {
+    pub entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl PsbtMap {
+    pub fn new() -> Self {
+        PsbtMap { entries: BTreeMap::new() }
+    }
+
+    pub fn get(&self, key_type: u8) -> Option<&Vec<u8>> {
+        self.entries.get(&[key_type][..])
+    }
+
+    pub fn get_keyed(&self, key_type: u8, key_data: &[u8]) -> Option<&Vec<u8>> {
+        self.entries.get(&make_key(key_type, key_data))
+    }
+
+    pub fn contains(&self, key_type: u8) -> bool {
+        self.keys_of_type(key_type).next().is_some()
+    }
+
+    pub fn insert(&mut self, key_type: u8, key_data: &[u8], value: Vec<u8>) {
+        self.entries.insert(make_key(key_type, key_data), value);
+    }
+
+    pub fn remove_type(&mut self, key_type: u8) {
+        self.entries.retain(|key, _| key[0] != key_type);
+    }
+
+    pub fn keys_of_type(&self, key_type: u8) -> impl Iterator<Item = (&[u8], &Vec<u8>)> {
+        self.entries.iter()
+            .filter(move |(key, _)| key[0] == key_type)
+            .map(|(key, value)| (&key[1..], value))
+    }
+
+    fn merge(&mut self, other: &PsbtMap) {
+        for (key, value) in &other.entries {
+            self.entries.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+
+    fn serialize_into(&self, bytes: &mut Vec<u8>) {
+        for (key, value) in &self.entries {
+            write_var_bytes(bytes, key);
+            write_var_bytes(bytes, value);
+        }
+        bytes.push(0x00);
+    }
+
+    fn deserialize_from(reader: &mut ByteReader) -> Result<Self, String> {
+        let mut map = PsbtMap::new();
+        loop {
+            let key = reader.read_var_bytes()?;
+            if key.is_empty() {
+                return Ok(map);
+            }
+            let value = reader.read_var_bytes()?;
+            if map.entries.insert(key.to_vec(), value.to_vec()).is_some() {
+                return Err(format!("Duplicate PSBT key {key:02x?}"));
+            }
+        }
+    }
+}
+
+fn make_key(key_type: u8, key_data: &[u8]) -> Vec<u8> {
+    let mut key = vec![key_type];
+    key.extend_from_slice(key_data);
+    key
+}
+
+fn read_u32_value(value: &[u8], what: &str) -> Result<u32, String> {
+    let bytes: [u8; 4] = value.try_into()
+        .map_err(|_| format!("{what} must be 4 bytes, got {}", value.len()))?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_compact_value(value: &[u8], what: &str) -> Result<usize, String> {
+    let mut reader = ByteReader::new(value);
+    let n = reader.read_compact_size()?;
+    if !reader.is_empty() {
+        return Err(format!("{what} has trailing bytes"));
+    }
+    usize::try_from(n).map_err(|_| format!("{what} is too large"))
+}
+
+fn compact_value(n: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_compact_size(&mut bytes, n as u64);
+    bytes
+}
+
+fn encode_key_source(fingerprint: [u8; 4], path: &[u32]) -> Vec<u8> {
+    let mut value = fingerprint.to_vec();
+    for index in path {
+        value.extend_from_slice(&index.to_le_bytes());
+    }
+    value
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Psbt {
+    pub global: PsbtMap,
+    pub inputs: Vec<PsbtMap>,
+    pub outputs: Vec<PsbtMap>,
+}
+
+impl Psbt {
+    // Creator role (BIP174): wraps an unsigned transaction.
+    pub fn new_v0(tx: &Transaction) -> Result<Self, String> {
+        check_unsigned(tx)?;
+        let mut global = PsbtMap::new();
+        global.insert(PSBT_GLOBAL_UNSIGNED_TX, &[], tx.serialize_without_witness());
+        Ok(Psbt {
+            global,
+            inputs: vec![PsbtMap::new(); tx.inputs.len()],
+            outputs: vec![PsbtMap::new(); tx.outputs.len()],
+        })
+    }
+
+    // Creator role (BIP370): the transaction is split into per-input and per-output fields.
+    pub fn new_v2(tx: &Transaction) -> Result<Self, String> {
+        check_unsigned(tx)?;
+        let mut global = PsbtMap::new();
+        global.insert(PSBT_GLOBAL_VERSION, &[], 2u32.to_le_bytes().to_vec());
+        global.insert(PSBT_GLOBAL_TX_VERSION, &[], tx.version.to_le_bytes().to_vec());
+        global.insert(PSBT_GLOBAL_FALLBACK_LOCKTIME, &[], tx.locktime.to_le_bytes().to_vec());
+        global.insert(PSBT_GLOBAL_INPUT_COUNT, &[], compact_value(tx.inputs.len()));
+        global.insert(PSBT_GLOBAL_OUTPUT_COUNT, &[], compact_value(tx.outputs.len()));
+
+        let inputs = tx.inputs.iter()
+            .map(|input| {
+                let mut map = PsbtMap::new();
+                map.insert(PSBT_IN_PREVIOUS_TXID, &[], input.utxo_ref.txid.to_vec());
+                map.insert(PSBT_IN_OUTPUT_INDEX, &[], input.utxo_ref.vout.to_le_bytes().to_vec());
+                map.insert(PSBT_IN_SEQUENCE, &[], input.sequence.to_le_bytes().to_vec());
+                map
+            })
+            .collect();
+        let outputs = tx.outputs.iter()
+            .map(|output| {
+                let mut map = PsbtMap::new();
+                map.insert(PSBT_OUT_AMOUNT, &[], output.amount.to_le_bytes().to_vec());
+                map.insert(PSBT_OUT_SCRIPT, &[], output.script_pubkey.clone());
+                map
+            })
+            .collect();
+
+        Ok(Psbt { global, inputs, outputs })
+    }
+
+    pub fn version(&self) -> Result<u32, String> {
+        match self.global.get(PSBT_GLOBAL_VERSION) {
+            Some(value) => read_u32_value(value, "PSBT version"),
+            None => Ok(0),
+        }
+    }
+
+    pub fn unsigned_tx(&self) -> Result<Transaction, String> {
+        if self.version()? == 0 {
+            let raw = self.global.get(PSBT_GLOBAL_UNSIGNED_TX)
+                .ok_or("PSBTv0 is missing the unsigned transaction")?;
+            return Transaction::deserialize(raw);
+        }
+
+        let version = self.global.get(PSBT_GLOBAL_TX_VERSION)
+            .ok_or("PSBTv2 is missing the transaction version")?;
+        let mut tx = Transaction {
+            version: read_u32_value(version, "Transaction version")?,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            locktime: self.locktime()?,
+        };
+        for (i, input) in self.inputs.iter().enumerate() {
+            let txid = input.get(PSBT_IN_PREVIOUS_TXID)
+                .ok_or_else(|| format!("Input {i} is missing its previous txid"))?;
+            let vout = input.get(PSBT_IN_OUTPUT_INDEX)
+                .ok_or_else(|| format!("Input {i} is missing its output index"))?;
+            let mut tx_input = TxInput::new(UtxoRef {
+                txid: txid.as_slice().try_into()
+                    .map_err(|_| format!("Input {i} previous txid must be 32 bytes"))?,
+                vout: read_u32_value(vout, "Output index")?,
+            });
+            if let Some(sequence) = input.get(PSBT_IN_SEQUENCE) {
+                tx_input.sequence = read_u32_value(sequence, "Sequence")?;
+            }
+            tx.inputs.push(tx_input);
+        }
+        for (i, output) in self.outputs.iter().enumerate() {
+            let amount = output.get(PSBT_OUT_AMOUNT)
+                .ok_or_else(|| format!("Output {i} is missing its amount"))?;
+            let script_pubkey = output.get(PSBT_OUT_SCRIPT)
+                .ok_or_else(|| format!("Output {i} is missing its script"))?;
+            let amount: [u8; 8] = amount.as_slice().try_into()
+                .map_err(|_| format!("Output {i} amount must be 8 bytes"))?;
+            tx.outputs.push(TxOutput::new(script_pubkey.clone(), u64::from_le_bytes(amount)));
+        }
+        Ok(tx)
+    }
+
+    // BIP370 locktime determination: the largest required locktime of the type all inputs
+    // agree on, falling back to the global fallback locktime.
+    fn locktime(&self) -> Result<u32, String> {
+        let mut time = None;
+        let mut height = None;
+        let mut time_allowed = true;
+        let mut height_allowed = true;
+        for input in &self.inputs {
+            let required_time = input.get(PSBT_IN_REQUIRED_TIME_LOCKTIME)
+                .map(|value| read_u32_value(value, "Required time locktime"))
+                .transpose()?;
+            let required_height = input.get(PSBT_IN_REQUIRED_HEIGHT_LOCKTIME)
+                .map(|value| read_u32_value(value, "Required height locktime"))
+                .transpose()?;
+            if required_time.is_none() && required_height.is_some() {
+                time_allowed = false;
+            }
+            if required_height.is_none() && required_time.is_some() {
+                height_allowed = false;
+            }
+            time = time.max(required_time);
+            height = height.max(required_height);
+        }
+
+        match (height_allowed, height, time_allowed, time) {
+            (true, Some(height), _, _) => Ok(height),
+            (_, _, true, Some(time)) => Ok(time),
+            (false, _, false, _) => Err("Inputs require incompatible locktime types".to_string()),
+            _ => match self.global.get(PSBT_GLOBAL_FALLBACK_LOCKTIME) {
+                Some(value) => read_u32_value(value, "Fallback locktime"),
+                None => Ok(0),
+            },
+        }
+    }
+
+    pub fn add_global_xpub(&mut self, xpub: &[u8; 78], fingerprint: [u8; 4], path: &[u32]) {
+        self.global.insert(PSBT_GLOBAL_XPUB, xpub, encode_key_source(fingerprint, path));
+    }
+
+    // Updater role.
+    pub fn set_witness_utxo(&mut self, index: usize, output: &TxOutput) -> Result<(), String> {
+        self.input_mut(index)?.insert(PSBT_IN_WITNESS_UTXO, &[], output.serialize());
+        Ok(())
+    }
+
+    pub fn set_non_witness_utxo(&mut self, index: usize, prev_tx: &Transaction) -> Result<(), String> {
+        let utxo_ref = self.unsigned_tx()?.inputs
+            .get(index)
+            .map(|input| input.utxo_ref)
+            .ok_or_else(|| format!("Input index {index} is out of range"))?;
+        if generate_txid(prev_tx) != utxo_ref.txid {
+            return Err(format!("Previous transaction does not match input {index}"));
+        }
+        self.input_mut(index)?.insert(PSBT_IN_NON_WITNESS_UTXO, &[], prev_tx.serialize());
+        Ok(())
+    }
+
+    pub fn add_input_bip32_derivation(
+        &mut self,
+        index: usize,
+        pubkey: &[u8; 33],
+        fingerprint: [u8; 4],
+        path: &[u32],
+    ) -> Result<(), String> {
+        let value = encode_key_source(fingerprint, path);
+        self.input_mut(index)?.insert(PSBT_IN_BIP32_DERIVATION, pubkey, value);
+        Ok(())
+    }
+
+    pub fn add_output_bip32_derivation(
+        &mut self,
+        index: usize,
+        pubkey: &[u8; 33],
+        fingerprint: [u8; 4],
+        path: &[u32],
+    ) -> Result<(), String> {
+        let output = self.outputs.get_mut(index)
+            .ok_or_else(|| format!("Output index {index} is out of range"))?;
+        output.insert(PSBT_OUT_BIP32_DERIVATION, pubkey, encode_key_source(fingerprint, path));
+        Ok(())
+    }
+
+    // Fills in witness UTXOs for every input whose coin is known to the database.
+    pub fn update_from_utxo_db<S: UtxoStore>(&mut self, utxo_db: &S) -> Result<usize, String> {
+        let tx = self.unsigned_tx()?;
+        let mut updated = 0;
+        for (index, input) in tx.inputs.iter().enumerate() {
+            if let Some(coin) = utxo_db.get(&input.utxo_ref)? {
+                self.set_witness_utxo(index, &coin.output)?;
+                updated += 1;
+            }
+        }
+        Ok(updated)
+    }
+
+    pub fn spent_output(&self, index: usize) -> Result<TxOutput, String> {
+        let input = self.inputs.get(index)
+            .ok_or_else(|| format!("Input index {index} is out of range"))?;
+        if let Some(raw) = input.get(PSBT_IN_WITNESS_UTXO) {
+            let mut reader = ByteReader::new(raw);
+            return TxOutput::deserialize_from(&mut reader);
+        }
+        if let Some(raw) = input.get(PSBT_IN_NON_WITNESS_UTXO) {
+            let prev_tx = Transaction::deserialize(raw)?;
+            let vout = self.unsigned_tx()?.inputs[index].utxo_ref.vout as usize;
+            return prev_tx.outputs.get(vout)
+                .cloned()
+                .ok_or_else(|| format!("Previous transaction has no output {vout}"));
+        }
+        Err(format!("Input {index} has no UTXO information"))
+    }
+
+    // Signer role: signs every P2WPKH or P2PKH input that pays to the key and returns the
+    // number of inputs signed.
+    pub fn sign(&mut self, secret_key: &SecretKey) -> Result<usize, String> {
+        let secp = Secp256k1::new();
+        let pubkey = PublicKey::from_secret_key(&secp, secret_key).serialize();
+        let pubkey_hash = script::hash160(&pubkey);
+        let tx = self.unsigned_tx()?;
+
+        let mut signed = 0;
+        for index in 0..self.inputs.len() {
+            if self.inputs[index].contains(PSBT_IN_FINAL_SCRIPTSIG)
+                || self.inputs[index].contains(PSBT_IN_FINAL_SCRIPTWITNESS)
+            {
+                continue;
+            }
+            let Ok(spent) = self.spent_output(index) else {
+                continue;
+            };
+
+            let sighash_type = match self.inputs[index].get(PSBT_IN_SIGHASH_TYPE) {
+                Some(value) => read_u32_value(value, "Sighash type")?,
+                None => SIGHASH_ALL,
+            };
+            if sighash_type != SIGHASH_ALL {
+                return Err(format!("Input {index} requests unsupported sighash type {sighash_type}"));
+            }
+
+            let script_code = script::p2pkh(&pubkey_hash);
+            let sighash = if script::p2wpkh_hash(&spent.script_pubkey) == Some(pubkey_hash) {
+                segwit_v0_sighash(&tx, index, &script_code, spent.amount, sighash_type)
+            } else if script::p2pkh_hash(&spent.script_pubkey) == Some(pubkey_hash) {
+                if !self.inputs[index].contains(PSBT_IN_NON_WITNESS_UTXO) {
+                    return Err(format!("Input {index} spends a legacy output without its previous transaction"));
+                }
+                legacy_sighash(&tx, index, &script_code, sighash_type)
+            } else {
+                continue;
+            };
+
+            let signature = secp.sign_ecdsa(Message::from_digest(sighash), secret_key);
+            let mut value = signature.serialize_der().to_vec();
+            value.push(sighash_type as u8);
+            self.inputs[index].insert(PSBT_IN_PARTIAL_SIG, &pubkey, value);
+            signed += 1;
+        }
+        Ok(signed)
+    }
+
+    // Combiner role.
+    pub fn combine(&mut self, other: &Psbt) -> Result<(), String> {
+        if self.version()? != other.version()? {
+            return Err("Cannot combine PSBTs of different versions".to_string());
+        }
+        if self.unsigned_tx()? != other.unsigned_tx()? {
+            return Err("Cannot combine PSBTs for different transactions".to_string());
+        }
+        self.global.merge(&other.global);
+        for (mine, theirs) in self.inputs.iter_mut().zip(&other.inputs) {
+            mine.merge(theirs);
+        }
+        for (mine, theirs) in self.outputs.iter_mut().zip(&other.outputs) {
+            mine.merge(theirs);
+        }
+        Ok(())
+    }
+
+    // Finalizer role: turns partial signatures into final scriptSigs and witnesses.
+    pub fn finalize(&mut self) -> Result<(), String> {
+        for index in 0..self.inputs.len() {
+            if self.inputs[index].contains(PSBT_IN_FINAL_SCRIPTSIG)
+                || self.inputs[index].contains(PSBT_IN_FINAL_SCRIPTWITNESS)
+            {
+                continue;
+            }
+
+            let spent = self.spent_output(index)?;
+            let input = &mut self.inputs[index];
+            let (pubkey_hash, is_witness) = if let Some(hash) = script::p2wpkh_hash(&spent.script_pubkey) {
+                (hash, true)
+            } else if let Some(hash) = script::p2pkh_hash(&spent.script_pubkey) {
+                (hash, false)
+            } else {
+                return Err(format!("Input {index} spends an unsupported script type"));
+            };
+
+            let (pubkey, signature) = input.keys_of_type(PSBT_IN_PARTIAL_SIG)
+                .find(|(pubkey, _)| script::hash160(pubkey) == pubkey_hash)
+                .map(|(pubkey, signature)| (pubkey.to_vec(), signature.clone()))
+                .ok_or_else(|| format!("Input {index} has no signature for its key"))?;
+
+            if is_witness {
+                let mut witness = Vec::new();
+                write_compact_size(&mut witness, 2);
+                write_var_bytes(&mut witness, &signature);
+                write_var_bytes(&mut witness, &pubkey);
+                input.insert(PSBT_IN_FINAL_SCRIPTWITNESS, &[], witness);
+            } else {
+                let mut script_sig = Vec::new();
+                script::push_data(&mut script_sig, &signature);
+                script::push_data(&mut script_sig, &pubkey);
+                input.insert(PSBT_IN_FINAL_SCRIPTSIG, &[], script_sig);
+            }
+            for key_type in PRE_FINAL_INPUT_KEYS {
+                input.remove_type(key_type);
+            }
+        }
+        Ok(())
+    }
+
+    // Extractor role.
+    pub fn extract_tx(&self) -> Result<Transaction, String> {
+        let mut tx = self.unsigned_tx()?;
+        for (index, (tx_input, input)) in tx.inputs.iter_mut().zip(&self.inputs).enumerate() {
+            let script_sig = input.get(PSBT_IN_FINAL_SCRIPTSIG);
+            let witness = input.get(PSBT_IN_FINAL_SCRIPTWITNESS);
+            if script_sig.is_none() && witness.is_none() {
+                return Err(format!("Input {index} is not finalized"));
+            }
+            if let Some(script_sig) = script_sig {
+                tx_input.script_sig = script_sig.clone();
+            }
+            if let Some(witness) = witness {
+                let mut reader = ByteReader::new(witness);
+                let count = reader.read_compact_size()?;
+                for _ in 0..count {
+                    tx_input.witness.push(reader.read_var_bytes()?.to_vec());
+                }
+            }
+        }
+        Ok(tx)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = PSBT_MAGIC.to_vec();
+        self.global.serialize_into(&mut bytes);
+        for input in &self.inputs {
+            input.serialize_into(&mut bytes);
+        }
+        for output in &self.outputs {
+            output.serialize_into(&mut bytes);
+        }
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = ByteReader::new(bytes);
+        if reader.read_bytes(PSBT_MAGIC.len())? != PSBT_MAGIC {
+            return Err("Missing PSBT magic bytes".to_string());
+        }
+
+        let global = PsbtMap::deserialize_from(&mut reader)?;
+        let mut psbt = Psbt { global, inputs: Vec::new(), outputs: Vec::new() };
+        let (input_count, output_count) = psbt.map_counts()?;
+        for _ in 0..input_count {
+            psbt.inputs.push(PsbtMap::deserialize_from(&mut reader)?);
+        }
+        for _ in 0..output_count {
+            psbt.outputs.push(PsbtMap::deserialize_from(&mut reader)?);
+        }
+        if !reader.is_empty() {
+            return Err(format!("{} trailing bytes after PSBT", reader.remaining()));
+        }
+
+        psbt.check_fields()?;
+        Ok(psbt)
+    }
+
+    pub fn to_base64(&self) -> String {
+        base64_encode(&self.serialize())
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self, String> {
+        Psbt::deserialize(&base64_decode(encoded)?)
+    }
+
+    fn input_mut(&mut self, index: usize) -> Result<&mut PsbtMap, String> {
+        self.inputs.get_mut(index)
+            .ok_or_else(|| format!("Input index {index} is out of range"))
+    }
+
+    fn map_counts(&self) -> Result<(usize, usize), String> {
+        match self.version()? {
+            0 => {
+                let tx = self.unsigned_tx()?;
+                Ok((tx.inputs.len(), tx.outputs.len()))
+            }
+            2 => {
+                let inputs = self.global.get(PSBT_GLOBAL_INPUT_COUNT)
+                    .ok_or("PSBTv2 is missing the input count")?;
+                let outputs = self.global.get(PSBT_GLOBAL_OUTPUT_COUNT)
+                    .ok_or("PSBTv2 is missing the output count")?;
+                Ok((
+                    read_compact_value(inputs, "Input count")?,
+                    read_compact_value(outputs, "Output count")?,
+                ))
+            }
+            version => Err(format!("Unsupported PSBT version {version}")),
+        }
+    }
+
+    fn check_fields(&self) -> Result<(), String> {
+        if self.version()? == 0 {
+            check_unsigned(&self.unsigned_tx()?)?;
+            if V2_ONLY_GLOBAL_KEYS.iter().any(|key| self.global.contains(*key))
+                || self.inputs.iter().any(|input| V2_ONLY_INPUT_KEYS.iter().any(|key| input.contains(*key)))
+                || self.outputs.iter().any(|output| V2_ONLY_OUTPUT_KEYS.iter().any(|key| output.contains(*key)))
+            {
+                return Err("PSBTv0 contains PSBTv2-only fields".to_string());
+            }
+        } else {
+            if self.global.contains(PSBT_GLOBAL_UNSIGNED_TX) {
+                return Err("PSBTv2 must not contain an unsigned transaction".to_string());
+            }
+            // Building the transaction checks that every required per-map field is present.
+            self.unsigned_tx()?;
+        }
+
+        for (index, input) in self.inputs.iter().enumerate() {
+            if let Some(raw) = input.get(PSBT_IN_NON_WITNESS_UTXO) {
+                let prev_tx = Transaction::deserialize(raw)?;
+                if generate_txid(&prev_tx) != self.unsigned_tx()?.inputs[index].utxo_ref.txid {
+                    return Err(format!("Input {index} previous transaction does not match its outpoint"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_unsigned(tx: &Transaction) -> Result<(), String> {
+    if tx.inputs.iter().any(|input| !input.script_sig.is_empty() || !input.witness.is_empty()) {
+        return Err("PSBT transactions must have empty scriptSigs and witnesses".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::coin::Coin;
+    use crate::transaction::database::UtxoDatabase;
+    use crate::transaction::transaction::Wallet;
+    use secp256k1::ecdsa::Signature;
+
+    fn test_key(byte: u8) -> (SecretKey, [u8; 33]) {
+        let secret_key = SecretKey::from_byte_array([byte; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&Secp256k1::new(), &secret_key).serialize();
+        (secret_key, pubkey)
+    }
+
+    fn funded_tx(script_pubkey: Vec<u8>) -> (UtxoDatabase, Transaction) {
+        let mut utxo_db = UtxoDatabase::new();
+        let utxo_ref = UtxoRef { txid: [7u8; 32], vout: 1 };
+        utxo_db.add_utxo(utxo_ref, Coin::new(TxOutput::new(script_pubkey, 100_000), 1, false));
+        let tx = Transaction {
+            version: 2,
+            inputs: vec![TxInput::new(utxo_ref)],
+            outputs: vec![TxOutput::p2wpkh(&[9u8; 20], 90_000)],
+            locktime: 0,
+        };
+        (utxo_db, tx)
+    }
+
+    #[test]
+    fn test_watch_only_to_air_gapped_signer_flow() {
+        let (secret_key, pubkey) = test_key(0x11);
+        let wallet = Wallet::new(script::hash160(&pubkey));
+        let mut utxo_db = UtxoDatabase::new();
+        utxo_db.add_utxo(
+            UtxoRef { txid: [3u8; 32], vout: 0 },
+            Coin::new(TxOutput::new(wallet.script_pubkey(), 50_000), 1, false),
+        );
+
+        // Online watch-only host: create and update.
+        let tx = wallet.create_transaction(&utxo_db, [8u8; 20], 20_000, 1).unwrap();
+        let mut psbt = Psbt::new_v0(&tx).unwrap();
+        assert_eq!(psbt.update_from_utxo_db(&utxo_db).unwrap(), 1);
+        psbt.add_input_bip32_derivation(0, &pubkey, [0xde, 0xad, 0xbe, 0xef], &[0x8000_0054, 0, 5]).unwrap();
+        let exported = psbt.to_base64();
+
+        // Air-gapped signer.
+        let mut offline = Psbt::from_base64(&exported).unwrap();
+        assert_eq!(offline.sign(&secret_key).unwrap(), 1);
+        let signed = offline.to_base64();
+
+        // Back online: combine, finalize and extract.
+        psbt.combine(&Psbt::from_base64(&signed).unwrap()).unwrap();
+        psbt.finalize().unwrap();
+        assert!(!psbt.inputs[0].contains(PSBT_IN_PARTIAL_SIG));
+        assert!(!psbt.inputs[0].contains(PSBT_IN_BIP32_DERIVATION));
+        let final_tx = psbt.extract_tx().unwrap();
+
+        assert_eq!(generate_txid(&final_tx), generate_txid(&tx));
+        let witness = &final_tx.inputs[0].witness;
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness[1], pubkey.to_vec());
+
+        let sighash = segwit_v0_sighash(&tx, 0, &script::p2pkh(&wallet.address), 50_000, SIGHASH_ALL);
+        let signature = Signature::from_der(&witness[0][..witness[0].len() - 1]).unwrap();
+        let secp = Secp256k1::new();
+        let pubkey = PublicKey::from_slice(&pubkey).unwrap();
+        assert!(secp.verify_ecdsa(Message::from_digest(sighash), &signature, &pubkey).is_ok());
+    }
+
+    #[test]
+    fn test_legacy_input_requires_previous_transaction() {
+        let (secret_key, pubkey) = test_key(0x22);
+        let prev_tx = Transaction {
+            version: 1,
+            inputs: vec![TxInput::new(UtxoRef { txid: [1u8; 32], vout: 0 })],
+            outputs: vec![TxOutput::new(script::p2pkh(&script::hash160(&pubkey)), 40_000)],
+            locktime: 0,
+        };
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![TxInput::new(UtxoRef { txid: generate_txid(&prev_tx), vout: 0 })],
+            outputs: vec![TxOutput::p2wpkh(&[9u8; 20], 39_000)],
+            locktime: 0,
+        };
+
+        let mut psbt = Psbt::new_v0(&tx).unwrap();
+        psbt.set_witness_utxo(0, &prev_tx.outputs[0]).unwrap();
+        assert!(psbt.sign(&secret_key).is_err());
+
+        let mut psbt = Psbt::new_v0(&tx).unwrap();
+        assert!(psbt.set_non_witness_utxo(0, &tx).is_err());
+        psbt.set_non_witness_utxo(0, &prev_tx).unwrap();
+        assert_eq!(psbt.sign(&secret_key).unwrap(), 1);
+        psbt.finalize().unwrap();
+
+        let final_tx = psbt.extract_tx().unwrap();
+        assert!(final_tx.inputs[0].witness.is_empty());
+        assert_eq!(final_tx.inputs[0].script_sig.last(), Some(&pubkey[32]));
+    }
+
+    #[test]
+    fn test_v2_round_trip() {
+        let (utxo_db, tx) = funded_tx(script::p2wpkh(&[5u8; 20]));
+        let mut psbt = Psbt::new_v2(&tx).unwrap();
+        psbt.update_from_utxo_db(&utxo_db).unwrap();
+
+        let decoded = Psbt::deserialize(&psbt.serialize()).unwrap();
+
+        assert_eq!(decoded, psbt);
+        assert_eq!(decoded.version().unwrap(), 2);
+        assert_eq!(decoded.unsigned_tx().unwrap(), tx);
+        assert!(!decoded.global.contains(PSBT_GLOBAL_UNSIGNED_TX));
+    }
+
+    #[test]
+    fn test_v2_required_locktime() {
+        let (_, tx) = funded_tx(script::p2wpkh(&[5u8; 20]));
+        let mut psbt = Psbt::new_v2(&tx).unwrap();
+        psbt.inputs[0].insert(PSBT_IN_REQUIRED_HEIGHT_LOCKTIME, &[], 800_000u32.to_le_bytes().to_vec());
+
+        assert_eq!(psbt.unsigned_tx().unwrap().locktime, 800_000);
+    }
+
+    #[test]
+    fn test_rejects_mixed_versions() {
+        let (_, tx) = funded_tx(script::p2wpkh(&[5u8; 20]));
+
+        let mut v0 = Psbt::new_v0(&tx).unwrap();
+        v0.outputs[0].insert(PSBT_OUT_AMOUNT, &[], 1u64.to_le_bytes().to_vec());
+        assert!(Psbt::deserialize(&v0.serialize()).is_err());
+
+        let mut v2 = Psbt::new_v2(&tx).unwrap();
+        v2.global.insert(PSBT_GLOBAL_UNSIGNED_TX, &[], tx.serialize());
+        assert!(Psbt::deserialize(&v2.serialize()).is_err());
+
+        let v0 = Psbt::new_v0(&tx).unwrap();
+        let mut v2 = Psbt::new_v2(&tx).unwrap();
+        assert!(v2.combine(&v0).is_err());
+    }
+
+    #[test]
+    fn test_unknown_keys_pass_through() {
+        let (_, tx) = funded_tx(script::p2wpkh(&[5u8; 20]));
+        let mut psbt = Psbt::new_v0(&tx).unwrap();
+        psbt.global.insert(0xfc, b"\x05acme\x01", vec![1, 2, 3]);
+        psbt.inputs[0].insert(0x99, &[0xaa], vec![4, 5]);
+        psbt.outputs[0].insert(0x42, &[], vec![6]);
+
+        let decoded = Psbt::from_base64(&psbt.to_base64()).unwrap();
+
+        assert_eq!(decoded.global.get_keyed(0xfc, b"\x05acme\x01"), Some(&vec![1, 2, 3]));
+        assert_eq!(decoded.inputs[0].get_keyed(0x99, &[0xaa]), Some(&vec![4, 5]));
+        assert_eq!(decoded.outputs[0].get(0x42), Some(&vec![6]));
+        assert_eq!(decoded.serialize(), psbt.serialize());
+    }
+
+    #[test]
+    fn test_extract_requires_finalized_inputs() {
+        let (_, tx) = funded_tx(script::p2wpkh(&[5u8; 20]));
+        let psbt = Psbt::new_v0(&tx).unwrap();
+
+        assert!(psbt.extract_tx().unwrap_err().contains("not finalized"));
+    }
+
+    #[test]
+    fn test_bip174_valid_vector_round_trips() {
+        // Taken from the BIP174 valid test vectors: one input carrying a non-witness UTXO.
+        let encoded = "cHNidP8BAHUCAAAAASaBcTce3/KF6Tet7qSze3gADAVmy7OtZGQXE8pCFxv2AAAAAAD+////AtPf9QUAAAAAGXapFNDFmQPFusKGh2DpD9UhpGZap2UgiKwA4fUFAAAAABepFDVF5uM7gyxHBQ8k0+65PJwDlIvHh7MuEwAAAQD9pQEBAAAAAAECiaPHHqtNIOA3G7ukzGmPopXJRjr6Ljl/hTPMti+VZ+UBAAAAFxYAFL4Y0VKpsBIDna89p95PUzSe7LmF/////4b4qkOnHf8USIk6UwpyN+9rRgi7st0tAXHmOuxqSJC0AQAAABcWABT+Pp7xp0XpdNkCxDVZQ6vLNL1TU/////8CAMLrCwAAAAAZdqkUhc/xCX/Z4Ai7NK9wnGIZeziXikiIrHL++E4sAAAAF6kUM5cluiHv1irHU6m80GfWx6ajnQWHAkcwRAIgJxK+IuAnDzlPVoMR3HyppolwuAJf3TskAinwf4pfOiQCIAGLONfc0xTnNMkna9b7QPZzMlvEuqFEyADS8vAtsnZcASED0uFWdJQbrUqZY3LLh+GFbTZSYG2YVi/jnF6efkE/IQUCSDBFAiEA0SuFLYXc2WHS9fSrZgZU327tzHlMDDPOXMMJ/7X85Y0CIGczio4OFyXBl/saiK9Z9R5E5CVbIBZ8hoQDHAXR8lkqASECI7cr7vCWXRC+B3jv7NYfysb3mk6haTkzgHNEZPhPKrMAAAAAAAAA";
+
+        let psbt = Psbt::from_base64(encoded).unwrap();
+
+        assert_eq!(psbt.version().unwrap(), 0);
+        assert_eq!(psbt.inputs.len(), 1);
+        assert_eq!(psbt.outputs.len(), 2);
+        assert!(psbt.inputs[0].contains(PSBT_IN_NON_WITNESS_UTXO));
+        assert_eq!(psbt.to_base64(), encoded);
+    }
+}
@@ -0,0 +1,152 @@
+use std::path::Path;
+
+use rocksdb::{ColumnFamily, IteratorMode, Options, WriteBatch, WriteOptions, DB};
+
+use crate::transaction::coin::Coin;
+use crate::transaction::store::{
+    decode_coin, decode_utxo_key, encode_coin, encode_utxo_key, BatchOp, UtxoBatch, UtxoStore,
+};
+use crate::transaction::transaction::UtxoRef;
+
+const COINS_CF: &str = "coins";
+const METADATA_CF: &str = "metadata";
+const BEST_BLOCK_KEY: &[u8] = b"best_block";
+
+// Coins live in their own column family; the best-block marker sits in `metadata` and is
+// always written in the same atomic batch as the coin changes it describes, so after a
+// crash the marker never points past the coins that are on disk.
+pub struct RocksUtxoDatabase {
+    db: DB,
+}
+
+impl RocksUtxoDatabase {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        let db = DB::open_cf(&options, path, [COINS_CF, METADATA_CF])
+            .map_err(|e| format!("Failed to open RocksDB at {}: {e}", path.display()))?;
+        Ok(RocksUtxoDatabase { db })
+    }
+
+    fn cf(&self, name: &str) -> Result<&ColumnFamily, String> {
+        self.db.cf_handle(name).ok_or_else(|| format!("Missing column family '{name}'"))
+    }
+
+    fn write(&self, batch: WriteBatch) -> Result<(), String> {
+        let mut options = WriteOptions::default();
+        options.set_sync(true);
+        self.db.write_opt(batch, &options).map_err(|e| format!("RocksDB write failed: {e}"))
+    }
+}
+
+impl UtxoStore for RocksUtxoDatabase {
+    fn get(&self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        self.db.get_cf(self.cf(COINS_CF)?, encode_utxo_key(utxo_ref))
+            .map_err(|e| format!("RocksDB read failed: {e}"))?
+            .map(|bytes| decode_coin(&bytes))
+            .transpose()
+    }
+
+    fn add(&mut self, utxo_ref: UtxoRef, coin: Coin) -> Result<(), String> {
+        let mut batch = UtxoBatch::new();
+        batch.add(utxo_ref, coin);
+        self.apply_batch(batch)
+    }
+
+    fn remove(&mut self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        let existing = self.get(utxo_ref)?;
+        if existing.is_some() {
+            let mut batch = UtxoBatch::new();
+            batch.remove(*utxo_ref);
+            self.apply_batch(batch)?;
+        }
+        Ok(existing)
+    }
+
+    fn apply_batch(&mut self, batch: UtxoBatch) -> Result<(), String> {
+        let coins = self.cf(COINS_CF)?;
+        let mut write_batch = WriteBatch::default();
+        for op in &batch.ops {
+            match op {
+                BatchOp::Add(utxo_ref, coin) => {
+                    write_batch.put_cf(coins, encode_utxo_key(utxo_ref), encode_coin(coin)?)
+                }
+                BatchOp::Remove(utxo_ref) => write_batch.delete_cf(coins, encode_utxo_key(utxo_ref)),
+            }
+        }
+        if let Some(block_hash) = batch.best_block {
+            write_batch.put_cf(self.cf(METADATA_CF)?, BEST_BLOCK_KEY, block_hash);
+        }
+        self.write(write_batch)
+    }
+
+    fn utxos(&self) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        self.db.iterator_cf(self.cf(COINS_CF)?, IteratorMode::Start)
+            .map(|entry| {
+                let (key, value) = entry.map_err(|e| format!("RocksDB iteration failed: {e}"))?;
+                Ok((decode_utxo_key(&key)?, decode_coin(&value)?))
+            })
+            .collect()
+    }
+
+    fn is_empty(&self) -> Result<bool, String> {
+        match self.db.iterator_cf(self.cf(COINS_CF)?, IteratorMode::Start).next() {
+            Some(entry) => entry.map(|_| false).map_err(|e| format!("RocksDB iteration failed: {e}")),
+            None => Ok(true),
+        }
+    }
+
+    fn best_block(&self) -> Result<Option<[u8; 32]>, String> {
+        let value = self.db.get_cf(self.cf(METADATA_CF)?, BEST_BLOCK_KEY)
+            .map_err(|e| format!("RocksDB read failed: {e}"))?;
+        value
+            .map(|bytes| {
+                bytes.as_slice().try_into()
+                    .map_err(|_| format!("Corrupt best block marker of {} bytes", bytes.len()))
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::store::conformance;
+    use crate::transaction::transaction::TxOutput;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rust-coin-rocks-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    #[test]
+    fn test_conformance() {
+        let mut run = 0;
+        conformance::run(|| {
+            run += 1;
+            RocksUtxoDatabase::open(&temp_path(&format!("conformance-{run}"))).unwrap()
+        });
+    }
+
+    #[test]
+    fn test_coins_survive_reopen() {
+        let path = temp_path("reopen");
+        let utxo_ref = UtxoRef { txid: [5u8; 32], vout: 2 };
+        let coin = Coin::new(TxOutput::p2wpkh(&[5u8; 20], 12_345), 840_000, true);
+        {
+            let mut store = RocksUtxoDatabase::open(&path).unwrap();
+            let mut batch = UtxoBatch::new();
+            batch.add(utxo_ref, coin.clone());
+            batch.set_best_block([1u8; 32]);
+            store.apply_batch(batch).unwrap();
+        }
+
+        let store = RocksUtxoDatabase::open(&path).unwrap();
+        assert_eq!(store.get(&utxo_ref).unwrap(), Some(coin));
+        assert_eq!(store.best_block().unwrap(), Some([1u8; 32]));
+        drop(store);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use bitcoin_hashes::hash160;
+
+pub const OP_0: u8 = 0x00;
+pub const OP_PUSHDATA1: u8 = 0x4c;
+pub const OP_PUSHDATA2: u8 = 0x4d;
+pub const OP_PUSHDATA4: u8 = 0x4e;
+pub const OP_1: u8 = 0x51;
+pub const OP_16: u8 = 0x60;
+pub const OP_RETURN: u8 = 0x6a;
+pub const OP_DUP: u8 = 0x76;
+pub const OP_EQUAL: u8 = 0x87;
+pub const OP_EQUALVERIFY: u8 = 0x88;
+pub const OP_HASH160: u8 = 0xa9;
+pub const OP_CHECKSIG: u8 = 0xac;
+pub const OP_CHECKSIGVERIFY: u8 = 0xad;
+pub const OP_CHECKMULTISIG: u8 = 0xae;
+pub const OP_CHECKMULTISIGVERIFY: u8 = 0xaf;
+
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    hash160::Hash::hash(data).to_byte_array()
+}
+
+pub fn p2pkh(pubkey_hash: &[u8; 20]) -> Vec<u8> {
+    let mut script = vec![OP_DUP, OP_HASH160, 20];
+    script.extend_from_slice(pubkey_hash);
+    script.extend_from_slice(&[OP_EQUALVERIFY, OP_CHECKSIG]);
+    script
+}
+
+pub fn p2sh(script_hash: &[u8; 20]) -> Vec<u8> {
+    let mut script = vec![OP_HASH160, 20];
+    script.extend_from_slice(script_hash);
+    script.push(OP_EQUAL);
+    script
+}
+
+pub fn p2wpkh(pubkey_hash: &[u8; 20]) -> Vec<u8> {
+    let mut script = vec![OP_0, 20];
+    script.extend_from_slice(pubkey_hash);
+    script
+}
+
+pub fn p2pkh_hash(script: &[u8]) -> Option<[u8; 20]> {
+    if script.len() == 25
+        && script[..3] == [OP_DUP, OP_HASH160, 20]
+        && script[23..] == [OP_EQUALVERIFY, OP_CHECKSIG]
+    {
+        script[3..23].try_into().ok()
+    } else {
+        None
+    }
+}
+
+pub fn p2sh_hash(script: &[u8]) -> Option<[u8; 20]> {
+    if script.len() == 23 && script[..2] == [OP_HASH160, 20] && script[22] == OP_EQUAL {
+        script[2..22].try_into().ok()
+    } else {
+        None
+    }
+}
+
+pub fn p2wpkh_hash(script: &[u8]) -> Option<[u8; 20]> {
+    if script.len() == 22 && script[..2] == [OP_0, 20] {
+        script[2..].try_into().ok()
+    } else {
+        None
+    }
+}
+
+// Outputs that no input can ever satisfy, so they never need to enter the UTXO set.
+pub fn is_unspendable(script: &[u8]) -> bool {
+    script.first() == Some(&OP_RETURN) || script.len() > MAX_SCRIPT_SIZE
+}
+
+pub fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    match data.len() {
+        0..=0x4b => script.push(data.len() as u8),
+        0x4c..=0xff => {
+            script.push(OP_PUSHDATA1);
+            script.push(data.len() as u8);
+        }
+        _ => {
+            script.push(OP_PUSHDATA2);
+            script.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        }
+    }
+    script.extend_from_slice(data);
+}
+
+// Walks a script one opcode at a time, yielding each opcode with the data it pushes (empty
+// for anything but a push). A push running past the end of the script yields an error and
+// ends the walk.
+pub fn instructions(script: &[u8]) -> Instructions<'_> {
+    Instructions { script, position: 0 }
+}
+
+pub struct Instructions<'a> {
+    script: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = Result<(u8, &'a [u8]), String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let opcode = *self.script.get(self.position)?;
+        self.position += 1;
+        let length_bytes = match opcode {
+            OP_PUSHDATA1 => 1,
+            OP_PUSHDATA2 => 2,
+            OP_PUSHDATA4 => 4,
+            _ => 0,
+        };
+        let length = if opcode < OP_PUSHDATA1 {
+            opcode as usize
+        } else if length_bytes > 0 {
+            let Some(bytes) = self.script.get(self.position..self.position + length_bytes) else {
+                self.position = self.script.len();
+                return Some(Err("Script ends inside a push length".to_string()));
+            };
+            self.position += length_bytes;
+            bytes.iter().rev().fold(0usize, |length, byte| length << 8 | *byte as usize)
+        } else {
+            0
+        };
+        match self.script.get(self.position..self.position.saturating_add(length)) {
+            Some(data) => {
+                self.position += length;
+                Some(Ok((opcode, data)))
+            }
+            None => {
+                self.position = self.script.len();
+                Some(Err(format!("Push of {length} bytes runs past the end of the script")))
+            }
+        }
+    }
+}
+
+pub fn is_push_only(script: &[u8]) -> bool {
+    instructions(script).all(|instruction| matches!(instruction, Ok((opcode, _)) if opcode <= OP_16))
+}
+
+// Signature operations in a script, counted the legacy way: a multisig counts 20 unless
+// `accurate` is set and the key count is given by the opcode just before it. Counting
+// stops at the first malformed push.
+pub fn sigop_count(script: &[u8], accurate: bool) -> usize {
+    let mut count = 0;
+    let mut previous = None;
+    for instruction in instructions(script) {
+        let Ok((opcode, _)) = instruction else { break };
+        match opcode {
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => count += 1,
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => match previous {
+                Some(keys @ OP_1..=OP_16) if accurate => count += (keys - OP_1 + 1) as usize,
+                _ => count += 20,
+            },
+            _ => {}
+        }
+        previous = Some(opcode);
+    }
+    count
+}
@@ -0,0 +1,340 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bitcoin_hashes::sha256;
+
+use crate::transaction::coin::Coin;
+use crate::transaction::encoding::{write_atomically, write_compact_size, write_var_bytes, ByteReader};
+use crate::transaction::script;
+use crate::transaction::store::{
+    decode_utxo_key, encode_utxo_key, resolve_batch, CoinChange, UtxoBatch, UtxoStore,
+};
+use crate::transaction::transaction::UtxoRef;
+
+const INDEX_FILE_MAGIC: &[u8; 8] = b"RCSCRIDX";
+const INDEX_FILE_VERSION: u32 = 1;
+
+pub type ScriptHash = [u8; 32];
+
+pub fn script_hash(script_pubkey: &[u8]) -> ScriptHash {
+    sha256::Hash::hash(script_pubkey).to_byte_array()
+}
+
+// Wraps a store with a scriptPubKey -> outpoints index so wallets can find their coins
+// without scanning the whole UTXO set. The index only changes after the backing store has
+// accepted a write, so the two never disagree.
+pub struct IndexedUtxoStore<S: UtxoStore> {
+    base: S,
+    index: HashMap<ScriptHash, HashSet<UtxoRef>>,
+    // A saved index file that still matches the coins. It is deleted before the first
+    // write, so a file left behind by a crash is never trusted.
+    saved_index: Option<PathBuf>,
+}
+
+impl<S: UtxoStore> IndexedUtxoStore<S> {
+    pub fn new(base: S) -> Result<Self, String> {
+        let mut store = IndexedUtxoStore { base, index: HashMap::new(), saved_index: None };
+        store.rebuild()?;
+        Ok(store)
+    }
+
+    // Loads a saved index if it was written at the store's current best block and
+    // rebuilds it from the coins otherwise, including when the file is unreadable. A
+    // store without a best block cannot show that its coins are unchanged since the
+    // save, so its index is always rebuilt.
+    pub fn open_with_index(base: S, path: &Path) -> Result<Self, String> {
+        let mut store = IndexedUtxoStore { base, index: HashMap::new(), saved_index: None };
+        let loaded = match fs::read(path) {
+            Ok(bytes) => store.load_index(&bytes).unwrap_or(false),
+            Err(_) => false,
+        };
+        if loaded {
+            store.saved_index = Some(path.to_path_buf());
+        } else {
+            store.rebuild()?;
+        }
+        Ok(store)
+    }
+
+    pub fn base(&self) -> &S {
+        &self.base
+    }
+
+    pub fn rebuild(&mut self) -> Result<(), String> {
+        self.index.clear();
+        for (utxo_ref, coin) in self.base.utxos()? {
+            self.insert(script_hash(&coin.output.script_pubkey), utxo_ref);
+        }
+        Ok(())
+    }
+
+    pub fn indexed_scripts(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn balance_for_script(&self, script_pubkey: &[u8]) -> Result<u64, String> {
+        Ok(self.utxos_for_script(script_pubkey)?
+            .iter()
+            .map(|(_, coin)| coin.output.amount)
+            .sum())
+    }
+
+    // Addresses are P2WPKH pubkey hashes, as used by `Wallet`.
+    pub fn utxos_for_address(&self, address: &[u8; 20]) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        self.utxos_for_script(&script::p2wpkh(address))
+    }
+
+    pub fn balance_for_address(&self, address: &[u8; 20]) -> Result<u64, String> {
+        self.balance_for_script(&script::p2wpkh(address))
+    }
+
+    // The saved index records the best block it matches; it is only trusted again if the
+    // backing store is still at that block and nothing was written since it was saved.
+    pub fn save_index(&mut self, path: &Path) -> Result<(), String> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(INDEX_FILE_MAGIC);
+        bytes.extend_from_slice(&INDEX_FILE_VERSION.to_le_bytes());
+        match self.base.best_block()? {
+            Some(block_hash) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&block_hash);
+            }
+            None => bytes.push(0),
+        }
+        write_compact_size(&mut bytes, self.index.len() as u64);
+        for (hash, outpoints) in &self.index {
+            bytes.extend_from_slice(hash);
+            write_compact_size(&mut bytes, outpoints.len() as u64);
+            for utxo_ref in outpoints {
+                write_var_bytes(&mut bytes, &encode_utxo_key(utxo_ref));
+            }
+        }
+
+        write_atomically(path, &bytes)?;
+        self.saved_index = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    fn invalidate_saved_index(&mut self) -> Result<(), String> {
+        if let Some(path) = self.saved_index.take() {
+            match fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    self.saved_index = Some(path.clone());
+                    return Err(format!("Failed to remove stale index {}: {e}", path.display()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // A partially read index is left for `rebuild` to clear.
+    fn load_index(&mut self, bytes: &[u8]) -> Result<bool, String> {
+        let mut reader = ByteReader::new(bytes);
+        if reader.read_bytes(INDEX_FILE_MAGIC.len())? != INDEX_FILE_MAGIC {
+            return Err("Not a script index file".to_string());
+        }
+        if reader.read_u32()? != INDEX_FILE_VERSION {
+            return Ok(false);
+        }
+        let best_block = match reader.read_u8()? {
+            0 => None,
+            _ => Some(reader.read_array::<32>()?),
+        };
+        if best_block.is_none() || best_block != self.base.best_block()? {
+            return Ok(false);
+        }
+
+        for _ in 0..reader.read_compact_size()? {
+            let hash = reader.read_array()?;
+            for _ in 0..reader.read_compact_size()? {
+                self.insert(hash, decode_utxo_key(reader.read_var_bytes()?)?);
+            }
+        }
+        if !reader.is_empty() {
+            return Err("Trailing bytes in script index file".to_string());
+        }
+        Ok(true)
+    }
+
+    fn insert(&mut self, hash: ScriptHash, utxo_ref: UtxoRef) {
+        self.index.entry(hash).or_default().insert(utxo_ref);
+    }
+
+    fn erase(&mut self, hash: &ScriptHash, utxo_ref: &UtxoRef) {
+        if let Some(outpoints) = self.index.get_mut(hash) {
+            outpoints.remove(utxo_ref);
+            if outpoints.is_empty() {
+                self.index.remove(hash);
+            }
+        }
+    }
+}
+
+impl<S: UtxoStore> UtxoStore for IndexedUtxoStore<S> {
+    fn get(&self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        self.base.get(utxo_ref)
+    }
+
+    fn add(&mut self, utxo_ref: UtxoRef, coin: Coin) -> Result<(), String> {
+        let mut batch = UtxoBatch::new();
+        batch.add(utxo_ref, coin);
+        self.apply_batch(batch)
+    }
+
+    fn remove(&mut self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        let existing = self.base.get(utxo_ref)?;
+        if existing.is_some() {
+            let mut batch = UtxoBatch::new();
+            batch.remove(*utxo_ref);
+            self.apply_batch(batch)?;
+        }
+        Ok(existing)
+    }
+
+    fn apply_batch(&mut self, batch: UtxoBatch) -> Result<(), String> {
+        let changes = resolve_batch(&self.base, &batch)?;
+        self.invalidate_saved_index()?;
+        self.base.apply_batch(batch)?;
+        for change in changes {
+            match change {
+                CoinChange::Added(utxo_ref, coin) => {
+                    self.insert(script_hash(&coin.output.script_pubkey), utxo_ref)
+                }
+                CoinChange::Removed(utxo_ref, coin) => {
+                    self.erase(&script_hash(&coin.output.script_pubkey), &utxo_ref)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn utxos(&self) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        self.base.utxos()
+    }
+
+    fn utxos_for_script(&self, script_pubkey: &[u8]) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        let Some(outpoints) = self.index.get(&script_hash(script_pubkey)) else {
+            return Ok(Vec::new());
+        };
+        let mut utxos = Vec::with_capacity(outpoints.len());
+        for utxo_ref in outpoints {
+            let coin = self.base.get(utxo_ref)?
+                .ok_or_else(|| format!("Script index refers to missing coin {utxo_ref:?}"))?;
+            utxos.push((*utxo_ref, coin));
+        }
+        Ok(utxos)
+    }
+
+    fn is_empty(&self) -> Result<bool, String> {
+        self.base.is_empty()
+    }
+
+    fn best_block(&self) -> Result<Option<[u8; 32]>, String> {
+        self.base.best_block()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::database::UtxoDatabase;
+    use crate::transaction::store::conformance;
+    use crate::transaction::transaction::{TxOutput, Wallet};
+
+    fn coin_for(address: &[u8; 20], amount: u64) -> Coin {
+        Coin::new(TxOutput::p2wpkh(address, amount), 1, false)
+    }
+
+    #[test]
+    fn test_conformance() {
+        conformance::run(|| IndexedUtxoStore::new(UtxoDatabase::new()).unwrap());
+    }
+
+    #[test]
+    fn test_balance_by_address_tracks_batches() {
+        let mut store = IndexedUtxoStore::new(UtxoDatabase::new()).unwrap();
+        let (alice, bob) = ([1u8; 20], [2u8; 20]);
+        let spent = UtxoRef { txid: [1u8; 32], vout: 0 };
+        store.add(spent, coin_for(&alice, 5_000)).unwrap();
+
+        let mut batch = UtxoBatch::new();
+        batch.remove(spent);
+        batch.add(UtxoRef { txid: [2u8; 32], vout: 0 }, coin_for(&alice, 3_000));
+        batch.add(UtxoRef { txid: [2u8; 32], vout: 1 }, coin_for(&bob, 1_500));
+        batch.add(UtxoRef { txid: [2u8; 32], vout: 2 }, coin_for(&bob, 400));
+        batch.remove(UtxoRef { txid: [2u8; 32], vout: 2 });
+        store.apply_batch(batch).unwrap();
+
+        assert_eq!(store.balance_for_address(&alice).unwrap(), 3_000);
+        assert_eq!(store.balance_for_address(&bob).unwrap(), 1_500);
+        assert_eq!(store.utxos_for_address(&[3u8; 20]).unwrap(), vec![]);
+        assert_eq!(store.indexed_scripts(), 2);
+
+        let wallet = Wallet::new(bob);
+        assert_eq!(wallet.spendable_utxos(&store).unwrap(), vec![(UtxoRef { txid: [2u8; 32], vout: 1 }, 1_500)]);
+    }
+
+    #[test]
+    fn test_saved_index_reloads_only_at_same_block() {
+        let path = std::env::temp_dir().join(format!("rust-coin-script-index-{}", std::process::id()));
+        let mut store = IndexedUtxoStore::new(UtxoDatabase::new()).unwrap();
+        let mut batch = UtxoBatch::new();
+        batch.add(UtxoRef { txid: [1u8; 32], vout: 0 }, coin_for(&[1u8; 20], 7_000));
+        batch.set_best_block([4u8; 32]);
+        store.apply_batch(batch).unwrap();
+        store.save_index(&path).unwrap();
+
+        let mut base = UtxoDatabase::new();
+        base.add_utxo(UtxoRef { txid: [1u8; 32], vout: 0 }, coin_for(&[1u8; 20], 7_000));
+        // Without the matching best block the saved file is ignored and the index rebuilt.
+        base.add_utxo(UtxoRef { txid: [2u8; 32], vout: 0 }, coin_for(&[1u8; 20], 1_000));
+        let rebuilt = IndexedUtxoStore::open_with_index(base, &path).unwrap();
+        assert_eq!(rebuilt.balance_for_address(&[1u8; 20]).unwrap(), 8_000);
+
+        let mut base = UtxoDatabase::new();
+        let mut batch = UtxoBatch::new();
+        batch.add(UtxoRef { txid: [1u8; 32], vout: 0 }, coin_for(&[1u8; 20], 7_000));
+        batch.set_best_block([4u8; 32]);
+        base.apply_batch(batch).unwrap();
+        let mut loaded = IndexedUtxoStore::open_with_index(base, &path).unwrap();
+        assert_eq!(loaded.balance_for_address(&[1u8; 20]).unwrap(), 7_000);
+
+        // Writing through the store makes the saved file stale, so it is removed.
+        loaded.add(UtxoRef { txid: [3u8; 32], vout: 0 }, coin_for(&[1u8; 20], 500)).unwrap();
+        assert!(!path.exists());
+        loaded.save_index(&path).unwrap();
+        assert!(path.exists());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_index_saved_without_best_block_is_rebuilt() {
+        let path = std::env::temp_dir().join(format!("rust-coin-script-index-unmarked-{}", std::process::id()));
+        let mut store = IndexedUtxoStore::new(UtxoDatabase::new()).unwrap();
+        store.add(UtxoRef { txid: [1u8; 32], vout: 0 }, coin_for(&[1u8; 20], 7_000)).unwrap();
+        store.save_index(&path).unwrap();
+
+        // Coins written behind the index's back leave the best block unset as before.
+        let mut base = UtxoDatabase::new();
+        base.add_utxo(UtxoRef { txid: [2u8; 32], vout: 0 }, coin_for(&[1u8; 20], 1_000));
+        let store = IndexedUtxoStore::open_with_index(base, &path).unwrap();
+        assert_eq!(store.utxos_for_address(&[1u8; 20]).unwrap().len(), 1);
+        assert_eq!(store.balance_for_address(&[1u8; 20]).unwrap(), 1_000);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_unreadable_index_is_rebuilt() {
+        let path = std::env::temp_dir().join(format!("rust-coin-script-index-corrupt-{}", std::process::id()));
+        fs::write(&path, b"not an index").unwrap();
+        let mut base = UtxoDatabase::new();
+        base.add_utxo(UtxoRef { txid: [1u8; 32], vout: 0 }, coin_for(&[1u8; 20], 7_000));
+
+        let store = IndexedUtxoStore::open_with_index(base, &path).unwrap();
+        assert_eq!(store.balance_for_address(&[1u8; 20]).unwrap(), 7_000);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::sync::{Mutex, RwLock, RwLockWriteGuard};
+
+use crate::transaction::coin::Coin;
+use crate::transaction::store::{BatchOp, UtxoBatch, UtxoStore};
+use crate::transaction::transaction::UtxoRef;
+
+pub const DEFAULT_SHARD_COUNT: usize = 64;
+
+// Coins spread over independently locked shards so validator threads can read and commit
+// through a shared reference. Readers only ever hold one shard's read lock.
+pub struct ShardedUtxoDatabase {
+    shards: Vec<RwLock<HashMap<UtxoRef, Coin>>>,
+    hasher: RandomState,
+    best_block: Mutex<Option<[u8; 32]>>,
+}
+
+impl Default for ShardedUtxoDatabase {
+    fn default() -> Self {
+        ShardedUtxoDatabase::new()
+    }
+}
+
+impl ShardedUtxoDatabase {
+    pub fn new() -> Self {
+        ShardedUtxoDatabase::with_shards(DEFAULT_SHARD_COUNT)
+    }
+
+    pub fn with_shards(shard_count: usize) -> Self {
+        ShardedUtxoDatabase {
+            shards: (0..shard_count.max(1)).map(|_| RwLock::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+            best_block: Mutex::new(None),
+        }
+    }
+
+    fn shard_index(&self, utxo_ref: &UtxoRef) -> usize {
+        (self.hasher.hash_one(utxo_ref) % self.shards.len() as u64) as usize
+    }
+
+    pub fn get_coin(&self, utxo_ref: &UtxoRef) -> Option<Coin> {
+        let shard = self.shards[self.shard_index(utxo_ref)].read().unwrap();
+        shard.get(utxo_ref).cloned()
+    }
+
+    pub fn contains(&self, utxo_ref: &UtxoRef) -> bool {
+        self.shards[self.shard_index(utxo_ref)].read().unwrap().contains_key(utxo_ref)
+    }
+
+    // Counts shard by shard, so concurrent commits may be partly included.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.read().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn best_block_hash(&self) -> Option<[u8; 32]> {
+        *self.best_block.lock().unwrap()
+    }
+
+    // Applies a batch atomically with respect to every other commit. The batch is refused,
+    // with nothing applied, if it spends a coin that is not there (someone else spent it
+    // first) or creates a coin that already exists.
+    pub fn commit(&self, batch: UtxoBatch) -> Result<(), String> {
+        self.write_batch(batch, true)
+    }
+
+    // All touched shards are write-locked in index order, which rules out deadlock between
+    // overlapping batches. Without `strict` the batch has the permissive `UtxoStore`
+    // semantics and always applies.
+    fn write_batch(&self, batch: UtxoBatch, strict: bool) -> Result<(), String> {
+        let mut locked: BTreeMap<usize, RwLockWriteGuard<'_, HashMap<UtxoRef, Coin>>> = BTreeMap::new();
+        let mut indices: Vec<usize> = batch.ops.iter()
+            .map(|op| match op {
+                BatchOp::Add(utxo_ref, _) | BatchOp::Remove(utxo_ref) => self.shard_index(utxo_ref),
+            })
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        for index in indices {
+            locked.insert(index, self.shards[index].write().unwrap());
+        }
+
+        if strict {
+            let mut pending: HashMap<UtxoRef, bool> = HashMap::new();
+            for op in &batch.ops {
+                match op {
+                    BatchOp::Add(utxo_ref, _) => {
+                        let exists = pending.get(utxo_ref).copied()
+                            .unwrap_or_else(|| locked[&self.shard_index(utxo_ref)].contains_key(utxo_ref));
+                        if exists {
+                            return Err(format!("Conflict: coin {utxo_ref:?} already exists"));
+                        }
+                        pending.insert(*utxo_ref, true);
+                    }
+                    BatchOp::Remove(utxo_ref) => {
+                        let exists = pending.get(utxo_ref).copied()
+                            .unwrap_or_else(|| locked[&self.shard_index(utxo_ref)].contains_key(utxo_ref));
+                        if !exists {
+                            return Err(format!("Conflict: coin {utxo_ref:?} is already spent or unknown"));
+                        }
+                        pending.insert(*utxo_ref, false);
+                    }
+                }
+            }
+        }
+
+        for op in batch.ops {
+            match op {
+                BatchOp::Add(utxo_ref, coin) => {
+                    let index = self.shard_index(&utxo_ref);
+                    locked.get_mut(&index).unwrap().insert(utxo_ref, coin);
+                }
+                BatchOp::Remove(utxo_ref) => {
+                    let index = self.shard_index(&utxo_ref);
+                    locked.get_mut(&index).unwrap().remove(&utxo_ref);
+                }
+            }
+        }
+        if let Some(block_hash) = batch.best_block {
+            *self.best_block.lock().unwrap() = Some(block_hash);
+        }
+        Ok(())
+    }
+}
+
+impl UtxoStore for ShardedUtxoDatabase {
+    fn get(&self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        Ok(self.get_coin(utxo_ref))
+    }
+
+    // The trait's writes take `&mut self`, so no `commit` can be running alongside them and
+    // there is nothing to detect conflicts against. They follow the permissive trait
+    // semantics; threads sharing the database go through `commit`.
+    fn add(&mut self, utxo_ref: UtxoRef, coin: Coin) -> Result<(), String> {
+        let index = self.shard_index(&utxo_ref);
+        self.shards[index].write().unwrap().insert(utxo_ref, coin);
+        Ok(())
+    }
+
+    fn remove(&mut self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        let index = self.shard_index(utxo_ref);
+        Ok(self.shards[index].write().unwrap().remove(utxo_ref))
+    }
+
+    fn apply_batch(&mut self, batch: UtxoBatch) -> Result<(), String> {
+        self.write_batch(batch, false)
+    }
+
+    fn utxos(&self) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        Ok(self.shards.iter()
+            .flat_map(|shard| {
+                let shard = shard.read().unwrap();
+                shard.iter().map(|(utxo_ref, coin)| (*utxo_ref, coin.clone())).collect::<Vec<_>>()
+            })
+            .collect())
+    }
+
+    fn is_empty(&self) -> Result<bool, String> {
+        Ok(self.len() == 0)
+    }
+
+    fn best_block(&self) -> Result<Option<[u8; 32]>, String> {
+        Ok(self.best_block_hash())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::transaction::store::conformance;
+    use crate::transaction::transaction::TxOutput;
+
+    fn coin(amount: u64) -> Coin {
+        Coin::new(TxOutput::p2wpkh(&[7u8; 20], amount), 1, false)
+    }
+
+    fn utxo_ref(thread: u8, n: u32) -> UtxoRef {
+        UtxoRef { txid: [thread; 32], vout: n }
+    }
+
+    #[test]
+    fn test_conformance() {
+        conformance::run(ShardedUtxoDatabase::new);
+        conformance::run(|| ShardedUtxoDatabase::with_shards(1));
+    }
+
+    #[test]
+    fn test_conflicting_batch_applies_nothing() {
+        let db = ShardedUtxoDatabase::new();
+        let mut setup = UtxoBatch::new();
+        setup.add(utxo_ref(1, 0), coin(1_000));
+        db.commit(setup).unwrap();
+
+        let mut batch = UtxoBatch::new();
+        batch.remove(utxo_ref(1, 0));
+        batch.add(utxo_ref(2, 0), coin(900));
+        batch.remove(utxo_ref(3, 0));
+        assert!(db.commit(batch).unwrap_err().contains("Conflict"));
+        assert!(db.contains(&utxo_ref(1, 0)));
+        assert!(!db.contains(&utxo_ref(2, 0)));
+
+        let mut duplicate = UtxoBatch::new();
+        duplicate.add(utxo_ref(1, 0), coin(5));
+        assert!(db.commit(duplicate.clone()).is_err());
+        assert_eq!(db.get_coin(&utxo_ref(1, 0)), Some(coin(1_000)));
+
+        // The same batch through the store interface overwrites instead.
+        let mut db = db;
+        db.apply_batch(duplicate).unwrap();
+        assert_eq!(db.get_coin(&utxo_ref(1, 0)), Some(coin(5)));
+    }
+
+    // Every thread walks its own chain of spends while also racing the others for a pool of
+    // shared coins. Each shared coin must be spent exactly once, and no coin may be lost.
+    #[test]
+    fn test_concurrent_writers_never_lose_or_duplicate_coins() {
+        const THREADS: u8 = 8;
+        const SHARED: u32 = 200;
+        const STEPS: u32 = 300;
+
+        let db = Arc::new(ShardedUtxoDatabase::with_shards(16));
+        let mut setup = UtxoBatch::new();
+        for n in 0..SHARED {
+            setup.add(utxo_ref(0, n), coin(n as u64));
+        }
+        for thread in 1..=THREADS {
+            setup.add(utxo_ref(thread, 0), coin(10_000));
+        }
+        db.commit(setup).unwrap();
+
+        let shared_won = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (1..=THREADS)
+            .map(|thread| {
+                let db = Arc::clone(&db);
+                let shared_won = Arc::clone(&shared_won);
+                thread::spawn(move || {
+                    let mut won = 0;
+                    for step in 0..STEPS {
+                        let mut batch = UtxoBatch::new();
+                        batch.remove(utxo_ref(thread, step));
+                        batch.add(utxo_ref(thread, step + 1), coin(10_000));
+                        db.commit(batch).unwrap();
+
+                        let target = utxo_ref(0, (step * 7 + thread as u32) % SHARED);
+                        assert!(db.get_coin(&utxo_ref(thread, step + 1)).is_some());
+                        let mut claim = UtxoBatch::new();
+                        claim.remove(target);
+                        claim.add(UtxoRef { txid: [100 + thread; 32], vout: target.vout }, coin(1));
+                        if db.commit(claim).is_ok() {
+                            won += 1;
+                        }
+                    }
+                    shared_won.fetch_add(won, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let won = shared_won.load(Ordering::SeqCst);
+        let utxos = db.utxos().unwrap();
+        let claimed = utxos.iter().filter(|(utxo_ref, _)| utxo_ref.txid[0] > 100).count();
+        let unclaimed = utxos.iter().filter(|(utxo_ref, _)| utxo_ref.txid[0] == 0).count();
+        assert_eq!(claimed, won);
+        assert_eq!(claimed + unclaimed, SHARED as usize);
+        for thread in 1..=THREADS {
+            assert!(db.contains(&utxo_ref(thread, STEPS)));
+            assert!(!db.contains(&utxo_ref(thread, STEPS - 1)));
+        }
+        assert_eq!(db.len(), SHARED as usize + THREADS as usize);
+    }
+}
@@ -0,0 +1,89 @@
+use bitcoin_hashes::sha256d;
+
+use crate::transaction::encoding::write_var_bytes;
+use crate::transaction::transaction::Transaction;
+
+pub const SIGHASH_ALL: u32 = 0x01;
+
+pub fn legacy_sighash(
+    tx: &Transaction,
+    input_index: usize,
+    script_code: &[u8],
+    sighash_type: u32,
+) -> [u8; 32] {
+    let mut copy = tx.clone();
+    for (i, input) in copy.inputs.iter_mut().enumerate() {
+        input.witness.clear();
+        input.script_sig = if i == input_index { script_code.to_vec() } else { Vec::new() };
+    }
+
+    let mut preimage = copy.serialize_without_witness();
+    preimage.extend_from_slice(&sighash_type.to_le_bytes());
+    sha256d::Hash::hash(&preimage).to_byte_array()
+}
+
+// BIP143 signature hash for version 0 witness programs, restricted to SIGHASH_ALL.
+pub fn segwit_v0_sighash(
+    tx: &Transaction,
+    input_index: usize,
+    script_code: &[u8],
+    amount: u64,
+    sighash_type: u32,
+) -> [u8; 32] {
+    let mut prevouts = Vec::new();
+    let mut sequences = Vec::new();
+    for input in &tx.inputs {
+        prevouts.extend_from_slice(&input.utxo_ref.txid);
+        prevouts.extend_from_slice(&input.utxo_ref.vout.to_le_bytes());
+        sequences.extend_from_slice(&input.sequence.to_le_bytes());
+    }
+    let mut outputs = Vec::new();
+    for output in &tx.outputs {
+        output.serialize_into(&mut outputs);
+    }
+
+    let input = &tx.inputs[input_index];
+    let mut preimage = Vec::new();
+    preimage.extend_from_slice(&tx.version.to_le_bytes());
+    preimage.extend_from_slice(sha256d::Hash::hash(&prevouts).as_byte_array());
+    preimage.extend_from_slice(sha256d::Hash::hash(&sequences).as_byte_array());
+    preimage.extend_from_slice(&input.utxo_ref.txid);
+    preimage.extend_from_slice(&input.utxo_ref.vout.to_le_bytes());
+    write_var_bytes(&mut preimage, script_code);
+    preimage.extend_from_slice(&amount.to_le_bytes());
+    preimage.extend_from_slice(&input.sequence.to_le_bytes());
+    preimage.extend_from_slice(sha256d::Hash::hash(&outputs).as_byte_array());
+    preimage.extend_from_slice(&tx.locktime.to_le_bytes());
+    preimage.extend_from_slice(&sighash_type.to_le_bytes());
+    sha256d::Hash::hash(&preimage).to_byte_array()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_bip143_native_p2wpkh_vector() {
+        let tx = Transaction::deserialize(&from_hex(
+            "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f00000000\
+             00eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a01000000\
+             00ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac90\
+             93510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000",
+        )).unwrap();
+        let script_code = from_hex("76a9141d0f172a0ecb48aee1be1f2687d2963ae33f71a188ac");
+
+        let sighash = segwit_v0_sighash(&tx, 1, &script_code, 600_000_000, SIGHASH_ALL);
+
+        assert_eq!(
+            sighash.to_vec(),
+            from_hex("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670")
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use sled::transaction::ConflictableTransactionError;
+use sled::{Db, Transactional, Tree};
+
+use crate::transaction::coin::Coin;
+use crate::transaction::store::{
+    decode_coin, decode_utxo_key, encode_coin, encode_utxo_key, BatchOp, UtxoBatch, UtxoStore,
+};
+use crate::transaction::transaction::UtxoRef;
+
+const COINS_TREE: &str = "coins";
+const METADATA_TREE: &str = "metadata";
+const BEST_BLOCK_KEY: &[u8] = b"best_block";
+// How many times close() tries to reopen the directory, doubling the wait from the first.
+const CLOSE_ATTEMPTS: u32 = 8;
+const CLOSE_FIRST_WAIT: Duration = Duration::from_millis(10);
+
+// Pure-Rust alternative to the RocksDB store with the same layout: a tree of coins and a
+// metadata tree whose best-block marker is updated in the same transaction as the coins.
+pub struct SledUtxoDatabase {
+    db: Db,
+    coins: Tree,
+    metadata: Tree,
+    path: PathBuf,
+}
+
+impl SledUtxoDatabase {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let db = sled::open(path)
+            .map_err(|e| format!("Failed to open sled database at {}: {e}", path.display()))?;
+        let coins = db.open_tree(COINS_TREE).map_err(|e| format!("Failed to open coins tree: {e}"))?;
+        let metadata = db.open_tree(METADATA_TREE)
+            .map_err(|e| format!("Failed to open metadata tree: {e}"))?;
+        Ok(SledUtxoDatabase { db, coins, metadata, path: path.to_path_buf() })
+    }
+
+    fn flush(&self) -> Result<(), String> {
+        self.db.flush().map(|_| ()).map_err(|e| format!("sled flush failed: {e}"))
+    }
+
+    // Flushes and releases every handle. sled's I/O threads can hold the database open for
+    // a moment after the last handle goes, so this reopens the directory with a bounded
+    // backoff until sled itself accepts it; the store can be reopened as soon as it returns.
+    pub fn close(self) -> Result<(), String> {
+        self.flush()?;
+        let SledUtxoDatabase { db, coins, metadata, path } = self;
+        drop(coins);
+        drop(metadata);
+        drop(db);
+
+        let mut wait = CLOSE_FIRST_WAIT;
+        for attempt in 1..=CLOSE_ATTEMPTS {
+            match sled::open(&path) {
+                Ok(db) => {
+                    drop(db);
+                    return Ok(());
+                }
+                Err(e) if attempt == CLOSE_ATTEMPTS => {
+                    return Err(format!("sled database at {} still in use after close: {e}", path.display()));
+                }
+                Err(_) => {
+                    thread::sleep(wait);
+                    wait *= 2;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl UtxoStore for SledUtxoDatabase {
+    fn get(&self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        self.coins.get(encode_utxo_key(utxo_ref))
+            .map_err(|e| format!("sled read failed: {e}"))?
+            .map(|bytes| decode_coin(&bytes))
+            .transpose()
+    }
+
+    fn add(&mut self, utxo_ref: UtxoRef, coin: Coin) -> Result<(), String> {
+        self.coins.insert(encode_utxo_key(&utxo_ref), encode_coin(&coin)?)
+            .map_err(|e| format!("sled write failed: {e}"))?;
+        self.flush()
+    }
+
+    // Every write is flushed before returning, matching the synced writes of the RocksDB
+    // store.
+    fn remove(&mut self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        let removed = self.coins.remove(encode_utxo_key(utxo_ref))
+            .map_err(|e| format!("sled write failed: {e}"))?;
+        if removed.is_some() {
+            self.flush()?;
+        }
+        removed.map(|bytes| decode_coin(&bytes)).transpose()
+    }
+
+    fn apply_batch(&mut self, batch: UtxoBatch) -> Result<(), String> {
+        // Encoded up front: the transaction closure may run more than once.
+        let writes = batch.ops.iter()
+            .map(|op| match op {
+                BatchOp::Add(utxo_ref, coin) => Ok((encode_utxo_key(utxo_ref), Some(encode_coin(coin)?))),
+                BatchOp::Remove(utxo_ref) => Ok((encode_utxo_key(utxo_ref), None)),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        (&self.coins, &self.metadata)
+            .transaction(|(coins, metadata)| {
+                for (key, value) in &writes {
+                    match value {
+                        Some(value) => {
+                            coins.insert(key.as_slice(), value.as_slice())?;
+                        }
+                        None => {
+                            coins.remove(key.as_slice())?;
+                        }
+                    }
+                }
+                if let Some(block_hash) = batch.best_block {
+                    metadata.insert(BEST_BLOCK_KEY, &block_hash[..])?;
+                }
+                Ok::<(), ConflictableTransactionError<String>>(())
+            })
+            .map_err(|e| format!("sled transaction failed: {e}"))?;
+        self.flush()
+    }
+
+    fn utxos(&self) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        self.coins.iter()
+            .map(|entry| {
+                let (key, value) = entry.map_err(|e| format!("sled iteration failed: {e}"))?;
+                Ok((decode_utxo_key(&key)?, decode_coin(&value)?))
+            })
+            .collect()
+    }
+
+    fn is_empty(&self) -> Result<bool, String> {
+        self.coins.first()
+            .map(|entry| entry.is_none())
+            .map_err(|e| format!("sled read failed: {e}"))
+    }
+
+    fn best_block(&self) -> Result<Option<[u8; 32]>, String> {
+        let value = self.metadata.get(BEST_BLOCK_KEY)
+            .map_err(|e| format!("sled read failed: {e}"))?;
+        value
+            .map(|bytes| {
+                bytes.as_ref().try_into()
+                    .map_err(|_| format!("Corrupt best block marker of {} bytes", bytes.len()))
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::store::conformance;
+    use crate::transaction::transaction::TxOutput;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rust-coin-sled-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    #[test]
+    fn test_conformance() {
+        let mut run = 0;
+        conformance::run(|| {
+            run += 1;
+            SledUtxoDatabase::open(&temp_path(&format!("conformance-{run}"))).unwrap()
+        });
+    }
+
+    #[test]
+    fn test_coins_survive_reopen() {
+        let path = temp_path("reopen");
+        let utxo_ref = UtxoRef { txid: [5u8; 32], vout: 2 };
+        let coin = Coin::new(TxOutput::p2wpkh(&[5u8; 20], 12_345), 840_000, true);
+        let mut store = SledUtxoDatabase::open(&path).unwrap();
+        let mut batch = UtxoBatch::new();
+        batch.add(utxo_ref, coin.clone());
+        batch.set_best_block([1u8; 32]);
+        store.apply_batch(batch).unwrap();
+        store.close().unwrap();
+
+        let store = SledUtxoDatabase::open(&path).unwrap();
+        assert_eq!(store.get(&utxo_ref).unwrap(), Some(coin));
+        assert_eq!(store.best_block().unwrap(), Some([1u8; 32]));
+        store.close().unwrap();
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use bitcoin_hashes::{sha256, HashEngine};
+
+use crate::transaction::store::{decode_coin, encode_coin, UtxoBatch, UtxoStore};
+use crate::transaction::transaction::UtxoRef;
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"RCUTXOSN";
+const SNAPSHOT_VERSION: u32 = 2;
+const MAX_COIN_BYTES: u32 = 10_100;
+const IMPORT_BATCH_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotMetadata {
+    pub block_hash: [u8; 32],
+    pub coin_count: u64,
+    pub content_hash: [u8; 32],
+}
+
+// Layout: magic, version, block hash and coin count, then one record per coin sorted by
+// outpoint (txid, vout, coin length, compressed coin), then the SHA-256 of the header and
+// all record bytes. Hashing the header ties the coins to the block they claim to be at, and
+// sorting makes the hash a function of the UTXO set alone, whichever backend produced it.
+// `UtxoStore` has no ordered iterator, so the whole set is held in memory while it is
+// sorted; exporting needs room for every coin.
+pub fn export_snapshot<S: UtxoStore, W: Write>(store: &S, writer: W) -> Result<SnapshotMetadata, String> {
+    let block_hash = store.best_block()?
+        .ok_or_else(|| "Cannot snapshot a store without a best block".to_string())?;
+    let mut coins = store.utxos()?;
+    coins.sort_by_key(|(utxo_ref, _)| (utxo_ref.txid, utxo_ref.vout));
+
+    let mut writer = BufWriter::new(writer);
+    let mut header = Vec::with_capacity(52);
+    header.extend_from_slice(SNAPSHOT_MAGIC);
+    header.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+    header.extend_from_slice(&block_hash);
+    header.extend_from_slice(&(coins.len() as u64).to_le_bytes());
+    write_all(&mut writer, &header)?;
+
+    let mut engine = sha256::Hash::engine();
+    engine.input(&header);
+    let mut record = Vec::new();
+    for (utxo_ref, coin) in &coins {
+        record.clear();
+        let coin_bytes = encode_coin(coin)?;
+        record.extend_from_slice(&utxo_ref.txid);
+        record.extend_from_slice(&utxo_ref.vout.to_le_bytes());
+        record.extend_from_slice(&(coin_bytes.len() as u32).to_le_bytes());
+        record.extend_from_slice(&coin_bytes);
+        engine.input(&record);
+        write_all(&mut writer, &record)?;
+    }
+
+    let content_hash = sha256::Hash::from_engine(engine).to_byte_array();
+    write_all(&mut writer, &content_hash)?;
+    writer.flush().map_err(|e| format!("Failed to write snapshot: {e}"))?;
+    Ok(SnapshotMetadata { block_hash, coin_count: coins.len() as u64, content_hash })
+}
+
+// Coins are written in batches as they are read, and the snapshot's block hash is only
+// recorded once the content hash has checked out. A store whose import failed has no best
+// block and should be thrown away. `expected_hash` pins the content to a known value, the
+// way assumeutxo hardcodes snapshot hashes.
+pub fn import_snapshot<S: UtxoStore, R: Read>(
+    reader: R,
+    store: &mut S,
+    expected_hash: Option<[u8; 32]>,
+) -> Result<SnapshotMetadata, String> {
+    if store.best_block()?.is_some() || !store.is_empty()? {
+        return Err("Snapshots can only be imported into an empty store".to_string());
+    }
+
+    let mut reader = BufReader::new(reader);
+    if &read_array::<8>(&mut reader)? != SNAPSHOT_MAGIC {
+        return Err("Not a UTXO snapshot".to_string());
+    }
+    let version = u32::from_le_bytes(read_array(&mut reader)?);
+    if version != SNAPSHOT_VERSION {
+        return Err(format!("Unsupported snapshot version {version}"));
+    }
+    let block_hash = read_array::<32>(&mut reader)?;
+    let coin_count_bytes = read_array::<8>(&mut reader)?;
+    let coin_count = u64::from_le_bytes(coin_count_bytes);
+
+    let mut engine = sha256::Hash::engine();
+    engine.input(SNAPSHOT_MAGIC);
+    engine.input(&version.to_le_bytes());
+    engine.input(&block_hash);
+    engine.input(&coin_count_bytes);
+    let mut batch = UtxoBatch::new();
+    let mut previous: Option<([u8; 32], u32)> = None;
+    for _ in 0..coin_count {
+        let txid = read_array::<32>(&mut reader)?;
+        let vout_bytes = read_array::<4>(&mut reader)?;
+        let length_bytes = read_array::<4>(&mut reader)?;
+        let vout = u32::from_le_bytes(vout_bytes);
+        let length = u32::from_le_bytes(length_bytes);
+        if length > MAX_COIN_BYTES {
+            return Err(format!("Snapshot coin of {length} bytes is too large"));
+        }
+        let mut coin_bytes = vec![0u8; length as usize];
+        read_exact(&mut reader, &mut coin_bytes)?;
+
+        if previous.is_some_and(|previous| previous >= (txid, vout)) {
+            return Err("Snapshot coins are not in strictly ascending order".to_string());
+        }
+        previous = Some((txid, vout));
+
+        engine.input(&txid);
+        engine.input(&vout_bytes);
+        engine.input(&length_bytes);
+        engine.input(&coin_bytes);
+        batch.add(UtxoRef { txid, vout }, decode_coin(&coin_bytes)?);
+        if batch.ops.len() >= IMPORT_BATCH_SIZE {
+            store.apply_batch(std::mem::take(&mut batch))?;
+        }
+    }
+
+    let content_hash = sha256::Hash::from_engine(engine).to_byte_array();
+    if read_array::<32>(&mut reader)? != content_hash {
+        return Err("Snapshot content hash does not match its coins".to_string());
+    }
+    if let Some(expected) = expected_hash
+        && expected != content_hash
+    {
+        return Err("Snapshot content hash does not match the expected hash".to_string());
+    }
+    let mut trailing = [0u8; 1];
+    if reader.read(&mut trailing).map_err(|e| format!("Failed to read snapshot: {e}"))? != 0 {
+        return Err("Trailing bytes after snapshot".to_string());
+    }
+
+    batch.set_best_block(block_hash);
+    store.apply_batch(batch)?;
+    Ok(SnapshotMetadata { block_hash, coin_count, content_hash })
+}
+
+fn write_all<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<(), String> {
+    writer.write_all(bytes).map_err(|e| format!("Failed to write snapshot: {e}"))
+}
+
+fn read_exact<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<(), String> {
+    reader.read_exact(buffer).map_err(|e| format!("Failed to read snapshot: {e}"))
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N], String> {
+    let mut buffer = [0u8; N];
+    read_exact(reader, &mut buffer)?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::coin::Coin;
+    use crate::transaction::database::UtxoDatabase;
+    use crate::transaction::transaction::TxOutput;
+
+    fn populated_store(count: u8) -> UtxoDatabase {
+        let mut store = UtxoDatabase::new();
+        let mut batch = UtxoBatch::new();
+        for seed in 0..count {
+            let coin = Coin::new(TxOutput::p2wpkh(&[seed; 20], 1_000 + seed as u64), seed as u32, seed == 0);
+            batch.add(UtxoRef { txid: [seed; 32], vout: seed as u32 % 3 }, coin);
+        }
+        batch.set_best_block([9u8; 32]);
+        store.apply_batch(batch).unwrap();
+        store
+    }
+
+    #[test]
+    fn test_round_trip_is_deterministic() {
+        let source = populated_store(50);
+        let mut bytes = Vec::new();
+        let exported = export_snapshot(&source, &mut bytes).unwrap();
+        assert_eq!(exported.coin_count, 50);
+
+        let mut target = UtxoDatabase::new();
+        let imported = import_snapshot(bytes.as_slice(), &mut target, Some(exported.content_hash)).unwrap();
+        assert_eq!(imported, exported);
+        assert_eq!(target.get_all_utxos(), source.get_all_utxos());
+        assert_eq!(target.best_block().unwrap(), Some([9u8; 32]));
+
+        // Re-exporting the imported set gives identical bytes.
+        let mut again = Vec::new();
+        export_snapshot(&target, &mut again).unwrap();
+        assert_eq!(again, bytes);
+    }
+
+    #[test]
+    fn test_rejects_tampered_or_unexpected_snapshots() {
+        let mut bytes = Vec::new();
+        let exported = export_snapshot(&populated_store(5), &mut bytes).unwrap();
+
+        // Flip a byte in the last coin's script; it still decodes but the hash no longer matches.
+        let mut tampered = bytes.clone();
+        let last_coin_byte = tampered.len() - 33;
+        tampered[last_coin_byte] ^= 1;
+        let mut target = UtxoDatabase::new();
+        let err = import_snapshot(tampered.as_slice(), &mut target, None).unwrap_err();
+        assert!(err.contains("content hash"), "{err}");
+        assert_eq!(target.best_block().unwrap(), None);
+
+        // The block hash is covered too, so the coins cannot be passed off as another block's.
+        let mut relabelled = bytes.clone();
+        relabelled[12] ^= 1;
+        let mut target = UtxoDatabase::new();
+        let err = import_snapshot(relabelled.as_slice(), &mut target, None).unwrap_err();
+        assert!(err.contains("content hash"), "{err}");
+
+        let mut target = UtxoDatabase::new();
+        let mut wrong = exported.content_hash;
+        wrong[0] ^= 1;
+        assert!(import_snapshot(bytes.as_slice(), &mut target, Some(wrong)).is_err());
+        assert_eq!(target.best_block().unwrap(), None);
+
+        let mut target = UtxoDatabase::new();
+        assert!(import_snapshot(&bytes[..bytes.len() - 1], &mut target, None).is_err());
+        assert!(import_snapshot(bytes.as_slice(), &mut populated_store(1), None).is_err());
+    }
+}
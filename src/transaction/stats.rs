@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+
+use crate::mempool::policy::{is_dust, DEFAULT_DUST_RELAY_FEE};
+use crate::transaction::coin::Coin;
+use crate::transaction::encoding::write_compact_size;
+use crate::transaction::script::{self, OP_RETURN};
+use crate::transaction::store::{resolve_batch, CoinChange, UtxoBatch, UtxoStore};
+use crate::transaction::transaction::UtxoRef;
+
+const STATS_DIGEST_VERSION: u8 = 2;
+
+// Upper bounds (exclusive) of the value histogram buckets; the last bucket holds
+// everything from 10 BTC up.
+pub const VALUE_BUCKETS: [u64; 7] = [1_000, 10_000, 100_000, 1_000_000, 10_000_000, 100_000_000, 1_000_000_000];
+pub const AGE_BUCKET_BLOCKS: u32 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ScriptType {
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    NullData,
+    Other,
+}
+
+impl ScriptType {
+    pub const ALL: [ScriptType; 5] = [
+        ScriptType::P2pkh,
+        ScriptType::P2sh,
+        ScriptType::P2wpkh,
+        ScriptType::NullData,
+        ScriptType::Other,
+    ];
+
+    pub fn classify(script_pubkey: &[u8]) -> ScriptType {
+        if script::p2pkh_hash(script_pubkey).is_some() {
+            ScriptType::P2pkh
+        } else if script::p2sh_hash(script_pubkey).is_some() {
+            ScriptType::P2sh
+        } else if script::p2wpkh_hash(script_pubkey).is_some() {
+            ScriptType::P2wpkh
+        } else if script_pubkey.first() == Some(&OP_RETURN) {
+            ScriptType::NullData
+        } else {
+            ScriptType::Other
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TypeTotals {
+    pub count: u64,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtxoStats {
+    pub coin_count: u64,
+    pub total_amount: u64,
+    pub by_script_type: BTreeMap<ScriptType, TypeTotals>,
+    pub value_histogram: [u64; VALUE_BUCKETS.len() + 1],
+    pub dust_count: u64,
+    // Coin counts keyed by creation height / AGE_BUCKET_BLOCKS.
+    pub height_histogram: BTreeMap<u32, u64>,
+}
+
+impl Default for UtxoStats {
+    fn default() -> Self {
+        UtxoStats::new()
+    }
+}
+
+impl UtxoStats {
+    pub fn new() -> Self {
+        UtxoStats {
+            coin_count: 0,
+            total_amount: 0,
+            by_script_type: BTreeMap::new(),
+            value_histogram: [0; VALUE_BUCKETS.len() + 1],
+            dust_count: 0,
+            height_histogram: BTreeMap::new(),
+        }
+    }
+
+    // A full scan, for seeding the running totals and for checking them.
+    pub fn from_store<S: UtxoStore>(store: &S) -> Result<Self, String> {
+        let mut stats = UtxoStats::new();
+        for (_, coin) in store.utxos()? {
+            stats.add_coin(&coin)?;
+        }
+        Ok(stats)
+    }
+
+    pub fn value_bucket(amount: u64) -> usize {
+        VALUE_BUCKETS.iter().position(|&bound| amount < bound).unwrap_or(VALUE_BUCKETS.len())
+    }
+
+    pub fn add_coin(&mut self, coin: &Coin) -> Result<(), String> {
+        let amount = coin.output.amount;
+        self.total_amount = self.total_amount.checked_add(amount)
+            .ok_or("Coin total overflows")?;
+        self.coin_count += 1;
+        let totals = self.by_script_type
+            .entry(ScriptType::classify(&coin.output.script_pubkey))
+            .or_default();
+        totals.count += 1;
+        totals.amount = totals.amount.checked_add(amount).ok_or("Coin total overflows")?;
+        self.value_histogram[Self::value_bucket(amount)] += 1;
+        if is_dust(&coin.output, DEFAULT_DUST_RELAY_FEE) {
+            self.dust_count += 1;
+        }
+        *self.height_histogram.entry(coin.height / AGE_BUCKET_BLOCKS).or_default() += 1;
+        Ok(())
+    }
+
+    // Fails if the coin was never counted, which means the totals and the store disagree.
+    pub fn remove_coin(&mut self, coin: &Coin) -> Result<(), String> {
+        let untracked = || "Removed coin is not in the statistics".to_string();
+        let amount = coin.output.amount;
+        self.coin_count = self.coin_count.checked_sub(1).ok_or_else(untracked)?;
+        self.total_amount = self.total_amount.checked_sub(amount).ok_or_else(untracked)?;
+        let script_type = ScriptType::classify(&coin.output.script_pubkey);
+        let totals = self.by_script_type.get_mut(&script_type).ok_or_else(untracked)?;
+        totals.count = totals.count.checked_sub(1).ok_or_else(untracked)?;
+        totals.amount = totals.amount.checked_sub(amount).ok_or_else(untracked)?;
+        if totals.count == 0 {
+            self.by_script_type.remove(&script_type);
+        }
+        let bucket = &mut self.value_histogram[Self::value_bucket(amount)];
+        *bucket = bucket.checked_sub(1).ok_or_else(untracked)?;
+        if is_dust(&coin.output, DEFAULT_DUST_RELAY_FEE) {
+            self.dust_count = self.dust_count.checked_sub(1).ok_or_else(untracked)?;
+        }
+        let height_bucket = coin.height / AGE_BUCKET_BLOCKS;
+        let count = self.height_histogram.get_mut(&height_bucket).ok_or_else(untracked)?;
+        *count -= 1;
+        if *count == 0 {
+            self.height_histogram.remove(&height_bucket);
+        }
+        Ok(())
+    }
+
+    // Deterministic encoding for monitoring: every script type and value bucket is always
+    // present, followed by the non-empty height buckets in order.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = vec![STATS_DIGEST_VERSION];
+        bytes.extend_from_slice(&self.coin_count.to_le_bytes());
+        bytes.extend_from_slice(&self.total_amount.to_le_bytes());
+        for script_type in ScriptType::ALL {
+            let totals = self.by_script_type.get(&script_type).copied().unwrap_or_default();
+            bytes.extend_from_slice(&totals.count.to_le_bytes());
+            bytes.extend_from_slice(&totals.amount.to_le_bytes());
+        }
+        for count in self.value_histogram {
+            bytes.extend_from_slice(&count.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.dust_count.to_le_bytes());
+        write_compact_size(&mut bytes, self.height_histogram.len() as u64);
+        for (bucket, count) in &self.height_histogram {
+            bytes.extend_from_slice(&bucket.to_le_bytes());
+            bytes.extend_from_slice(&count.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+// Keeps `UtxoStats` current as coins come and go, so reading them never needs a scan.
+pub struct StatsUtxoStore<S: UtxoStore> {
+    base: S,
+    stats: UtxoStats,
+}
+
+impl<S: UtxoStore> StatsUtxoStore<S> {
+    pub fn new(base: S) -> Result<Self, String> {
+        let stats = UtxoStats::from_store(&base)?;
+        Ok(StatsUtxoStore { base, stats })
+    }
+
+    // For callers that already know the totals, such as an empty store; skips the scan.
+    pub fn with_stats(base: S, stats: UtxoStats) -> Self {
+        StatsUtxoStore { base, stats }
+    }
+
+    pub fn base(&self) -> &S {
+        &self.base
+    }
+
+    pub fn stats(&self) -> &UtxoStats {
+        &self.stats
+    }
+}
+
+impl<S: UtxoStore> UtxoStore for StatsUtxoStore<S> {
+    fn get(&self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        self.base.get(utxo_ref)
+    }
+
+    fn add(&mut self, utxo_ref: UtxoRef, coin: Coin) -> Result<(), String> {
+        let mut batch = UtxoBatch::new();
+        batch.add(utxo_ref, coin);
+        self.apply_batch(batch)
+    }
+
+    fn remove(&mut self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String> {
+        let existing = self.base.get(utxo_ref)?;
+        if existing.is_some() {
+            let mut batch = UtxoBatch::new();
+            batch.remove(*utxo_ref);
+            self.apply_batch(batch)?;
+        }
+        Ok(existing)
+    }
+
+    fn apply_batch(&mut self, batch: UtxoBatch) -> Result<(), String> {
+        // The new totals are worked out first so a bad batch leaves both sides untouched.
+        let mut stats = self.stats.clone();
+        for change in resolve_batch(&self.base, &batch)? {
+            match change {
+                CoinChange::Added(_, coin) => stats.add_coin(&coin)?,
+                CoinChange::Removed(_, coin) => stats.remove_coin(&coin)?,
+            }
+        }
+        self.base.apply_batch(batch)?;
+        self.stats = stats;
+        Ok(())
+    }
+
+    fn utxos(&self) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        self.base.utxos()
+    }
+
+    fn utxos_for_script(&self, script_pubkey: &[u8]) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        self.base.utxos_for_script(script_pubkey)
+    }
+
+    fn is_empty(&self) -> Result<bool, String> {
+        self.base.is_empty()
+    }
+
+    fn best_block(&self) -> Result<Option<[u8; 32]>, String> {
+        self.base.best_block()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::database::UtxoDatabase;
+    use crate::transaction::store::conformance;
+    use crate::transaction::transaction::TxOutput;
+
+    fn coin(script_pubkey: Vec<u8>, amount: u64, height: u32) -> Coin {
+        Coin::new(TxOutput::new(script_pubkey, amount), height, false)
+    }
+
+    #[test]
+    fn test_conformance() {
+        conformance::run(|| StatsUtxoStore::new(UtxoDatabase::new()).unwrap());
+    }
+
+    #[test]
+    fn test_running_totals_match_full_scan() {
+        let mut store = StatsUtxoStore::new(UtxoDatabase::new()).unwrap();
+        let refs: Vec<UtxoRef> = (0..4u8).map(|i| UtxoRef { txid: [i; 32], vout: 0 }).collect();
+        store.add(refs[0], coin(script::p2pkh(&[1; 20]), 500, 5)).unwrap();
+        store.add(refs[1], coin(script::p2wpkh(&[2; 20]), 250_000_000, 12_000)).unwrap();
+
+        let mut batch = UtxoBatch::new();
+        batch.remove(refs[0]);
+        batch.add(refs[2], coin(script::p2sh(&[3; 20]), 40_000, 25_000));
+        batch.add(refs[3], coin(vec![OP_RETURN, 0x01, 0xff], 0, 25_001));
+        // Replacing a coin in place must drop the old one from the totals.
+        batch.add(refs[1], coin(script::p2wpkh(&[2; 20]), 9_000, 12_000));
+        store.apply_batch(batch).unwrap();
+
+        let stats = store.stats();
+        assert_eq!(*stats, UtxoStats::from_store(store.base()).unwrap());
+        assert_eq!(stats.coin_count, 3);
+        assert_eq!(stats.total_amount, 49_000);
+        // Unspendable outputs are never dust.
+        assert_eq!(stats.dust_count, 0);
+        assert_eq!(stats.value_histogram, [1, 1, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(stats.by_script_type.get(&ScriptType::P2pkh), None);
+        assert_eq!(stats.by_script_type[&ScriptType::NullData], TypeTotals { count: 1, amount: 0 });
+        assert_eq!(stats.height_histogram, BTreeMap::from([(1, 1), (2, 2)]));
+        assert_eq!(stats.serialize().len(), 1 + 16 + 5 * 16 + 8 * 8 + 8 + 1 + 2 * 12);
+    }
+
+    #[test]
+    fn test_dust_follows_relay_policy() {
+        let mut stats = UtxoStats::new();
+        for (script_pubkey, amount) in [(script::p2wpkh(&[1; 20]), 293), (script::p2wpkh(&[1; 20]), 294), (script::p2pkh(&[1; 20]), 545)] {
+            stats.add_coin(&coin(script_pubkey, amount, 1)).unwrap();
+        }
+        assert_eq!(stats.dust_count, 2);
+    }
+
+    #[test]
+    fn test_untracked_coin_is_an_error() {
+        let mut stats = UtxoStats::new();
+        let tracked = coin(script::p2wpkh(&[1; 20]), 1_000, 5);
+        stats.add_coin(&tracked).unwrap();
+
+        assert!(stats.clone().remove_coin(&coin(script::p2wpkh(&[1; 20]), 2_000, 5)).is_err());
+        assert!(stats.clone().remove_coin(&coin(script::p2pkh(&[1; 20]), 1_000, 5)).is_err());
+        assert!(stats.add_coin(&coin(script::p2wpkh(&[2; 20]), u64::MAX, 5)).is_err());
+        stats.remove_coin(&tracked).unwrap();
+        assert_eq!(stats, UtxoStats::new());
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use crate::transaction::coin::Coin;
+use crate::transaction::encoding::{write_compact_size, ByteReader};
+use crate::transaction::transaction::UtxoRef;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BatchOp {
+    Add(UtxoRef, Coin),
+    Remove(UtxoRef),
+}
+
+// A set of coin changes that a backend must apply all-or-nothing, in order, together with
+// the hash of the block they bring the store up to.
+#[derive(Debug, Clone, Default)]
+pub struct UtxoBatch {
+    pub ops: Vec<BatchOp>,
+    pub best_block: Option<[u8; 32]>,
+}
+
+impl UtxoBatch {
+    pub fn new() -> Self {
+        UtxoBatch::default()
+    }
+
+    pub fn add(&mut self, utxo_ref: UtxoRef, coin: Coin) {
+        self.ops.push(BatchOp::Add(utxo_ref, coin));
+    }
+
+    pub fn remove(&mut self, utxo_ref: UtxoRef) {
+        self.ops.push(BatchOp::Remove(utxo_ref));
+    }
+
+    pub fn set_best_block(&mut self, block_hash: [u8; 32]) {
+        self.best_block = Some(block_hash);
+    }
+}
+
+// Common interface over the in-memory and on-disk coin databases.
+pub trait UtxoStore {
+    fn get(&self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String>;
+
+    fn add(&mut self, utxo_ref: UtxoRef, coin: Coin) -> Result<(), String>;
+
+    fn remove(&mut self, utxo_ref: &UtxoRef) -> Result<Option<Coin>, String>;
+
+    // Adding over an existing coin replaces it and removing a missing coin does nothing;
+    // only backend failures are errors.
+    fn apply_batch(&mut self, batch: UtxoBatch) -> Result<(), String>;
+
+    fn utxos(&self) -> Result<Vec<(UtxoRef, Coin)>, String>;
+
+    // Backends with a script index can answer this without a full scan.
+    fn utxos_for_script(&self, script_pubkey: &[u8]) -> Result<Vec<(UtxoRef, Coin)>, String> {
+        Ok(self.utxos()?
+            .into_iter()
+            .filter(|(_, coin)| coin.output.script_pubkey == script_pubkey)
+            .collect())
+    }
+
+    // Backends that can see their first coin without loading the rest should override this.
+    fn is_empty(&self) -> Result<bool, String> {
+        Ok(self.utxos()?.is_empty())
+    }
+
+    fn best_block(&self) -> Result<Option<[u8; 32]>, String>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoinChange {
+    Added(UtxoRef, Coin),
+    Removed(UtxoRef, Coin),
+}
+
+// Spells out which coins a batch will create and destroy against the store's current
+// contents, for wrappers that keep derived data in step with the coins. Adding over an
+// existing coin counts as removing it first, and removing a missing coin changes nothing.
+pub fn resolve_batch<S: UtxoStore>(store: &S, batch: &UtxoBatch) -> Result<Vec<CoinChange>, String> {
+    let mut overlay: HashMap<UtxoRef, Option<Coin>> = HashMap::new();
+    let mut changes = Vec::with_capacity(batch.ops.len());
+    for op in &batch.ops {
+        let utxo_ref = match op {
+            BatchOp::Add(utxo_ref, _) | BatchOp::Remove(utxo_ref) => *utxo_ref,
+        };
+        let current = match overlay.get(&utxo_ref) {
+            Some(coin) => coin.clone(),
+            None => store.get(&utxo_ref)?,
+        };
+        if let Some(coin) = current {
+            changes.push(CoinChange::Removed(utxo_ref, coin));
+        }
+        let next = match op {
+            BatchOp::Add(_, coin) => {
+                changes.push(CoinChange::Added(utxo_ref, coin.clone()));
+                Some(coin.clone())
+            }
+            BatchOp::Remove(_) => None,
+        };
+        overlay.insert(utxo_ref, next);
+    }
+    Ok(changes)
+}
+
+// Keys are the txid followed by the output index as a compact size, so almost every
+// key is 33 bytes.
+pub fn encode_utxo_key(utxo_ref: &UtxoRef) -> Vec<u8> {
+    let mut key = Vec::with_capacity(33);
+    key.extend_from_slice(&utxo_ref.txid);
+    write_compact_size(&mut key, utxo_ref.vout as u64);
+    key
+}
+
+pub fn decode_utxo_key(key: &[u8]) -> Result<UtxoRef, String> {
+    let mut reader = ByteReader::new(key);
+    let txid = reader.read_array()?;
+    let vout = reader.read_compact_size()?;
+    if !reader.is_empty() || vout > u32::MAX as u64 {
+        return Err(format!("Malformed coin key of {} bytes", key.len()));
+    }
+    Ok(UtxoRef { txid, vout: vout as u32 })
+}
+
+pub fn encode_coin(coin: &Coin) -> Result<Vec<u8>, String> {
+    coin.compress()
+}
+
+pub fn decode_coin(bytes: &[u8]) -> Result<Coin, String> {
+    Coin::decompress(bytes)
+}
+
+// Behaviour every backend must share. Backends call `run` from their own test module.
+#[cfg(test)]
+pub mod conformance {
+    use super::*;
+    use crate::transaction::transaction::TxOutput;
+
+    fn coin(seed: u8, vout: u32) -> (UtxoRef, Coin) {
+        let output = TxOutput::p2wpkh(&[seed; 20], 1_000 * seed as u64 + vout as u64);
+        (UtxoRef { txid: [seed; 32], vout }, Coin::new(output, seed as u32 * 10, vout == 0))
+    }
+
+    pub fn run<S: UtxoStore>(mut make_store: impl FnMut() -> S) {
+        add_get_remove(&mut make_store());
+        batch_applies_in_order(&mut make_store());
+        batch_overwrites_and_ignores_missing(&mut make_store());
+        overwrite_then_remove(&mut make_store());
+        iteration_sees_every_coin(&mut make_store());
+        lookup_by_script(&mut make_store());
+    }
+
+    fn add_get_remove<S: UtxoStore>(store: &mut S) {
+        let (utxo_ref, output) = coin(1, 0);
+        assert_eq!(store.get(&utxo_ref).unwrap(), None);
+        assert!(store.is_empty().unwrap());
+
+        store.add(utxo_ref, output.clone()).unwrap();
+        assert!(!store.is_empty().unwrap());
+        assert_eq!(store.get(&utxo_ref).unwrap(), Some(output.clone()));
+        assert_eq!(store.get(&UtxoRef { txid: [1; 32], vout: 1 }).unwrap(), None);
+
+        assert_eq!(store.remove(&utxo_ref).unwrap(), Some(output));
+        assert_eq!(store.remove(&utxo_ref).unwrap(), None);
+        assert_eq!(store.get(&utxo_ref).unwrap(), None);
+        assert!(store.is_empty().unwrap());
+    }
+
+    fn batch_applies_in_order<S: UtxoStore>(store: &mut S) {
+        let (kept_ref, kept) = coin(2, 0);
+        let (spent_ref, spent) = coin(3, 300);
+        let (existing_ref, existing) = coin(4, 70_000);
+        store.add(existing_ref, existing).unwrap();
+        assert_eq!(store.best_block().unwrap(), None);
+
+        let mut batch = UtxoBatch::new();
+        batch.add(kept_ref, kept.clone());
+        batch.add(spent_ref, spent);
+        batch.remove(spent_ref);
+        batch.remove(existing_ref);
+        batch.set_best_block([9; 32]);
+        store.apply_batch(batch).unwrap();
+
+        assert_eq!(store.get(&kept_ref).unwrap(), Some(kept));
+        assert_eq!(store.get(&spent_ref).unwrap(), None);
+        assert_eq!(store.get(&existing_ref).unwrap(), None);
+        assert_eq!(store.best_block().unwrap(), Some([9; 32]));
+    }
+
+    fn batch_overwrites_and_ignores_missing<S: UtxoStore>(store: &mut S) {
+        let (utxo_ref, original) = coin(5, 0);
+        let (missing_ref, _) = coin(6, 0);
+        let mut replacement = original.clone();
+        replacement.output.amount += 1;
+        store.add(utxo_ref, original).unwrap();
+
+        let mut batch = UtxoBatch::new();
+        batch.add(utxo_ref, replacement.clone());
+        batch.remove(missing_ref);
+        store.apply_batch(batch).unwrap();
+
+        assert_eq!(store.get(&utxo_ref).unwrap(), Some(replacement));
+        assert_eq!(store.get(&missing_ref).unwrap(), None);
+        assert_eq!(store.utxos().unwrap().len(), 1);
+    }
+
+    // Removing a coin that replaced another must not bring the old one back.
+    fn overwrite_then_remove<S: UtxoStore>(store: &mut S) {
+        let (utxo_ref, original) = coin(7, 0);
+        let mut replacement = original.clone();
+        replacement.output.amount += 1;
+        store.add(utxo_ref, original.clone()).unwrap();
+
+        let mut batch = UtxoBatch::new();
+        batch.add(utxo_ref, replacement.clone());
+        batch.remove(utxo_ref);
+        store.apply_batch(batch).unwrap();
+        assert_eq!(store.get(&utxo_ref).unwrap(), None);
+
+        store.add(utxo_ref, original).unwrap();
+        store.add(utxo_ref, replacement.clone()).unwrap();
+        assert_eq!(store.remove(&utxo_ref).unwrap(), Some(replacement));
+        assert_eq!(store.get(&utxo_ref).unwrap(), None);
+        assert!(store.utxos().unwrap().is_empty());
+    }
+
+    fn iteration_sees_every_coin<S: UtxoStore>(store: &mut S) {
+        let coins: Vec<_> = (1..=5).flat_map(|seed| [coin(seed, 0), coin(seed, 1)]).collect();
+        for (utxo_ref, output) in &coins {
+            store.add(*utxo_ref, output.clone()).unwrap();
+        }
+        store.remove(&coins[0].0).unwrap();
+
+        let mut stored = store.utxos().unwrap();
+        stored.sort_by_key(|(utxo_ref, _)| (utxo_ref.txid, utxo_ref.vout));
+        assert_eq!(stored, coins[1..].to_vec());
+    }
+
+    fn lookup_by_script<S: UtxoStore>(store: &mut S) {
+        let (first_ref, first) = coin(6, 0);
+        let (second_ref, mut second) = coin(7, 1);
+        second.output.script_pubkey = first.output.script_pubkey.clone();
+        let (other_ref, other) = coin(8, 0);
+        store.add(first_ref, first.clone()).unwrap();
+        store.add(second_ref, second.clone()).unwrap();
+        store.add(other_ref, other).unwrap();
+
+        let mut found = store.utxos_for_script(&first.output.script_pubkey).unwrap();
+        found.sort_by_key(|(utxo_ref, _)| utxo_ref.txid);
+        assert_eq!(found, vec![(first_ref, first), (second_ref, second)]);
+        assert!(store.utxos_for_script(&[0x51]).unwrap().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utxo_key_round_trip() {
+        for vout in [0, 252, 253, 70_000, u32::MAX] {
+            let utxo_ref = UtxoRef { txid: [7u8; 32], vout };
+            assert_eq!(decode_utxo_key(&encode_utxo_key(&utxo_ref)).unwrap(), utxo_ref);
+        }
+        assert_eq!(encode_utxo_key(&UtxoRef { txid: [0u8; 32], vout: 1 }).len(), 33);
+        assert!(decode_utxo_key(&[0u8; 31]).is_err());
+    }
+}
@@ -1,99 +1,926 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use bitcoin_hashes::sha256d;
+
+use crate::mempool::fees::{EstimateMode, FeeEstimator};
+use crate::mempool::orphan::{OrphanPool, ORPHAN_EXPIRY};
+use crate::mempool::package;
+use crate::mempool::persist::{self, MempoolLoadReport};
+use crate::mempool::policy::{self, PolicyConfig, PolicyError};
+use crate::mempool::pool::{apply_fee_delta, feerate, Mempool, MempoolEntry, MempoolLimits, Txid, DEFAULT_MEMPOOL_EXPIRY};
+use crate::mempool::rbf::{self, RbfConfig};
+use crate::mempool::view::MempoolCoinView;
+
+use crate::transaction::batch::Payment;
+use crate::transaction::coin::{block_subsidy, Coin, MAX_MONEY};
 use crate::transaction::database::UtxoDatabase;
+use crate::transaction::encoding::{hex_encode, write_compact_size, write_var_bytes, ByteReader};
+use crate::transaction::script;
+use crate::transaction::stats::{StatsUtxoStore, UtxoStats};
+use crate::transaction::store::{UtxoBatch, UtxoStore};
+
+pub const SEQUENCE_FINAL: u32 = 0xffff_ffff;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct UtxoRef {
-    txid: [u8; 32],
-    vout: u32,
+    pub txid: [u8; 32],
+    pub vout: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TxInput {
-    utxo_ref: UtxoRef,
-    signature: [u8; 64],
+    pub utxo_ref: UtxoRef,
+    pub script_sig: Vec<u8>,
+    pub sequence: u32,
+    pub witness: Vec<Vec<u8>>,
 }
 
-#[derive(Debug)]
+impl TxInput {
+    pub fn new(utxo_ref: UtxoRef) -> Self {
+        TxInput {
+            utxo_ref,
+            script_sig: Vec::new(),
+            sequence: SEQUENCE_FINAL,
+            witness: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TxOutput {
-    address: [u8; 20],
-    amount: u64,
+    pub script_pubkey: Vec<u8>,
+    pub amount: u64,
 }
 
-#[derive(Debug)]
+impl TxOutput {
+    pub fn new(script_pubkey: Vec<u8>, amount: u64) -> Self {
+        TxOutput { script_pubkey, amount }
+    }
+
+    pub fn p2wpkh(pubkey_hash: &[u8; 20], amount: u64) -> Self {
+        TxOutput::new(script::p2wpkh(pubkey_hash), amount)
+    }
+
+    pub fn serialize_into(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.amount.to_le_bytes());
+        write_var_bytes(bytes, &self.script_pubkey);
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.serialize_into(&mut bytes);
+        bytes
+    }
+
+    pub fn deserialize_from(reader: &mut ByteReader) -> Result<Self, String> {
+        let amount = reader.read_u64()?;
+        let script_pubkey = reader.read_var_bytes()?.to_vec();
+        Ok(TxOutput { script_pubkey, amount })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Transaction {
-    version: u32,
-    inputs: Vec<TxInput>,
-    outputs: Vec<TxOutput>,
-    locktime: u32,
+    pub version: u32,
+    pub inputs: Vec<TxInput>,
+    pub outputs: Vec<TxOutput>,
+    pub locktime: u32,
+}
+
+impl Transaction {
+    // A coinbase has a single input spending the null outpoint.
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.len() == 1
+            && self.inputs[0].utxo_ref.txid == [0u8; 32]
+            && self.inputs[0].utxo_ref.vout == u32::MAX
+    }
+
+    pub fn has_witness(&self) -> bool {
+        self.inputs.iter().any(|input| !input.witness.is_empty())
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.encode(self.has_witness())
+    }
+
+    pub fn serialize_without_witness(&self) -> Vec<u8> {
+        self.encode(false)
+    }
+
+    fn encode(&self, include_witness: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        if include_witness {
+            bytes.extend_from_slice(&[0x00, 0x01]);
+        }
+        write_compact_size(&mut bytes, self.inputs.len() as u64);
+        for input in &self.inputs {
+            bytes.extend_from_slice(&input.utxo_ref.txid);
+            bytes.extend_from_slice(&input.utxo_ref.vout.to_le_bytes());
+            write_var_bytes(&mut bytes, &input.script_sig);
+            bytes.extend_from_slice(&input.sequence.to_le_bytes());
+        }
+        write_compact_size(&mut bytes, self.outputs.len() as u64);
+        for output in &self.outputs {
+            output.serialize_into(&mut bytes);
+        }
+        if include_witness {
+            for input in &self.inputs {
+                write_compact_size(&mut bytes, input.witness.len() as u64);
+                for item in &input.witness {
+                    write_var_bytes(&mut bytes, item);
+                }
+            }
+        }
+        bytes.extend_from_slice(&self.locktime.to_le_bytes());
+        bytes
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = ByteReader::new(bytes);
+        let tx = Transaction::deserialize_from(&mut reader)?;
+        if !reader.is_empty() {
+            return Err(format!("{} trailing bytes after transaction", reader.remaining()));
+        }
+        Ok(tx)
+    }
+
+    pub fn deserialize_from(reader: &mut ByteReader) -> Result<Self, String> {
+        let version = reader.read_u32()?;
+        let mut input_count = reader.read_compact_size()?;
+        let mut has_witness = false;
+        if input_count == 0 && reader.peek_u8() == Some(0x01) {
+            reader.read_u8()?;
+            has_witness = true;
+            input_count = reader.read_compact_size()?;
+        }
+
+        let mut inputs = Vec::new();
+        for _ in 0..input_count {
+            let txid = reader.read_array()?;
+            let vout = reader.read_u32()?;
+            let script_sig = reader.read_var_bytes()?.to_vec();
+            let sequence = reader.read_u32()?;
+            inputs.push(TxInput {
+                utxo_ref: UtxoRef { txid, vout },
+                script_sig,
+                sequence,
+                witness: Vec::new(),
+            });
+        }
+
+        let output_count = reader.read_compact_size()?;
+        let mut outputs = Vec::new();
+        for _ in 0..output_count {
+            outputs.push(TxOutput::deserialize_from(reader)?);
+        }
+
+        if has_witness {
+            for input in &mut inputs {
+                let item_count = reader.read_compact_size()?;
+                for _ in 0..item_count {
+                    input.witness.push(reader.read_var_bytes()?.to_vec());
+                }
+            }
+        }
+
+        let locktime = reader.read_u32()?;
+        Ok(Transaction { version, inputs, outputs, locktime })
+    }
+
+    pub fn size(&self) -> usize {
+        self.serialize().len()
+    }
+
+    pub fn weight(&self) -> usize {
+        self.serialize_without_witness().len() * 3 + self.size()
+    }
+
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(4)
+    }
+}
+
+fn burned_amount(transaction: &Transaction) -> u64 {
+    transaction.outputs.iter()
+        .filter(|output| script::is_unspendable(&output.script_pubkey))
+        .map(|output| output.amount)
+        .sum()
+}
+
+// Sums amounts the way consensus bounds them: no single amount nor the total may exceed
+// MAX_MONEY, which also keeps the sum from overflowing.
+fn money_total(amounts: impl IntoIterator<Item = u64>, what: &str) -> Result<u64, String> {
+    let mut total: u64 = 0;
+    for amount in amounts {
+        if amount > MAX_MONEY {
+            return Err(format!("{what} amount {amount} exceeds the maximum of {MAX_MONEY}"));
+        }
+        total = total.checked_add(amount)
+            .filter(|total| *total <= MAX_MONEY)
+            .ok_or_else(|| format!("{what} total exceeds the maximum of {MAX_MONEY}"))?;
+    }
+    Ok(total)
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
+
+pub fn generate_txid(transaction: &Transaction) -> [u8; 32] {
+    sha256d::Hash::hash(&transaction.serialize_without_witness()).to_byte_array()
+}
+
+// Why the processor refused a transaction. `Consensus` failures make the transaction
+// invalid against the current coins, `Policy` failures only keep it from being relayed,
+// and `Mempool` failures come from what the mempool already holds or its own limits:
+// duplicates, conflicts that cannot be replaced, package rules and fee floors. `Store`
+// means the coins could not be read at all and says nothing about the transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AcceptError {
+    Consensus(String),
+    Policy(PolicyError),
+    Mempool(String),
+    Store(String),
+}
+
+impl AcceptError {
+    // Rewrites the message, keeping the kind of failure.
+    fn map_message(self, f: impl FnOnce(String) -> String) -> Self {
+        match self {
+            AcceptError::Consensus(message) => AcceptError::Consensus(f(message)),
+            AcceptError::Policy(error) => AcceptError::Policy(PolicyError { message: f(error.message), ..error }),
+            AcceptError::Mempool(message) => AcceptError::Mempool(f(message)),
+            AcceptError::Store(message) => AcceptError::Store(f(message)),
+        }
+    }
+}
+
+impl fmt::Display for AcceptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AcceptError::Consensus(message)
+            | AcceptError::Mempool(message)
+            | AcceptError::Store(message) => f.write_str(message),
+            AcceptError::Policy(error) => error.fmt(f),
+        }
+    }
 }
 
-pub struct TransactionProcessor {
-    utxo_db: UtxoDatabase,
-    mempool: Vec<Transaction>,
+// Generic over the coin store so tests can run in memory while nodes keep their coins on
+// disk. `utxo_db` only ever holds confirmed coins: accepted transactions wait in the
+// mempool, which is layered over it as a coin view, until a block confirms them.
+pub struct TransactionProcessor<S: UtxoStore = UtxoDatabase> {
+    // Wrapped so the running coin totals answer supply queries without a scan.
+    utxo_db: StatsUtxoStore<S>,
+    mempool: Mempool,
+    orphans: OrphanPool,
+    // Orphans evicted for space or rejected on retry, until `take_dropped_orphans`.
+    dropped_orphans: Vec<(Txid, String)>,
+    mempool_expiry: u64,
+    policy: PolicyConfig,
+    fee_estimator: FeeEstimator,
+    rbf_config: RbfConfig,
+    height: u32,
+    burned: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Supply {
+    pub spendable: u64,
+    pub burned: u64,
+}
+
+impl Supply {
+    pub fn total(&self) -> u64 {
+        self.spendable + self.burned
+    }
 }
 
 impl TransactionProcessor {
     pub fn new() -> Self {
+        TransactionProcessor::with_stats_store(StatsUtxoStore::with_stats(UtxoDatabase::new(), UtxoStats::new()))
+    }
+}
+
+impl Default for TransactionProcessor {
+    fn default() -> Self {
+        TransactionProcessor::new()
+    }
+}
+
+impl<S: UtxoStore> TransactionProcessor<S> {
+    // Seeds the coin totals with one scan of `utxo_db`.
+    pub fn with_store(utxo_db: S) -> Result<Self, String> {
+        Ok(TransactionProcessor::with_stats_store(StatsUtxoStore::new(utxo_db)?))
+    }
+
+    pub fn with_stats_store(utxo_db: StatsUtxoStore<S>) -> Self {
         TransactionProcessor {
-            utxo_db: UtxoDatabase::new(),
-            mempool: Vec::new(),
+            utxo_db,
+            mempool: Mempool::new(),
+            orphans: OrphanPool::new(),
+            dropped_orphans: Vec::new(),
+            mempool_expiry: DEFAULT_MEMPOOL_EXPIRY,
+            policy: PolicyConfig::default(),
+            fee_estimator: FeeEstimator::new(),
+            rbf_config: RbfConfig::default(),
+            height: 0,
+            burned: 0,
         }
     }
 
-    pub fn validate_and_add_transaction(&mut self, tx: Transaction) -> Result<(), String> {
-        let mut total_input_amount = 0;
+    pub fn utxo_db(&self) -> &S {
+        self.utxo_db.base()
+    }
+
+    pub fn utxo_stats(&self) -> &UtxoStats {
+        self.utxo_db.stats()
+    }
+
+    // Height of the next block; coins it confirms are stamped with it and coinbase
+    // maturity is measured against it.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn set_height(&mut self, height: u32) {
+        self.height = height;
+    }
+
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    // Mempool transactions for a block of at most `max_vsize` vbytes, best ancestor
+    // feerate first. Each pick brings its unconfirmed ancestors along, parents before
+    // children; a package that no longer fits is skipped.
+    pub fn block_template(&self, max_vsize: u64) -> Vec<Transaction> {
+        let mut included = HashSet::new();
+        let mut template = Vec::new();
+        let mut total_vsize = 0;
+        for txid in self.mempool.sorted_by_ancestor_feerate() {
+            if included.contains(&txid) {
+                continue;
+            }
+            let mut package: Vec<&MempoolEntry> = self.mempool.ancestors(&txid).into_iter()
+                .chain([txid])
+                .filter(|member| !included.contains(member))
+                .filter_map(|member| self.mempool.get(&member))
+                .collect();
+            let package_vsize: u64 = package.iter().map(|entry| entry.vsize).sum();
+            if total_vsize + package_vsize > max_vsize {
+                continue;
+            }
+            total_vsize += package_vsize;
+            package.sort_by_key(|entry| (entry.ancestor_count, entry.txid));
+            for entry in package {
+                included.insert(entry.txid);
+                template.push(entry.tx.clone());
+            }
+        }
+        template
+    }
+
+    // Confirmed coins minus those spent in the mempool, plus unconfirmed outputs if asked
+    // for. Wallets select coins from this rather than from `utxo_db`.
+    pub fn coin_view(&self, include_unconfirmed: bool) -> MempoolCoinView<'_, S> {
+        MempoolCoinView::new(self.utxo_db.base(), &self.mempool, include_unconfirmed)
+    }
+
+    pub fn orphans(&self) -> &OrphanPool {
+        &self.orphans
+    }
+
+    pub fn set_orphan_limits(&mut self, max_count: usize, max_size: u64) {
+        self.orphans = OrphanPool::with_limits(max_count, max_size);
+    }
+
+    // Orphans that left the pool without being accepted since the last call, with why.
+    pub fn take_dropped_orphans(&mut self) -> Vec<(Txid, String)> {
+        std::mem::take(&mut self.dropped_orphans)
+    }
+
+    fn add_orphan(&mut self, transaction: Transaction, time: u64) -> Result<(), String> {
+        let evicted = self.orphans.add(transaction, time)?;
+        self.dropped_orphans.extend(evicted.into_iter().map(|txid| (txid, "Orphan pool full".to_string())));
+        Ok(())
+    }
+
+    // Seconds a transaction may wait in the mempool before it is dropped.
+    pub fn set_mempool_expiry(&mut self, expiry: u64) {
+        self.mempool_expiry = expiry;
+    }
+
+    // Drops mempool transactions older than the expiry, with their descendants, and
+    // stale orphans. The coins expired transactions spent show up in the coin view again.
+    pub fn expire_mempool(&mut self, now: u64) -> Vec<Txid> {
+        self.orphans.expire(now.saturating_sub(ORPHAN_EXPIRY));
+        self.mempool.expire(now.saturating_sub(self.mempool_expiry))
+            .into_iter()
+            .map(|entry| entry.txid)
+            .collect()
+    }
+
+    pub fn fee_estimator(&self) -> &FeeEstimator {
+        &self.fee_estimator
+    }
+
+    // Feerate in sat/vB for confirming within `target_blocks`, never below what the
+    // mempool currently accepts.
+    pub fn estimate_fee(&self, target_blocks: usize, mode: EstimateMode) -> Option<f64> {
+        let estimate = self.fee_estimator.estimate_fee(target_blocks, mode)?;
+        Some(estimate.max(self.mempool.min_fee_rate(unix_time()) as f64 / 1_000.0))
+    }
+
+    // Whole sat/vB for a wallet to pay, rounded up from the estimate. Without enough
+    // history it falls back to the lowest feerate the mempool relays.
+    pub fn fee_per_vbyte(&self, target_blocks: usize, mode: EstimateMode) -> u64 {
+        let relay_floor = self.mempool.min_fee_rate(unix_time())
+            .max(self.policy.min_relay_fee)
+            .div_ceil(1_000);
+        self.estimate_fee(target_blocks, mode)
+            .map_or(relay_floor, |estimate| (estimate.ceil() as u64).max(relay_floor))
+    }
+
+    pub fn save_fee_estimates(&self, path: &Path) -> Result<(), String> {
+        self.fee_estimator.save(path)
+    }
+
+    // A missing file leaves the estimator empty.
+    pub fn load_fee_estimates(&mut self, path: &Path) -> Result<(), String> {
+        if path.exists() {
+            self.fee_estimator = FeeEstimator::load(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_mempool_limits(&mut self, limits: MempoolLimits) {
+        self.mempool.set_limits(limits);
+    }
+
+    pub fn policy(&self) -> &PolicyConfig {
+        &self.policy
+    }
+
+    // Relay policy only governs what enters the mempool; `connect_block` ignores it.
+    pub fn set_policy(&mut self, policy: PolicyConfig) {
+        self.policy = policy;
+    }
+
+    pub fn set_rbf_config(&mut self, config: RbfConfig) {
+        self.rbf_config = config;
+    }
+
+    // Returns the txids of any mempool transactions the new one replaced.
+    pub fn validate_and_add_transaction(&mut self, transaction: Transaction) -> Result<Vec<Txid>, AcceptError> {
+        self.validate_and_add_transaction_at(transaction, unix_time())
+    }
+
+    // `time` is the arrival time recorded in the mempool, in seconds since the epoch. A
+    // transaction spending outputs nobody has seen yet is held in the orphan pool and
+    // retried once its parents are accepted.
+    pub fn validate_and_add_transaction_at(&mut self, transaction: Transaction, time: u64) -> Result<Vec<Txid>, AcceptError> {
+        self.expire_mempool(time);
+        let txid = generate_txid(&transaction);
+        if self.has_missing_inputs(&transaction).map_err(AcceptError::Store)? {
+            self.add_orphan(transaction, time).map_err(AcceptError::Mempool)?;
+            return Err(AcceptError::Mempool(format!(
+                "Transaction {} has missing inputs and is held as an orphan",
+                hex_encode(&txid)
+            )));
+        }
+        let replaced = self.accept_single(transaction, time)?;
+        self.process_orphans(txid, time).map_err(|e| AcceptError::Store(format!(
+            "Transaction {} was accepted but its orphans could not be checked: {e}",
+            hex_encode(&txid)
+        )))?;
+        Ok(replaced)
+    }
+
+    fn has_missing_inputs(&self, transaction: &Transaction) -> Result<bool, String> {
+        let view = self.coin_view(true);
         for input in &transaction.inputs {
-            if let Some(output) = self.utxo_db.get_utxo(&input.utxo_ref) {
-                total_input_amount += output.amount;
+            if view.coin(&input.utxo_ref)?.is_none() && !self.mempool.contains(&input.utxo_ref.txid) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // Retries the orphans waiting on `parent`, and in turn those waiting on any orphan
+    // accepted along the way. Orphans still missing another parent go back in the pool;
+    // those that fail for any other reason are dropped and recorded. A store failure
+    // stops the walk with the orphan left in the pool.
+    fn process_orphans(&mut self, parent: Txid, time: u64) -> Result<(), String> {
+        let mut queue = VecDeque::from([parent]);
+        while let Some(parent) = queue.pop_front() {
+            for txid in self.orphans.children_of(&parent) {
+                let Some(orphan) = self.orphans.remove(&txid) else { continue };
+                let missing_inputs = match self.has_missing_inputs(&orphan.tx) {
+                    Ok(missing_inputs) => missing_inputs,
+                    Err(e) => {
+                        self.add_orphan(orphan.tx, orphan.time)?;
+                        return Err(e);
+                    }
+                };
+                if missing_inputs {
+                    if let Err(e) = self.add_orphan(orphan.tx, orphan.time) {
+                        self.dropped_orphans.push((txid, e));
+                    }
+                    continue;
+                }
+                match self.accept_single(orphan.tx.clone(), time) {
+                    Ok(_) => queue.push_back(txid),
+                    Err(AcceptError::Store(e)) => {
+                        self.add_orphan(orphan.tx, orphan.time)?;
+                        return Err(e);
+                    }
+                    Err(e) => self.dropped_orphans.push((txid, e.to_string())),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn accept_single(&mut self, transaction: Transaction, time: u64) -> Result<Vec<Txid>, AcceptError> {
+        let txid = generate_txid(&transaction);
+        // Checked first, or the transaction would be treated as conflicting with itself.
+        if self.mempool.contains(&txid) {
+            return Err(AcceptError::Mempool(format!("Transaction {} is already in the mempool", hex_encode(&txid))));
+        }
+        let conflicts: HashSet<Txid> = transaction.inputs.iter()
+            .filter_map(|input| self.mempool.spender(&input.utxo_ref).map(|entry| entry.txid))
+            .collect();
+        if conflicts.is_empty() {
+            let txid = self.accept_transaction(transaction, time, true)?;
+            self.trim_mempool(time, &[txid]).map_err(AcceptError::Mempool)?;
+            self.track_fee(&txid);
+            return Ok(Vec::new());
+        }
+
+        // Everything the replacement can be judged on by itself is checked before the
+        // originals are touched.
+        let fee = self.check_transaction(&transaction, time, true)?;
+        let modified_fee = apply_fee_delta(fee, self.mempool.fee_delta(&txid));
+        let replaced = rbf::check_replacement(&self.mempool, &transaction, modified_fee, &conflicts, &self.rbf_config)
+            .map_err(AcceptError::Mempool)?;
+        self.mempool.check_replacement_limits(&transaction, &replaced).map_err(AcceptError::Mempool)?;
+        let removed = self.mempool.remove_set(&replaced);
+        let accepted = self.mempool.add(transaction, fee, time)
+            .and_then(|txid| self.trim_mempool(time, &[txid]).map(|_| txid));
+        match accepted {
+            Ok(txid) => {
+                self.track_fee(&txid);
+                Ok(removed.into_iter().map(|entry| entry.txid).collect())
+            }
+            Err(e) => {
+                let lost = self.restore_replaced(removed).map_err(AcceptError::Store)?;
+                if !lost.is_empty() {
+                    return Err(AcceptError::Mempool(format!(
+                        "{e}; replaced transactions could not be restored: {}",
+                        lost.join(", ")
+                    )));
+                }
+                Err(AcceptError::Mempool(e))
+            }
+        }
+    }
+
+    // Puts back transactions taken out for a replacement that did not go through. They
+    // were accepted before, so they go straight into the mempool without being judged
+    // again; only those whose parents were evicted in the meantime are lost.
+    fn restore_replaced(&mut self, removed: Vec<MempoolEntry>) -> Result<Vec<String>, String> {
+        let mut lost = Vec::new();
+        for entry in removed {
+            let restored = if self.has_missing_inputs(&entry.tx)? {
+                Err("parent evicted".to_string())
             } else {
-                return Err(format!("Input references non-existent UTXO: {:?}", input.utxo_ref));
+                self.mempool.add(entry.tx, entry.fee, entry.time)
+            };
+            if let Err(reason) = restored {
+                lost.push(format!("{} ({reason})", hex_encode(&entry.txid)));
             }
         }
+        Ok(lost)
+    }
 
-        let total_output_amount: u64 = transaction.outputs.iter()
-            .map(|output| output.amount)
-            .sum();
+    // Accepts a child and its unconfirmed parents, parents first, as a unit. Members are
+    // judged on the package's combined feerate, so a child paying enough can carry a
+    // parent that is below the mempool minimum on its own. Returns the txids of the
+    // members added; members already in the mempool are skipped.
+    pub fn submit_package(&mut self, package: Vec<Transaction>, time: u64) -> Result<Vec<Txid>, AcceptError> {
+        let txids = package::check_package(&package).map_err(AcceptError::Mempool)?;
+        self.expire_mempool(time);
+        let mut package_outputs: HashMap<UtxoRef, u64> = HashMap::new();
+        let mut package_fee = 0;
+        let mut package_vsize = 0;
+        for (index, tx) in package.iter().enumerate() {
+            if self.mempool.contains(&txids[index]) {
+                continue;
+            }
+            let mut input_amounts = Vec::with_capacity(tx.inputs.len());
+            for input in &tx.inputs {
+                let amount = match package_outputs.get(&input.utxo_ref) {
+                    Some(amount) => *amount,
+                    None => self.coin_view(true).coin(&input.utxo_ref).map_err(AcceptError::Store)?
+                        .map(|coin| coin.output.amount)
+                        .ok_or_else(|| AcceptError::Consensus(format!(
+                            "Package member {index} ({}) spends non-existent UTXO {:?}",
+                            hex_encode(&txids[index]), input.utxo_ref
+                        )))?,
+                };
+                input_amounts.push(amount);
+            }
+            let total_input_amount = money_total(input_amounts, "Input").map_err(AcceptError::Consensus)?;
+            let total_output_amount = money_total(tx.outputs.iter().map(|output| output.amount), "Output")
+                .map_err(AcceptError::Consensus)?;
+            let fee = total_input_amount.checked_sub(total_output_amount).ok_or_else(|| AcceptError::Consensus(format!(
+                "Package member {index} ({}) spends more than its inputs: inputs={}, outputs={}",
+                hex_encode(&txids[index]), total_input_amount, total_output_amount
+            )))?;
+            package_fee += apply_fee_delta(fee, self.mempool.fee_delta(&txids[index]));
+            package_vsize += tx.vsize() as u64;
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                package_outputs.insert(UtxoRef { txid: txids[index], vout: vout as u32 }, output.amount);
+            }
+        }
+
+        let min_fee_rate = self.mempool.min_fee_rate(time).max(self.policy.min_relay_fee);
+        if package_vsize > 0 && feerate(package_fee, package_vsize) < min_fee_rate {
+            return Err(AcceptError::Mempool(format!(
+                "Package feerate {} sat/kvB is below the mempool minimum of {min_fee_rate} sat/kvB",
+                feerate(package_fee, package_vsize)
+            )));
+        }
+
+        let mut added = Vec::new();
+        for (index, tx) in package.into_iter().enumerate() {
+            if self.mempool.contains(&txids[index]) {
+                continue;
+            }
+            match self.accept_transaction(tx, time, false) {
+                Ok(txid) => added.push(txid),
+                Err(e) => {
+                    self.mempool.remove_set(&added.iter().copied().collect());
+                    return Err(e.map_message(|message| {
+                        format!("Package member {index} ({}) rejected: {message}", hex_encode(&txids[index]))
+                    }));
+                }
+            }
+        }
+        self.trim_mempool(time, &added).map_err(AcceptError::Mempool)?;
+        for txid in &added {
+            self.process_orphans(*txid, time).map_err(|e| AcceptError::Store(format!(
+                "Package was accepted but its orphans could not be checked: {e}"
+            )))?;
+        }
+        Ok(added)
+    }
+
+    // See `Mempool::prioritise_transaction`; the transaction need not have arrived yet.
+    pub fn prioritise_transaction(&mut self, txid: &Txid, fee_delta: i64) {
+        self.mempool.prioritise_transaction(txid, fee_delta);
+    }
+
+    pub fn clear_fee_delta(&mut self, txid: &Txid) {
+        self.mempool.clear_fee_delta(txid);
+    }
+
+    pub fn clear_fee_deltas(&mut self) {
+        self.mempool.clear_fee_deltas();
+    }
+
+    pub fn save_mempool(&self, path: &Path) -> Result<usize, String> {
+        persist::dump_mempool(&self.mempool, path)
+    }
+
+    // Replays a saved mempool against the current coins, keeping each transaction's
+    // original arrival time and fee delta, and restores the deltas of transactions that
+    // had not arrived yet. Transactions that no longer validate, say
+    // because a block spent their inputs while the node was down, are dropped and
+    // reported rather than failing the load. A missing file is an empty mempool.
+    pub fn load_mempool(&mut self, path: &Path, now: u64) -> Result<MempoolLoadReport, String> {
+        let mut report = MempoolLoadReport::default();
+        if !path.exists() {
+            return Ok(report);
+        }
+        let saved_mempool = persist::read_mempool_file(path)?;
+        for (txid, fee_delta) in &saved_mempool.fee_deltas {
+            self.mempool.prioritise_transaction(txid, *fee_delta);
+        }
+        let cutoff = now.saturating_sub(self.mempool_expiry);
+        for saved in saved_mempool.transactions {
+            let txid = generate_txid(&saved.tx);
+            self.mempool.prioritise_transaction(&txid, saved.fee_delta);
+            if saved.time < cutoff {
+                report.dropped.push((txid, "Expired".to_string()));
+                continue;
+            }
+            match self.accept_transaction(saved.tx, saved.time, false) {
+                Ok(txid) => report.accepted.push(txid),
+                Err(reason) => report.dropped.push((txid, reason.to_string())),
+            }
+        }
+        for entry in self.mempool.trim_to_size(now) {
+            report.accepted.retain(|txid| *txid != entry.txid);
+            report.dropped.push((entry.txid, "Mempool full".to_string()));
+        }
+        Ok(report)
+    }
+
+    // Only transactions relayed on their own feed the estimator: a package member's
+    // feerate says little about what confirmed it, and reloaded transactions have
+    // already been waiting for an unknown number of blocks.
+    fn track_fee(&mut self, txid: &Txid) {
+        if let Some(entry) = self.mempool.get(txid) {
+            self.fee_estimator.track(*txid, entry.fee, entry.vsize, self.height);
+        }
+    }
+
+    // Looks the inputs up in the coin view, ignoring any mempool transaction that already
+    // spends them, and returns the fee along with the coins spent.
+    fn check_inputs(&self, transaction: &Transaction) -> Result<(u64, Vec<Coin>), AcceptError> {
+        let view = self.coin_view(true);
+        let mut spent = Vec::with_capacity(transaction.inputs.len());
+        let mut seen = HashSet::with_capacity(transaction.inputs.len());
+        for input in &transaction.inputs {
+            if !seen.insert(input.utxo_ref) {
+                return Err(AcceptError::Consensus(format!("Transaction spends {:?} more than once", input.utxo_ref)));
+            }
+            let Some(coin) = view.coin(&input.utxo_ref).map_err(AcceptError::Store)? else {
+                return Err(AcceptError::Consensus(format!("Input references non-existent UTXO: {:?}", input.utxo_ref)));
+            };
+            if !coin.is_mature(self.height) {
+                return Err(AcceptError::Consensus(format!(
+                    "Input spends immature coinbase output {:?} created at height {}",
+                    input.utxo_ref, coin.height
+                )));
+            }
+            spent.push(coin);
+        }
+
+        let total_input_amount = money_total(spent.iter().map(|coin| coin.output.amount), "Input")
+            .map_err(AcceptError::Consensus)?;
+        let total_output_amount = money_total(transaction.outputs.iter().map(|output| output.amount), "Output")
+            .map_err(AcceptError::Consensus)?;
 
         if total_input_amount < total_output_amount {
-            return Err(format!(
+            return Err(AcceptError::Consensus(format!(
                 "Insufficient funds: inputs={}, outputs={}",
                 total_input_amount, total_output_amount
-            ));
+            )));
         }
+        Ok((total_input_amount - total_output_amount, spent))
+    }
 
-        for input in &transaction.inputs {
-            self.utxo_db.remove_utxo(&input.utxo_ref);
+    // `enforce_min_fee` is off for package members, which are judged on the package
+    // feerate, and for transactions reloaded from disk.
+    fn accept_transaction(&mut self, transaction: Transaction, time: u64, enforce_min_fee: bool) -> Result<Txid, AcceptError> {
+        let fee = self.check_transaction(&transaction, time, enforce_min_fee)?;
+        self.mempool.add(transaction, fee, time).map_err(AcceptError::Mempool)
+    }
+
+    // Consensus checks first, then relay policy. Returns the fee.
+    fn check_transaction(&self, transaction: &Transaction, time: u64, enforce_min_fee: bool) -> Result<u64, AcceptError> {
+        let (fee, spent) = self.check_inputs(transaction)?;
+        policy::check_standard(transaction, &spent, &self.policy).map_err(AcceptError::Policy)?;
+        let modified_fee = apply_fee_delta(fee, self.mempool.fee_delta(&generate_txid(transaction)));
+        let vsize = transaction.vsize() as u64;
+        if enforce_min_fee {
+            policy::check_min_relay_fee(modified_fee, vsize, &self.policy).map_err(AcceptError::Policy)?;
+        }
+        let min_fee_rate = self.mempool.min_fee_rate(time);
+        if enforce_min_fee && feerate(modified_fee, vsize) < min_fee_rate {
+            return Err(AcceptError::Mempool(format!(
+                "Mempool minimum fee not met: {} sat/kvB is below {min_fee_rate} sat/kvB",
+                feerate(modified_fee, vsize)
+            )));
         }
+        Ok(fee)
+    }
 
-        let txid = generate_txid(&transaction);
-        for (vout, output) in transaction.outputs.iter().enumerate() {
-            let utxo_ref = UtxoRef {
-                txid,
-                vout: vout as u32,
-            };
-            self.utxo_db.add_utxo(utxo_ref, output.clone());
+    // Fails if any of the just `accepted` transactions had to be evicted to make room.
+    // Evicted transactions only ever lived in the mempool, so the coins they spent are
+    // visible again as soon as they are gone.
+    fn trim_mempool(&mut self, time: u64, accepted: &[Txid]) -> Result<(), String> {
+        let evicted = self.mempool.trim_to_size(time);
+        if evicted.iter().any(|entry| accepted.contains(&entry.txid)) {
+            return Err("Mempool full: transaction feerate too low to stay in the pool".to_string());
         }
+        Ok(())
+    }
 
-        self.mempool.push(transaction);
+    // Applies a block's transactions to the confirmed coins in one batch and moves on to
+    // the next height. Relay policy does not apply, but consensus does: inputs must exist
+    // and be mature, no transaction may pay out more than it spends, and the coinbase may
+    // claim no more than the subsidy plus the block's fees. Confirmed transactions leave
+    // the mempool; mempool transactions that spend the same coins are dropped together
+    // with their descendants.
+    pub fn connect_block(&mut self, block_hash: [u8; 32], transactions: &[Transaction]) -> Result<(), String> {
+        let mut batch = UtxoBatch::new();
+        // Coins created earlier in the block, looked up before the confirmed set.
+        let mut created: HashMap<UtxoRef, Coin> = HashMap::new();
+        let mut spent = HashSet::new();
+        let mut burned = 0;
+        let mut fees = 0;
+        let mut coinbase_amount = 0;
+        for (index, tx) in transactions.iter().enumerate() {
+            let txid = generate_txid(tx);
+            let total_output_amount = money_total(tx.outputs.iter().map(|output| output.amount), "Output")
+                .map_err(|e| format!("Block transaction {}: {e}", hex_encode(&txid)))?;
+            if tx.is_coinbase() {
+                if index != 0 {
+                    return Err(format!("Block transaction {} is a coinbase but not the first", hex_encode(&txid)));
+                }
+                coinbase_amount = total_output_amount;
+            } else {
+                let mut input_amounts = Vec::with_capacity(tx.inputs.len());
+                for input in &tx.inputs {
+                    let coin = if spent.insert(input.utxo_ref) {
+                        match created.get(&input.utxo_ref) {
+                            Some(coin) => Some(coin.clone()),
+                            None => self.utxo_db.get(&input.utxo_ref)?,
+                        }
+                    } else {
+                        None
+                    };
+                    let Some(coin) = coin else {
+                        return Err(format!(
+                            "Block transaction {} spends missing or already spent {:?}",
+                            hex_encode(&txid), input.utxo_ref
+                        ));
+                    };
+                    if !coin.is_mature(self.height) {
+                        return Err(format!(
+                            "Block transaction {} spends immature coinbase output {:?} created at height {}",
+                            hex_encode(&txid), input.utxo_ref, coin.height
+                        ));
+                    }
+                    input_amounts.push(coin.output.amount);
+                    batch.remove(input.utxo_ref);
+                }
+                let total_input_amount = money_total(input_amounts, "Input")
+                    .map_err(|e| format!("Block transaction {}: {e}", hex_encode(&txid)))?;
+                let fee = total_input_amount.checked_sub(total_output_amount).ok_or_else(|| format!(
+                    "Block transaction {} spends more than its inputs: inputs={}, outputs={}",
+                    hex_encode(&txid), total_input_amount, total_output_amount
+                ))?;
+                fees = money_total([fees, fee], "Fee")?;
+            }
+            for (vout, output) in tx.outputs.iter().enumerate() {
+                if script::is_unspendable(&output.script_pubkey) {
+                    continue;
+                }
+                let utxo_ref = UtxoRef { txid, vout: vout as u32 };
+                let coin = Coin::new(output.clone(), self.height, tx.is_coinbase());
+                created.insert(utxo_ref, coin.clone());
+                batch.add(utxo_ref, coin);
+            }
+            burned += burned_amount(tx);
+        }
+        let allowed = block_subsidy(self.height) + fees;
+        if coinbase_amount > allowed {
+            return Err(format!(
+                "Coinbase pays {coinbase_amount}, more than the subsidy and fees of {allowed}"
+            ));
+        }
+        batch.set_best_block(block_hash);
+        self.utxo_db.apply_batch(batch)?;
+        self.burned += burned;
+
+        let confirmed: Vec<Txid> = transactions.iter().map(generate_txid).collect();
+        self.mempool.remove_set(&confirmed.iter().copied().collect());
+        for txid in &confirmed {
+            self.mempool.clear_fee_delta(txid);
+        }
+        for utxo_ref in &spent {
+            if let Some(conflict) = self.mempool.spender(utxo_ref).map(|entry| entry.txid) {
+                self.mempool.remove_with_descendants(&conflict);
+            }
+        }
+        self.orphans.remove_for_block(&confirmed, &spent);
+        self.fee_estimator.process_block(self.height, &confirmed, |txid| self.mempool.contains(txid));
+        self.height += 1;
         Ok(())
     }
 
     pub fn get_transaction_by_input<'a>(&'a self, utxo_ref: &UtxoRef) -> Option<&'a Transaction> {
-        self.mempool.iter().find(|tx| {
-            tx.inputs.iter().any(|input| input.utxo_ref == *utxo_ref)
-        })
+        self.mempool.spender(utxo_ref).map(|entry| &entry.tx)
     }
 
-    pub fn get_total_supply(&self) -> u64 {
-        self.utxo_db.get_all_utxos().values()
-            .map(|output| output.amount)
-            .sum()
+    // Covers confirmed coins only. Burned value is not in the coin store, so it only
+    // counts blocks connected by this processor.
+    pub fn get_total_supply(&self) -> Result<Supply, String> {
+        Ok(Supply { spendable: self.utxo_db.stats().total_amount, burned: self.burned })
     }
 }
 
-struct Wallet {
-    address: [u8; 20],
+pub struct Wallet {
+    pub address: [u8; 20],
 }
 
 impl Wallet {
@@ -101,56 +928,475 @@ impl Wallet {
         Wallet { address }
     }
 
-    pub fn create_transaction(
+    pub fn script_pubkey(&self) -> Vec<u8> {
+        script::p2wpkh(&self.address)
+    }
+
+    pub fn spendable_utxos<S: UtxoStore>(&self, utxo_db: &S) -> Result<Vec<(UtxoRef, u64)>, String> {
+        Ok(utxo_db.utxos_for_script(&self.script_pubkey())?
+            .into_iter()
+            .map(|(utxo_ref, coin)| {
+                (utxo_ref, coin.output.amount)
+            })
+            .collect())
+    }
+
+    pub fn create_transaction<S: UtxoStore>(
         &self,
-        utxo_db: &UtxoDatabase,
+        utxo_db: &S,
         recipient: [u8; 20],
         amount: u64,
+        fee_per_vbyte: u64,
     ) -> Result<Transaction, String> {
-        let our_utxos: Vec<(UtxoRef, &TxOutput)> = utxo_db.get_all_utxos()
-            .iter()
-            .filter(|(_, output)| {
-                output.address == self.address
-            })
-            .map(|(utxo_ref, output)| {
-                (*utxo_ref, output)
-            })
-            .collect();
+        self.create_batch_transaction(utxo_db, &[Payment::to_address(&recipient, amount)], fee_per_vbyte)
+    }
+}
 
-        let total_available: u64 = our_utxos.iter()
-            .map(|(_, output)| {
-                output.amount
-            })
-            .sum();
+// Builders for tests that only care about which coins a transaction spends and what it
+// pays out.
+#[cfg(test)]
+pub mod testing {
+    use super::{Coin, Transaction, TransactionProcessor, TxInput, TxOutput, UtxoDatabase, UtxoRef};
 
-        if total_available < amount {
-            return Err(format!(
-                "Insufficient funds: available={}, required={}",
-                total_available, amount
-            ));
+    // One P2WPKH output per amount, all to the same key.
+    pub fn spend(inputs: &[UtxoRef], amounts: &[u64]) -> Transaction {
+        Transaction {
+            version: 2,
+            inputs: inputs.iter().copied().map(TxInput::new).collect(),
+            outputs: amounts.iter().map(|amount| TxOutput::p2wpkh(&[2u8; 20], *amount)).collect(),
+            locktime: 0,
         }
+    }
 
-        let mut selected_utxos = Vec::new();
-        let mut selected_amount = 0;
+    pub fn pay(input: UtxoRef, amount: u64) -> Transaction {
+        spend(&[input], &[amount])
+    }
 
-        for (utxo_ref, output) in our_utxos {
-            selected_utxos.push(utxo_ref);
-            selected_amount += output.amount;
-            if selected_amount >= amount {
-                break;
-            }
+    // A processor over an in-memory store holding a confirmed 100,000 sat coin at each ref.
+    pub fn funded_processor(utxo_refs: &[UtxoRef]) -> TransactionProcessor {
+        let mut utxo_db = UtxoDatabase::new();
+        for utxo_ref in utxo_refs {
+            utxo_db.add_utxo(*utxo_ref, Coin::new(TxOutput::p2wpkh(&[1u8; 20], 100_000), 1, false));
         }
+        TransactionProcessor::with_store(utxo_db).unwrap()
+    }
+}
 
-        let inputs: Vec<TxInput> = selected_utxos
-            .into_iter()
-            .map(|utxo_ref| {
-                let signature = [0u8; 64];
-                TxInput { utxo_ref, signature }
-            })
-            .collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::testing::{funded_processor, pay, spend};
+    use crate::mempool::orphan::DEFAULT_MAX_ORPHAN_SIZE;
+    use crate::mempool::policy::PolicyCode;
+    use crate::transaction::sled_db::SledUtxoDatabase;
+
+    #[test]
+    fn test_unspendable_outputs_are_burned_not_stored() {
+        let funding_ref = UtxoRef { txid: [1u8; 32], vout: 0 };
+        let mut utxo_db = UtxoDatabase::new();
+        utxo_db.add_utxo(funding_ref, Coin::new(TxOutput::p2wpkh(&[1u8; 20], 10_000), 1, false));
+        let mut processor = TransactionProcessor::with_store(utxo_db).unwrap();
+        let tx = Transaction {
+            version: 2,
+            inputs: vec![TxInput::new(funding_ref)],
+            outputs: vec![
+                TxOutput::p2wpkh(&[2u8; 20], 6_000),
+                TxOutput::new(vec![script::OP_RETURN, 0x02, 0xbe, 0xef], 1_000),
+                TxOutput::new(vec![0x51; script::MAX_SCRIPT_SIZE + 1], 500),
+                TxOutput::new(vec![script::OP_RETURN], 0),
+            ],
+            locktime: 0,
+        };
+        let err = processor.validate_and_add_transaction(tx.clone()).unwrap_err();
+        assert!(matches!(&err, AcceptError::Policy(error) if error.code == PolicyCode::ScriptPubKey), "{err}");
+        assert!(err.to_string().starts_with("Non-standard transaction (scriptpubkey)"), "{err}");
+        // Relay policy does not apply to blocks.
+        let txid = generate_txid(&tx);
+        processor.connect_block([7u8; 32], &[tx]).unwrap();
 
-        let mut outputs = vec![
-            TxOutput { address: recipient, amount }
+        assert!(processor.utxo_db().get(&UtxoRef { txid, vout: 0 }).unwrap().is_some());
+        for vout in 1..4 {
+            assert_eq!(processor.utxo_db().get(&UtxoRef { txid, vout }).unwrap(), None);
+        }
+        let supply = processor.get_total_supply().unwrap();
+        assert_eq!(supply, Supply { spendable: 6_000, burned: 1_500 });
+        assert_eq!(supply.total(), 7_500);
+        assert_eq!(*processor.utxo_stats(), UtxoStats::from_store(processor.utxo_db()).unwrap());
+    }
+
+    #[test]
+    fn test_eviction_restores_spent_coins() {
+        let funding: Vec<UtxoRef> = (1..=2u8).map(|i| UtxoRef { txid: [i; 32], vout: 0 }).collect();
+        let mut processor = funded_processor(&funding);
+
+        let cheap = pay(funding[0], 99_900);
+        let cheap_txid = generate_txid(&cheap);
+        processor.validate_and_add_transaction_at(cheap.clone(), 10).unwrap();
+        let child = pay(UtxoRef { txid: cheap_txid, vout: 0 }, 99_650);
+        processor.validate_and_add_transaction_at(child.clone(), 10).unwrap();
+        let vsize = cheap.vsize() as u64;
+        processor.set_mempool_limits(MempoolLimits { max_size: vsize * 2, ..MempoolLimits::default() });
+
+        processor.validate_and_add_transaction_at(pay(funding[1], 90_000), 20).unwrap();
+        assert_eq!(processor.mempool().len(), 1);
+        let view = processor.coin_view(true);
+        assert_eq!(view.get(&funding[0]).unwrap().unwrap().output.amount, 100_000);
+        assert_eq!(view.get(&UtxoRef { txid: cheap_txid, vout: 0 }).unwrap(), None);
+        assert_eq!(view.get(&UtxoRef { txid: generate_txid(&child), vout: 0 }).unwrap(), None);
+
+        let err = processor.validate_and_add_transaction_at(cheap, 30).unwrap_err();
+        assert!(err.to_string().contains("minimum fee"), "{err}");
+    }
+
+    #[test]
+    fn test_fee_bump_replaces_stuck_transaction() {
+        let funding = UtxoRef { txid: [1u8; 32], vout: 0 };
+        let mut processor = funded_processor(&[funding]);
+        let bump = |amount: u64| {
+            let mut tx = pay(funding, amount);
+            tx.inputs[0].sequence = rbf::MAX_BIP125_RBF_SEQUENCE;
+            tx
+        };
+
+        let stuck = bump(99_900);
+        assert_eq!(processor.validate_and_add_transaction_at(stuck.clone(), 1).unwrap(), Vec::<Txid>::new());
+        assert!(processor.validate_and_add_transaction_at(bump(99_899), 2).is_err());
+
+        let err = processor.validate_and_add_transaction_at(stuck.clone(), 2).unwrap_err();
+        assert!(matches!(&err, AcceptError::Mempool(message) if message.contains("already in the mempool")), "{err}");
+
+        let bumped = bump(99_000);
+        let replaced = processor.validate_and_add_transaction_at(bumped.clone(), 2).unwrap();
+        assert_eq!(replaced, vec![generate_txid(&stuck)]);
+        assert_eq!(processor.mempool().len(), 1);
+        assert_eq!(processor.get_transaction_by_input(&funding), Some(&bumped));
+        let view = processor.coin_view(true);
+        assert_eq!(view.get(&UtxoRef { txid: generate_txid(&stuck), vout: 0 }).unwrap(), None);
+        assert!(view.get(&UtxoRef { txid: generate_txid(&bumped), vout: 0 }).unwrap().unwrap().is_unconfirmed());
+
+        // A replacement refused by policy is refused before the original is touched.
+        processor.set_policy(PolicyConfig { max_standard_weight: 1, ..PolicyConfig::default() });
+        let err = processor.validate_and_add_transaction_at(bump(98_000), 3).unwrap_err();
+        assert!(matches!(err, AcceptError::Policy(_)), "{err}");
+        assert_eq!(processor.get_transaction_by_input(&funding), Some(&bumped));
+
+        // One that is accepted but then trimmed for lack of room puts the original back.
+        processor.set_policy(PolicyConfig::default());
+        processor.set_mempool_limits(MempoolLimits { max_size: 1, ..MempoolLimits::default() });
+        let err = processor.validate_and_add_transaction_at(bump(98_000), 3).unwrap_err();
+        assert!(err.to_string().contains("Mempool full"), "{err}");
+        assert_eq!(processor.get_transaction_by_input(&funding), Some(&bumped));
+        assert_eq!(processor.mempool().len(), 1);
+    }
+
+    #[test]
+    fn test_child_pays_for_parent_in_package() {
+        let funding: Vec<UtxoRef> = (1..=3u8).map(|i| UtxoRef { txid: [i; 32], vout: 0 }).collect();
+        let mut processor = funded_processor(&funding);
+
+        // Raise the mempool floor by forcing an eviction, then lift the size limit again.
+        let vsize = pay(funding[0], 0).vsize() as u64;
+        processor.set_mempool_limits(MempoolLimits { max_size: vsize, ..MempoolLimits::default() });
+        processor.validate_and_add_transaction_at(pay(funding[0], 99_000), 1).unwrap();
+        processor.validate_and_add_transaction_at(pay(funding[1], 90_000), 1).unwrap();
+        processor.set_mempool_limits(MempoolLimits::default());
+
+        let parent = pay(funding[2], 99_950);
+        let parent_txid = generate_txid(&parent);
+        assert!(processor.validate_and_add_transaction_at(parent.clone(), 1).unwrap_err().to_string().contains("minimum fee"));
+
+        let weak_child = pay(UtxoRef { txid: parent_txid, vout: 0 }, 99_900);
+        let err = processor.submit_package(vec![parent.clone(), weak_child], 1).unwrap_err();
+        assert!(err.to_string().contains("Package feerate"), "{err}");
+
+        let child = pay(UtxoRef { txid: parent_txid, vout: 0 }, 90_000);
+        let added = processor.submit_package(vec![parent.clone(), child.clone()], 1).unwrap();
+        assert_eq!(added, vec![parent_txid, generate_txid(&child)]);
+        assert!(processor.coin_view(true).get(&UtxoRef { txid: generate_txid(&child), vout: 0 }).unwrap().is_some());
+
+        let orphan = pay(UtxoRef { txid: [9u8; 32], vout: 0 }, 1_000);
+        let err = processor.submit_package(vec![orphan], 1).unwrap_err();
+        assert!(err.to_string().contains("Package member 0"), "{err}");
+    }
+
+    #[test]
+    fn test_package_cannot_carry_unrelated_transaction() {
+        let funding: Vec<UtxoRef> = (1..=2u8).map(|i| UtxoRef { txid: [i; 32], vout: 0 }).collect();
+        let mut processor = funded_processor(&funding);
+
+        let free_rider = pay(funding[0], 100_000);
+        let generous = pay(funding[1], 50_000);
+        let err = processor.submit_package(vec![free_rider, generous], 1).unwrap_err();
+        assert!(err.to_string().contains("not a parent"), "{err}");
+        assert!(processor.mempool().is_empty());
+    }
+
+    #[test]
+    fn test_connect_block_confirms_and_evicts_conflicts() {
+        let funding: Vec<UtxoRef> = (1..=2u8).map(|i| UtxoRef { txid: [i; 32], vout: 0 }).collect();
+        let mut processor = funded_processor(&funding);
+        processor.set_height(5);
+
+        let parent = pay(funding[0], 99_000);
+        let child = pay(UtxoRef { txid: generate_txid(&parent), vout: 0 }, 98_000);
+        let doomed = pay(funding[1], 99_000);
+        for tx in [&parent, &child, &doomed] {
+            processor.validate_and_add_transaction_at(tx.clone(), 1).unwrap();
+        }
+        assert_eq!(processor.utxo_db().get(&funding[0]).unwrap().unwrap().height, 1);
+
+        let coinbase = Transaction {
+            version: 2,
+            inputs: vec![TxInput::new(UtxoRef { txid: [0u8; 32], vout: u32::MAX })],
+            outputs: vec![TxOutput::p2wpkh(&[3u8; 20], 50_000)],
+            locktime: 0,
+        };
+        let rival = pay(funding[1], 95_000);
+        processor.connect_block([8u8; 32], &[coinbase.clone(), parent.clone(), rival]).unwrap();
+
+        assert_eq!(processor.height(), 6);
+        assert_eq!(processor.utxo_db().best_block().unwrap(), Some([8u8; 32]));
+        let mined = processor.utxo_db().get(&UtxoRef { txid: generate_txid(&parent), vout: 0 }).unwrap().unwrap();
+        assert_eq!((mined.height, mined.is_coinbase), (5, false));
+        assert!(processor.utxo_db().get(&UtxoRef { txid: generate_txid(&coinbase), vout: 0 }).unwrap().unwrap().is_coinbase);
+        let child_txid = generate_txid(&child);
+        assert_eq!(processor.mempool().len(), 1);
+        assert!(processor.mempool().get(&child_txid).unwrap().parents.is_empty());
+        assert_eq!(processor.fee_estimator().best_height(), Some(5));
+        assert_eq!(processor.fee_estimator().tracked_count(), 1);
+
+        let stale = processor.connect_block([9u8; 32], &[pay(funding[0], 1)]).unwrap_err();
+        assert!(stale.contains("already spent"), "{stale}");
+    }
+
+    #[test]
+    fn test_connect_block_rejects_inflation() {
+        let funding = UtxoRef { txid: [1u8; 32], vout: 0 };
+        let mut processor = funded_processor(&[funding]);
+        let coinbase = |amount| Transaction {
+            version: 2,
+            inputs: vec![TxInput::new(UtxoRef { txid: [0u8; 32], vout: u32::MAX })],
+            outputs: vec![TxOutput::p2wpkh(&[3u8; 20], amount)],
+            locktime: 0,
+        };
+        let subsidy = block_subsidy(0);
+        let new_coins = coinbase(subsidy);
+
+        let rejected = [
+            (vec![pay(funding, 150_000)], "more than its inputs"),
+            (vec![spend(&[funding], &[MAX_MONEY, MAX_MONEY])], "exceeds the maximum"),
+            (vec![coinbase(subsidy + 1_001), pay(funding, 99_000)], "more than the subsidy"),
+            (vec![new_coins.clone(), pay(UtxoRef { txid: generate_txid(&new_coins), vout: 0 }, 1_000)], "immature"),
+            (vec![pay(funding, 99_000), new_coins], "not the first"),
         ];
+        for (block, reason) in rejected {
+            let err = processor.connect_block([8u8; 32], &block).unwrap_err();
+            assert!(err.contains(reason), "{err}");
+        }
+        assert_eq!((processor.height(), processor.utxo_db().best_block().unwrap()), (0, None));
+        assert!(processor.utxo_db().get(&funding).unwrap().is_some());
+
+        processor.connect_block([8u8; 32], &[coinbase(subsidy + 1_000), pay(funding, 99_000)]).unwrap();
+        assert_eq!(processor.get_total_supply().unwrap().spendable, 100_000 + subsidy);
+    }
+
+    #[test]
+    fn test_mempool_survives_restart_minus_invalidated_entries() {
+        let funding: Vec<UtxoRef> = (1..=2u8).map(|i| UtxoRef { txid: [i; 32], vout: 0 }).collect();
+        let mut processor = funded_processor(&funding);
+        let parent = pay(funding[0], 99_000);
+        let child = pay(UtxoRef { txid: generate_txid(&parent), vout: 0 }, 98_000);
+        let doomed = pay(funding[1], 99_000);
+        for (tx, time) in [(&parent, 10), (&child, 20), (&doomed, 30)] {
+            processor.validate_and_add_transaction_at(tx.clone(), time).unwrap();
+        }
+        processor.prioritise_transaction(&generate_txid(&child), 5_000);
+        processor.prioritise_transaction(&[9u8; 32], 700);
+        let path = std::env::temp_dir().join(format!("rust-coin-mempool-restart-{}.dat", std::process::id()));
+        assert_eq!(processor.save_mempool(&path).unwrap(), 3);
+
+        // While the node was down a block spent the coin `doomed` relied on.
+        let mut restarted = funded_processor(&funding[..1]);
+        let report = restarted.load_mempool(&path, 40).unwrap();
+        assert_eq!(report.accepted, vec![generate_txid(&parent), generate_txid(&child)]);
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0].0, generate_txid(&doomed));
+        assert!(report.dropped[0].1.contains("non-existent"), "{}", report.dropped[0].1);
+        let restored = restarted.mempool().get(&generate_txid(&child)).unwrap();
+        assert_eq!((restored.time, restored.fee_delta), (20, 5_000));
+        assert_eq!(restarted.mempool().fee_delta(&[9u8; 32]), 700);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(restarted.load_mempool(&path, 40).unwrap(), MempoolLoadReport::default());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_orphans_wait_for_parents_and_old_transactions_expire() {
+        let funding: Vec<UtxoRef> = (1..=2u8).map(|i| UtxoRef { txid: [i; 32], vout: 0 }).collect();
+        let mut processor = funded_processor(&funding);
+        let parent = pay(funding[0], 99_000);
+        let child = pay(UtxoRef { txid: generate_txid(&parent), vout: 0 }, 98_000);
+        let grandchild = pay(UtxoRef { txid: generate_txid(&child), vout: 0 }, 97_000);
+
+        for tx in [&grandchild, &child] {
+            let err = processor.validate_and_add_transaction_at(tx.clone(), 100).unwrap_err();
+            assert!(err.to_string().contains("orphan"), "{err}");
+        }
+        assert_eq!(processor.orphans().len(), 2);
+        processor.validate_and_add_transaction_at(parent.clone(), 100).unwrap();
+        assert!(processor.orphans().is_empty());
+        assert_eq!(processor.mempool().len(), 3);
+
+        processor.set_mempool_expiry(1_000);
+        processor.validate_and_add_transaction_at(pay(funding[1], 99_000), 1_050).unwrap();
+        let expired = processor.expire_mempool(1_101);
+        assert_eq!(expired.len(), 3);
+        assert_eq!(processor.mempool().len(), 1);
+        assert_eq!(processor.coin_view(true).get(&funding[0]).unwrap().unwrap().output.amount, 100_000);
+    }
+
+    #[test]
+    fn test_dropped_orphans_are_reported() {
+        let funding: Vec<UtxoRef> = (1..=2u8).map(|i| UtxoRef { txid: [i; 32], vout: 0 }).collect();
+        let mut processor = funded_processor(&funding);
+        let parent = pay(funding[0], 99_000);
+        // Spends more than the parent's output, so it fails once the parent arrives.
+        let greedy = pay(UtxoRef { txid: generate_txid(&parent), vout: 0 }, 150_000);
+        assert!(processor.validate_and_add_transaction_at(greedy.clone(), 100).is_err());
+        processor.validate_and_add_transaction_at(parent, 100).unwrap();
+        let dropped = processor.take_dropped_orphans();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].0, generate_txid(&greedy));
+        assert!(dropped[0].1.contains("Insufficient funds"), "{}", dropped[0].1);
+        assert!(processor.orphans().is_empty());
+
+        processor.set_orphan_limits(1, DEFAULT_MAX_ORPHAN_SIZE);
+        for amount in [90_000, 80_000] {
+            assert!(processor.validate_and_add_transaction_at(pay(UtxoRef { txid: [9u8; 32], vout: 0 }, amount), 100).is_err());
+        }
+        let dropped = processor.take_dropped_orphans();
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].1, "Orphan pool full");
+        assert!(processor.take_dropped_orphans().is_empty());
+    }
+
+    #[test]
+    fn test_rejects_transaction_spending_an_input_twice() {
+        let funding = UtxoRef { txid: [1u8; 32], vout: 0 };
+        let mut processor = funded_processor(&[funding]);
+        let doubled = spend(&[funding, funding], &[199_000]);
+
+        let err = processor.validate_and_add_transaction(doubled.clone()).unwrap_err();
+        assert!(matches!(&err, AcceptError::Consensus(message) if message.contains("more than once")), "{err}");
+        assert!(processor.mempool().is_empty());
+        assert!(processor.submit_package(vec![doubled], 1).is_err());
+        assert!(processor.mempool().is_empty());
+    }
+
+    #[test]
+    fn test_block_template_takes_parents_with_their_children() {
+        let funding = [UtxoRef { txid: [1u8; 32], vout: 0 }, UtxoRef { txid: [2u8; 32], vout: 0 }];
+        let mut processor = funded_processor(&funding);
+        let parent = pay(funding[0], 99_800);
+        let child = pay(UtxoRef { txid: generate_txid(&parent), vout: 0 }, 89_800);
+        let other = pay(funding[1], 97_000);
+        for tx in [&parent, &child, &other] {
+            processor.validate_and_add_transaction(tx.clone()).unwrap();
+        }
+
+        assert_eq!(processor.block_template(u64::MAX), vec![parent.clone(), child.clone(), other.clone()]);
+        let vsize = parent.vsize() as u64;
+        assert_eq!(processor.block_template(2 * vsize), vec![parent, child]);
+        assert_eq!(processor.block_template(vsize), vec![other]);
+        assert!(processor.block_template(0).is_empty());
+    }
+
+    #[test]
+    fn test_wallet_fee_falls_back_to_relay_floor() {
+        let mut processor = TransactionProcessor::new();
+        assert_eq!(processor.estimate_fee(6, EstimateMode::Economical), None);
+        assert_eq!(processor.fee_per_vbyte(6, EstimateMode::Economical), 1);
+        processor.set_policy(PolicyConfig { min_relay_fee: 2_500, ..PolicyConfig::default() });
+        assert_eq!(processor.fee_per_vbyte(6, EstimateMode::Conservative), 3);
+    }
+
+    #[test]
+    fn test_processor_and_wallet_on_disk() {
+        let path = std::env::temp_dir().join(format!("rust-coin-processor-on-disk-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let wallet = Wallet::new([1u8; 20]);
+        let funding = UtxoRef { txid: [3u8; 32], vout: 0 };
+        let mut store = SledUtxoDatabase::open(&path).unwrap();
+        store.add(funding, Coin::new(TxOutput::new(wallet.script_pubkey(), 50_000), 1, false)).unwrap();
+        let mut processor = TransactionProcessor::with_store(store).unwrap();
+
+        let fee_per_vbyte = processor.fee_per_vbyte(6, EstimateMode::Economical);
+        let tx = wallet.create_transaction(&processor.coin_view(false), [2u8; 20], 20_000, fee_per_vbyte).unwrap();
+        let change = tx.outputs[1].amount;
+        assert!(change < 30_000);
+        processor.validate_and_add_transaction(tx.clone()).unwrap();
+        assert_eq!(processor.utxo_db().get(&funding).unwrap().unwrap().output.amount, 50_000);
+        processor.connect_block([4u8; 32], std::slice::from_ref(&tx)).unwrap();
+
+        let store = processor.utxo_db();
+        assert_eq!(store.get(&funding).unwrap(), None);
+        assert_eq!(wallet.spendable_utxos(store).unwrap(), vec![(UtxoRef { txid: generate_txid(&tx), vout: 1 }, change)]);
+        assert_eq!(processor.get_total_supply().unwrap().spendable, 20_000 + change);
+        drop(processor);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    // Holds no coins and fails every lookup, like a store whose disk went away.
+    struct UnreadableStore;
+
+    impl UtxoStore for UnreadableStore {
+        fn get(&self, _: &UtxoRef) -> Result<Option<Coin>, String> {
+            Err("Disk read failed".to_string())
+        }
+
+        fn add(&mut self, _: UtxoRef, _: Coin) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn remove(&mut self, _: &UtxoRef) -> Result<Option<Coin>, String> {
+            Ok(None)
+        }
+
+        fn apply_batch(&mut self, _: UtxoBatch) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn utxos(&self) -> Result<Vec<(UtxoRef, Coin)>, String> {
+            Ok(Vec::new())
+        }
+
+        fn best_block(&self) -> Result<Option<[u8; 32]>, String> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_store_failures_are_not_blamed_on_the_transaction() {
+        let mut processor = TransactionProcessor::with_store(UnreadableStore).unwrap();
+        let tx = pay(UtxoRef { txid: [1u8; 32], vout: 0 }, 1_000);
+        let err = processor.validate_and_add_transaction(tx.clone()).unwrap_err();
+        assert_eq!(err, AcceptError::Store("Disk read failed".to_string()));
+        assert!(matches!(processor.submit_package(vec![tx], 1), Err(AcceptError::Store(_))));
+        assert!(processor.orphans().is_empty());
+    }
+
+    #[test]
+    fn test_rejects_output_amounts_beyond_max_money() {
+        let funding = UtxoRef { txid: [1u8; 32], vout: 0 };
+        let mut processor = funded_processor(&[funding]);
+
+        for amounts in [&[u64::MAX, 1][..], &[MAX_MONEY + 1], &[MAX_MONEY, 1]] {
+            let tx = spend(&[funding], amounts);
+            let err = processor.validate_and_add_transaction(tx.clone()).unwrap_err();
+            assert!(matches!(&err, AcceptError::Consensus(message) if message.contains("exceeds the maximum")), "{err}");
+            let err = processor.submit_package(vec![tx], 1).unwrap_err();
+            assert!(err.to_string().contains("exceeds the maximum"), "{err}");
+        }
+        assert!(processor.mempool().is_empty());
+    }
+}
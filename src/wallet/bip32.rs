@@ -0,0 +1,289 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin_hashes::hmac::{Hmac, HmacEngine};
+use bitcoin_hashes::{sha512, GeneralHash, Hash, HashEngine};
+use secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey};
+
+use crate::transaction::encoding::{base58check_decode, base58check_encode};
+use crate::transaction::script;
+
+pub const HARDENED: u32 = 0x8000_0000;
+
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+const TPUB_VERSION: [u8; 4] = [0x04, 0x35, 0x87, 0xcf];
+const XPRV_VERSION: [u8; 4] = [0x04, 0x88, 0xad, 0xe4];
+const TPRV_VERSION: [u8; 4] = [0x04, 0x35, 0x83, 0x94];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedPubKey {
+    pub is_testnet: bool,
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    pub public_key: PublicKey,
+}
+
+impl ExtendedPubKey {
+    pub fn fingerprint(&self) -> [u8; 4] {
+        let hash = script::hash160(&self.public_key.serialize());
+        [hash[0], hash[1], hash[2], hash[3]]
+    }
+
+    pub fn derive_child(&self, index: u32) -> Result<ExtendedPubKey, String> {
+        if index >= HARDENED {
+            return Err(format!(
+                "Cannot derive hardened child {}' from a public key",
+                index - HARDENED
+            ));
+        }
+
+        let mut engine = HmacEngine::<sha512::Hash>::new(&self.chain_code);
+        engine.input(&self.public_key.serialize());
+        engine.input(&index.to_be_bytes());
+        let hmac = Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+
+        let mut tweak = [0u8; 32];
+        tweak.copy_from_slice(&hmac[..32]);
+        let tweak = Scalar::from_be_bytes(tweak)
+            .map_err(|_| format!("Child {index} is invalid, use the next index"))?;
+        let public_key = self.public_key
+            .add_exp_tweak(&Secp256k1::verification_only(), &tweak)
+            .map_err(|_| format!("Child {index} is invalid, use the next index"))?;
+
+        let mut chain_code = [0u8; 32];
+        chain_code.copy_from_slice(&hmac[32..]);
+        Ok(ExtendedPubKey {
+            is_testnet: self.is_testnet,
+            depth: self.depth.checked_add(1).ok_or("Maximum derivation depth exceeded")?,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            public_key,
+        })
+    }
+
+    pub fn derive_path(&self, path: &[u32]) -> Result<ExtendedPubKey, String> {
+        let mut key = self.clone();
+        for index in path {
+            key = key.derive_child(*index)?;
+        }
+        Ok(key)
+    }
+
+    pub fn encode(&self) -> [u8; 78] {
+        let mut bytes = [0u8; 78];
+        bytes[..4].copy_from_slice(if self.is_testnet { &TPUB_VERSION } else { &XPUB_VERSION });
+        bytes[4] = self.depth;
+        bytes[5..9].copy_from_slice(&self.parent_fingerprint);
+        bytes[9..13].copy_from_slice(&self.child_number.to_be_bytes());
+        bytes[13..45].copy_from_slice(&self.chain_code);
+        bytes[45..].copy_from_slice(&self.public_key.serialize());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<ExtendedPubKey, String> {
+        if bytes.len() != 78 {
+            return Err(format!("Extended key must be 78 bytes, got {}", bytes.len()));
+        }
+        let version = &bytes[..4];
+        let is_testnet = if version == XPUB_VERSION {
+            false
+        } else if version == TPUB_VERSION {
+            true
+        } else if version == XPRV_VERSION || version == TPRV_VERSION {
+            return Err("Extended private keys are not accepted here".to_string());
+        } else {
+            return Err(format!("Unknown extended key version {version:02x?}"));
+        };
+
+        let public_key = PublicKey::from_slice(&bytes[45..])
+            .map_err(|e| format!("Invalid extended public key: {e}"))?;
+        Ok(ExtendedPubKey {
+            is_testnet,
+            depth: bytes[4],
+            parent_fingerprint: bytes[5..9].try_into().unwrap(),
+            child_number: u32::from_be_bytes(bytes[9..13].try_into().unwrap()),
+            chain_code: bytes[13..45].try_into().unwrap(),
+            public_key,
+        })
+    }
+}
+
+// Private counterpart used only where a seed has been unlocked; it deliberately has no
+// string encoding so it cannot end up in descriptors or logs.
+#[derive(Clone)]
+pub struct ExtendedPrivKey {
+    pub is_testnet: bool,
+    pub depth: u8,
+    pub parent_fingerprint: [u8; 4],
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    pub secret_key: SecretKey,
+}
+
+impl ExtendedPrivKey {
+    pub fn new_master(seed: &[u8], is_testnet: bool) -> Result<ExtendedPrivKey, String> {
+        let mut engine = HmacEngine::<sha512::Hash>::new(b"Bitcoin seed");
+        engine.input(seed);
+        let hmac = Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+
+        let secret_key = SecretKey::from_byte_array(hmac[..32].try_into().unwrap())
+            .map_err(|_| "Seed produces an invalid master key".to_string())?;
+        Ok(ExtendedPrivKey {
+            is_testnet,
+            depth: 0,
+            parent_fingerprint: [0u8; 4],
+            child_number: 0,
+            chain_code: hmac[32..].try_into().unwrap(),
+            secret_key,
+        })
+    }
+
+    pub fn to_public(&self) -> ExtendedPubKey {
+        ExtendedPubKey {
+            is_testnet: self.is_testnet,
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            public_key: PublicKey::from_secret_key(&Secp256k1::signing_only(), &self.secret_key),
+        }
+    }
+
+    pub fn fingerprint(&self) -> [u8; 4] {
+        self.to_public().fingerprint()
+    }
+
+    pub fn derive_child(&self, index: u32) -> Result<ExtendedPrivKey, String> {
+        let mut engine = HmacEngine::<sha512::Hash>::new(&self.chain_code);
+        if index >= HARDENED {
+            engine.input(&[0u8]);
+            engine.input(&self.secret_key.secret_bytes());
+        } else {
+            engine.input(&self.to_public().public_key.serialize());
+        }
+        engine.input(&index.to_be_bytes());
+        let hmac = Hmac::<sha512::Hash>::from_engine(engine).to_byte_array();
+
+        let tweak = Scalar::from_be_bytes(hmac[..32].try_into().unwrap())
+            .map_err(|_| format!("Child {index} is invalid, use the next index"))?;
+        let secret_key = self.secret_key
+            .add_tweak(&tweak)
+            .map_err(|_| format!("Child {index} is invalid, use the next index"))?;
+        Ok(ExtendedPrivKey {
+            is_testnet: self.is_testnet,
+            depth: self.depth.checked_add(1).ok_or("Maximum derivation depth exceeded")?,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code: hmac[32..].try_into().unwrap(),
+            secret_key,
+        })
+    }
+
+    pub fn derive_path(&self, path: &[u32]) -> Result<ExtendedPrivKey, String> {
+        let mut key = self.clone();
+        for index in path {
+            key = key.derive_child(*index)?;
+        }
+        Ok(key)
+    }
+}
+
+impl FromStr for ExtendedPubKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ExtendedPubKey::decode(&base58check_decode(s)?)
+    }
+}
+
+impl fmt::Display for ExtendedPubKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", base58check_encode(&self.encode()))
+    }
+}
+
+// Parses "84h/0'/0/5" style paths (without a leading "m").
+pub fn parse_path(path: &str) -> Result<Vec<u32>, String> {
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    path.split('/')
+        .map(|step| {
+            let (number, hardened) = match step.strip_suffix(['\'', 'h', 'H']) {
+                Some(number) => (number, true),
+                None => (step, false),
+            };
+            let index: u32 = number.parse()
+                .map_err(|_| format!("Invalid derivation step '{step}'"))?;
+            if index >= HARDENED {
+                return Err(format!("Derivation index {index} is out of range"));
+            }
+            Ok(if hardened { index + HARDENED } else { index })
+        })
+        .collect()
+}
+
+pub fn format_path(path: &[u32]) -> String {
+    path.iter()
+        .map(|index| {
+            if *index >= HARDENED {
+                format!("{}h", index - HARDENED)
+            } else {
+                index.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // BIP32 test vector 1.
+    const M_0H: &str = "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw";
+    const M_0H_1: &str = "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ";
+
+    #[test]
+    fn test_public_derivation_matches_vector() {
+        let parent: ExtendedPubKey = M_0H.parse().unwrap();
+
+        let child = parent.derive_child(1).unwrap();
+
+        assert_eq!(child.to_string(), M_0H_1);
+        assert_eq!(child.parent_fingerprint, parent.fingerprint());
+        assert_eq!(child.depth, 2);
+    }
+
+    #[test]
+    fn test_private_derivation_matches_vector() {
+        let seed: Vec<u8> = (0u8..16).collect();
+        let master = ExtendedPrivKey::new_master(&seed, false).unwrap();
+
+        let account = master.derive_path(&[HARDENED]).unwrap();
+
+        assert_eq!(account.to_public().to_string(), M_0H);
+        let child = account.derive_child(1).unwrap();
+        assert_eq!(child.to_public().to_string(), M_0H_1);
+    }
+
+    #[test]
+    fn test_rejects_hardened_and_private() {
+        let parent: ExtendedPubKey = M_0H.parse().unwrap();
+        assert!(parent.derive_child(HARDENED).is_err());
+
+        let xprv = "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi";
+        assert!(xprv.parse::<ExtendedPubKey>().unwrap_err().contains("private"));
+    }
+
+    #[test]
+    fn test_path_round_trip() {
+        let path = parse_path("84h/0'/0/5").unwrap();
+        assert_eq!(path, vec![84 + HARDENED, HARDENED, 0, 5]);
+        assert_eq!(format_path(&path), "84h/0h/0/5");
+        assert!(parse_path("84x/0").is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use std::fmt;
+use std::str::FromStr;
+
+use secp256k1::PublicKey;
+
+use crate::transaction::encoding::{hex_decode, hex_encode};
+use crate::transaction::script;
+use crate::wallet::bip32::{format_path, parse_path, ExtendedPubKey, HARDENED};
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+// Master key fingerprint and derivation path.
+pub type KeyOrigin = ([u8; 4], Vec<u32>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptKind {
+    Pkh,
+    Wpkh,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum KeyExpr {
+    Single(PublicKey),
+    Extended {
+        xpub: ExtendedPubKey,
+        path: Vec<u32>,
+        ranged: bool,
+    },
+}
+
+// Public-only output descriptor (BIP380/381/382): pkh(KEY) or wpkh(KEY), where KEY is a hex
+// public key or an xpub with an optional key origin and an optional trailing /* range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Descriptor {
+    pub kind: ScriptKind,
+    origin: Option<KeyOrigin>,
+    key: KeyExpr,
+}
+
+impl Descriptor {
+    pub fn from_xpub(kind: ScriptKind, xpub: ExtendedPubKey, branch: u32) -> Self {
+        Descriptor {
+            kind,
+            origin: None,
+            key: KeyExpr::Extended { xpub, path: vec![branch], ranged: true },
+        }
+    }
+
+    pub fn is_ranged(&self) -> bool {
+        matches!(self.key, KeyExpr::Extended { ranged: true, .. })
+    }
+
+    pub fn derive_public_key(&self, index: u32) -> Result<PublicKey, String> {
+        match &self.key {
+            KeyExpr::Single(public_key) => Ok(*public_key),
+            KeyExpr::Extended { xpub, path, ranged } => {
+                let mut full_path = path.clone();
+                if *ranged {
+                    full_path.push(index);
+                }
+                Ok(xpub.derive_path(&full_path)?.public_key)
+            }
+        }
+    }
+
+    pub fn script_pubkey(&self, index: u32) -> Result<Vec<u8>, String> {
+        let pubkey_hash = script::hash160(&self.derive_public_key(index)?.serialize());
+        Ok(match self.kind {
+            ScriptKind::Pkh => script::p2pkh(&pubkey_hash),
+            ScriptKind::Wpkh => script::p2wpkh(&pubkey_hash),
+        })
+    }
+
+    // Master fingerprint and full derivation path of the key at `index`, as recorded in
+    // PSBT BIP32 derivation fields.
+    pub fn key_source(&self, index: u32) -> KeyOrigin {
+        let (fingerprint, mut full_path) = match (&self.origin, &self.key) {
+            (Some((fingerprint, path)), _) => (*fingerprint, path.clone()),
+            (None, KeyExpr::Single(public_key)) => {
+                let hash = script::hash160(&public_key.serialize());
+                ([hash[0], hash[1], hash[2], hash[3]], Vec::new())
+            }
+            (None, KeyExpr::Extended { xpub, .. }) => (xpub.fingerprint(), Vec::new()),
+        };
+        if let KeyExpr::Extended { path, ranged, .. } = &self.key {
+            full_path.extend_from_slice(path);
+            if *ranged {
+                full_path.push(index);
+            }
+        }
+        (fingerprint, full_path)
+    }
+
+    pub fn xpub_with_origin(&self) -> Option<(&ExtendedPubKey, [u8; 4], Vec<u32>)> {
+        match &self.key {
+            KeyExpr::Extended { xpub, .. } => {
+                let (fingerprint, path) = self.origin.clone()
+                    .unwrap_or_else(|| (xpub.fingerprint(), Vec::new()));
+                Some((xpub, fingerprint, path))
+            }
+            KeyExpr::Single(_) => None,
+        }
+    }
+
+    fn body(&self) -> String {
+        let mut key = String::new();
+        if let Some((fingerprint, path)) = &self.origin {
+            key.push('[');
+            key.push_str(&hex_encode(fingerprint));
+            if !path.is_empty() {
+                key.push('/');
+                key.push_str(&format_path(path));
+            }
+            key.push(']');
+        }
+        match &self.key {
+            KeyExpr::Single(public_key) => key.push_str(&hex_encode(&public_key.serialize())),
+            KeyExpr::Extended { xpub, path, ranged } => {
+                key.push_str(&xpub.to_string());
+                if !path.is_empty() {
+                    key.push('/');
+                    key.push_str(&format_path(path));
+                }
+                if *ranged {
+                    key.push_str("/*");
+                }
+            }
+        }
+        match self.kind {
+            ScriptKind::Pkh => format!("pkh({key})"),
+            ScriptKind::Wpkh => format!("wpkh({key})"),
+        }
+    }
+}
+
+impl FromStr for Descriptor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let body = match s.split_once('#') {
+            Some((body, checksum)) => {
+                if descriptor_checksum(body)? != checksum {
+                    return Err(format!("Descriptor checksum mismatch, expected {}", descriptor_checksum(body)?));
+                }
+                body
+            }
+            None => s,
+        };
+
+        let (kind, inner) = if let Some(inner) = body.strip_prefix("wpkh(") {
+            (ScriptKind::Wpkh, inner)
+        } else if let Some(inner) = body.strip_prefix("pkh(") {
+            (ScriptKind::Pkh, inner)
+        } else {
+            return Err(format!("Unsupported descriptor '{body}', expected pkh() or wpkh()"));
+        };
+        let inner = inner.strip_suffix(')')
+            .ok_or_else(|| format!("Unterminated descriptor '{body}'"))?;
+
+        let (origin, key) = parse_origin(inner)?;
+        let key = parse_key(key)?;
+        Ok(Descriptor { kind, origin, key })
+    }
+}
+
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let body = self.body();
+        let checksum = descriptor_checksum(&body).map_err(|_| fmt::Error)?;
+        write!(f, "{body}#{checksum}")
+    }
+}
+
+fn parse_origin(key: &str) -> Result<(Option<KeyOrigin>, &str), String> {
+    let Some(rest) = key.strip_prefix('[') else {
+        return Ok((None, key));
+    };
+    let (origin, key) = rest.split_once(']')
+        .ok_or("Unterminated key origin")?;
+    let (fingerprint, path) = origin.split_once('/').unwrap_or((origin, ""));
+    let fingerprint: [u8; 4] = hex_decode(fingerprint)?
+        .try_into()
+        .map_err(|_| "Key origin fingerprint must be 4 bytes".to_string())?;
+    Ok((Some((fingerprint, parse_path(path)?)), key))
+}
+
+fn parse_key(key: &str) -> Result<KeyExpr, String> {
+    if key.starts_with("xpub") || key.starts_with("tpub") {
+        let mut steps = key.split('/');
+        let xpub: ExtendedPubKey = steps.next().unwrap_or_default().parse()?;
+        let mut rest: Vec<&str> = steps.collect();
+        let ranged = rest.last() == Some(&"*");
+        if ranged {
+            rest.pop();
+        }
+        let path = parse_path(&rest.join("/"))?;
+        if path.iter().any(|index| *index >= HARDENED) {
+            return Err("Hardened derivation after an xpub needs private keys".to_string());
+        }
+        return Ok(KeyExpr::Extended { xpub, path, ranged });
+    }
+    if key.starts_with("xprv") || key.starts_with("tprv") {
+        return Err("Watch-only descriptors must not contain private keys".to_string());
+    }
+
+    let public_key = PublicKey::from_slice(&hex_decode(key)?)
+        .map_err(|e| format!("Invalid public key in descriptor: {e}"))?;
+    Ok(KeyExpr::Single(public_key))
+}
+
+fn checksum_polymod(symbols: &[u64]) -> u64 {
+    const GENERATOR: [u64; 5] = [0xf5dee51989, 0xa9fdca3312, 0x1bab10e32d, 0x3706b1677a, 0x644d626ffd];
+    let mut chk = 1u64;
+    for value in symbols {
+        let top = chk >> 35;
+        chk = ((chk & 0x7_ffff_ffff) << 5) ^ value;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+pub fn descriptor_checksum(descriptor: &str) -> Result<String, String> {
+    let mut symbols = Vec::new();
+    let mut groups = Vec::new();
+    for c in descriptor.chars() {
+        let value = INPUT_CHARSET.find(c)
+            .ok_or_else(|| format!("Invalid character '{c}' in descriptor"))? as u64;
+        symbols.push(value & 31);
+        groups.push(value >> 5);
+        if groups.len() == 3 {
+            symbols.push(groups[0] * 9 + groups[1] * 3 + groups[2]);
+            groups.clear();
+        }
+    }
+    match groups.len() {
+        1 => symbols.push(groups[0]),
+        2 => symbols.push(groups[0] * 3 + groups[1]),
+        _ => {}
+    }
+    symbols.extend_from_slice(&[0; 8]);
+
+    let checksum = checksum_polymod(&symbols) ^ 1;
+    Ok((0..8)
+        .map(|i| CHECKSUM_CHARSET[((checksum >> (5 * (7 - i))) & 31) as usize] as char)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XPUB: &str = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+
+    #[test]
+    fn test_checksum_vectors() {
+        assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert_eq!(
+            descriptor_checksum("addr(mkmZxiEcEd8ZqjQWVZuC6so5dFMKEFpN2j)").unwrap(),
+            "02wpgw69"
+        );
+    }
+
+    #[test]
+    fn test_parse_ranged_descriptor_with_origin() {
+        let text = format!("pkh([d34db33f/44'/0'/0']{XPUB}/1/*)#ml40v0wf");
+
+        let descriptor: Descriptor = text.parse().unwrap();
+
+        assert!(descriptor.is_ranged());
+        assert_eq!(descriptor.kind, ScriptKind::Pkh);
+        let (fingerprint, path) = descriptor.key_source(7);
+        assert_eq!(fingerprint, [0xd3, 0x4d, 0xb3, 0x3f]);
+        assert_eq!(path, vec![44 + HARDENED, HARDENED, HARDENED, 1, 7]);
+        assert_ne!(descriptor.script_pubkey(0).unwrap(), descriptor.script_pubkey(1).unwrap());
+
+        let reparsed: Descriptor = descriptor.to_string().parse().unwrap();
+        assert_eq!(reparsed, descriptor);
+    }
+
+    #[test]
+    fn test_rejects_bad_descriptors() {
+        let bad_checksum = format!("pkh([d34db33f/44'/0'/0']{XPUB}/1/*)#ml40v0wq");
+        assert!(bad_checksum.parse::<Descriptor>().unwrap_err().contains("checksum"));
+
+        let hardened = format!("wpkh({XPUB}/1h/*)");
+        assert!(hardened.parse::<Descriptor>().is_err());
+
+        let private = "wpkh(xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi/0/*)";
+        assert!(private.parse::<Descriptor>().is_err());
+
+        assert!("sh(wpkh(02aa))".parse::<Descriptor>().is_err());
+    }
+
+    #[test]
+    fn test_single_key_descriptor() {
+        let pubkey = "03a34b99f22c790c4e36b2b3c2c35a36db06226e41c692fc82b8b56ac1c540c5bd";
+        let descriptor: Descriptor = format!("wpkh({pubkey})").parse().unwrap();
+
+        assert!(!descriptor.is_ranged());
+        let expected = script::p2wpkh(&script::hash160(&hex_decode(pubkey).unwrap()));
+        assert_eq!(descriptor.script_pubkey(0).unwrap(), expected);
+        assert_eq!(descriptor.script_pubkey(5).unwrap(), expected);
+    }
+}
@@ -0,0 +1,5 @@
+pub mod bip32;
+pub mod descriptor;
+pub mod file;
+pub mod rescan;
+pub mod watch_only;
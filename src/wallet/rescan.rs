@@ -0,0 +1,187 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::transaction::store::UtxoStore;
+use crate::transaction::transaction::{generate_txid, Transaction, TxOutput, UtxoRef};
+use crate::wallet::watch_only::{Chain, HistoryEntry, WatchOnlyWallet};
+
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+#[derive(Debug, Clone)]
+pub struct RescanReport {
+    pub balance: u64,
+    pub utxos: Vec<(UtxoRef, TxOutput)>,
+    pub next_receive_index: u32,
+    pub next_change_index: u32,
+    pub transactions_found: usize,
+}
+
+// Rebuilds a restored wallet's state. Each chain is walked from index 0 and scanning
+// stops once `gap_limit` consecutive derived scripts have never appeared on chain.
+pub fn rescan<S: UtxoStore>(
+    wallet: &mut WatchOnlyWallet,
+    utxo_db: &S,
+    chain_history: &[Transaction],
+    gap_limit: u32,
+) -> Result<RescanReport, String> {
+    if gap_limit == 0 {
+        return Err("Gap limit must be at least 1".to_string());
+    }
+
+    // The store is asked per derived script so a rescan never loads the whole UTXO set.
+    let history_scripts: HashSet<&[u8]> = chain_history.iter()
+        .flat_map(|tx| tx.outputs.iter().map(|output| output.script_pubkey.as_slice()))
+        .collect();
+
+    for chain in [Chain::Receive, Chain::Change] {
+        let descriptor = wallet.descriptor(chain).clone();
+        if !descriptor.is_ranged() {
+            continue;
+        }
+        let mut last_used = None;
+        let mut index = 0;
+        while index < last_used.map_or(0, |used| used + 1) + gap_limit {
+            let script_pubkey = descriptor.script_pubkey(index)?;
+            if history_scripts.contains(script_pubkey.as_slice())
+                || !utxo_db.utxos_for_script(&script_pubkey)?.is_empty()
+            {
+                last_used = Some(index);
+            }
+            index += 1;
+        }
+        if let Some(used) = last_used {
+            wallet.mark_used(chain, used)?;
+        }
+    }
+
+    let history = rebuild_history(wallet, utxo_db, chain_history)?;
+    let transactions_found = history.len();
+    let next_receive = wallet.next_index(Chain::Receive);
+    let next_change = wallet.next_index(Chain::Change);
+    wallet.restore_state(next_receive, next_change, history)?;
+
+    Ok(RescanReport {
+        balance: wallet.balance(utxo_db)?,
+        utxos: wallet.utxos(utxo_db)?,
+        next_receive_index: next_receive,
+        next_change_index: next_change,
+        transactions_found,
+    })
+}
+
+// Amounts sent are resolved against earlier transactions in the history first, since the
+// coins they spent are no longer in the UTXO set.
+fn rebuild_history<S: UtxoStore>(
+    wallet: &WatchOnlyWallet,
+    utxo_db: &S,
+    chain_history: &[Transaction],
+) -> Result<Vec<HistoryEntry>, String> {
+    let mut created: HashMap<UtxoRef, &TxOutput> = HashMap::new();
+    let mut history = Vec::new();
+    for tx in chain_history {
+        let txid = generate_txid(tx);
+        let mut sent = 0;
+        for input in &tx.inputs {
+            let output = match created.get(&input.utxo_ref) {
+                Some(output) => Some((*output).clone()),
+                None => utxo_db.get(&input.utxo_ref)?.map(|coin| coin.output),
+            };
+            if let Some(output) = output
+                && wallet.is_mine(&output.script_pubkey)
+            {
+                sent += output.amount;
+            }
+        }
+        let received: u64 = tx.outputs.iter()
+            .filter(|output| wallet.is_mine(&output.script_pubkey))
+            .map(|output| output.amount)
+            .sum();
+
+        for (vout, output) in tx.outputs.iter().enumerate() {
+            created.insert(UtxoRef { txid, vout: vout as u32 }, output);
+        }
+        if sent > 0 || received > 0 {
+            history.push(HistoryEntry { txid, received, sent, transaction: tx.clone() });
+        }
+    }
+    Ok(history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::coin::Coin;
+    use crate::transaction::database::UtxoDatabase;
+    use crate::transaction::script;
+    use crate::transaction::transaction::TxInput;
+    use crate::wallet::descriptor::ScriptKind;
+
+    const XPUB: &str = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+
+    fn pay(inputs: Vec<UtxoRef>, outputs: Vec<(Vec<u8>, u64)>) -> Transaction {
+        Transaction {
+            version: 2,
+            inputs: inputs.into_iter().map(TxInput::new).collect(),
+            outputs: outputs.into_iter().map(|(script_pubkey, amount)| TxOutput::new(script_pubkey, amount)).collect(),
+            locktime: 0,
+        }
+    }
+
+    fn apply(utxo_db: &mut UtxoDatabase, tx: &Transaction) {
+        for input in &tx.inputs {
+            utxo_db.remove_utxo(&input.utxo_ref);
+        }
+        let txid = generate_txid(tx);
+        for (vout, output) in tx.outputs.iter().enumerate() {
+            utxo_db.add_utxo(UtxoRef { txid, vout: vout as u32 }, Coin::new(output.clone(), 1, false));
+        }
+    }
+
+    #[test]
+    fn test_window_extends_past_hits() {
+        let template = WatchOnlyWallet::from_xpub(XPUB, ScriptKind::Wpkh).unwrap();
+        let receive = template.descriptor(Chain::Receive);
+        let mut utxo_db = UtxoDatabase::new();
+        // Index 35 is only reachable because the hit at 18 moves the window forward.
+        let funding = pay(vec![UtxoRef { txid: [1u8; 32], vout: 0 }], vec![
+            (receive.script_pubkey(18).unwrap(), 10_000),
+            (receive.script_pubkey(35).unwrap(), 20_000),
+            (receive.script_pubkey(80).unwrap(), 40_000),
+            (template.descriptor(Chain::Change).script_pubkey(2).unwrap(), 5_000),
+        ]);
+        apply(&mut utxo_db, &funding);
+
+        let mut wallet = WatchOnlyWallet::from_xpub(XPUB, ScriptKind::Wpkh).unwrap();
+        let report = rescan(&mut wallet, &utxo_db, &[], DEFAULT_GAP_LIMIT).unwrap();
+
+        assert_eq!(report.next_receive_index, 36);
+        assert_eq!(report.next_change_index, 3);
+        assert_eq!(report.balance, 35_000);
+        assert_eq!(report.utxos.len(), 3);
+        assert_eq!(wallet.next_index(Chain::Receive), 36);
+    }
+
+    #[test]
+    fn test_history_finds_spent_coins() {
+        let template = WatchOnlyWallet::from_xpub(XPUB, ScriptKind::Wpkh).unwrap();
+        let mut utxo_db = UtxoDatabase::new();
+        let funding = pay(vec![UtxoRef { txid: [1u8; 32], vout: 0 }], vec![
+            (template.descriptor(Chain::Receive).script_pubkey(4).unwrap(), 50_000),
+        ]);
+        let spend = pay(vec![UtxoRef { txid: generate_txid(&funding), vout: 0 }], vec![
+            (script::p2wpkh(&[9u8; 20]), 30_000),
+            (template.descriptor(Chain::Change).script_pubkey(0).unwrap(), 19_000),
+        ]);
+        apply(&mut utxo_db, &funding);
+        apply(&mut utxo_db, &spend);
+
+        let mut wallet = WatchOnlyWallet::from_xpub(XPUB, ScriptKind::Wpkh).unwrap();
+        let report = rescan(&mut wallet, &utxo_db, &[funding, spend], 5).unwrap();
+
+        assert_eq!(report.next_receive_index, 5);
+        assert_eq!(report.next_change_index, 1);
+        assert_eq!(report.balance, 19_000);
+        assert_eq!(report.transactions_found, 2);
+        assert_eq!(wallet.history()[1].sent, 50_000);
+        assert_eq!(wallet.history()[1].received, 19_000);
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+
+use crate::transaction::batch::{
+    build_funded_transaction, sort_largest_first, validate_payments, Payment,
+};
+use crate::transaction::psbt::Psbt;
+use crate::transaction::store::UtxoStore;
+use crate::transaction::transaction::{generate_txid, Transaction, TxOutput, UtxoRef};
+use crate::wallet::bip32::ExtendedPubKey;
+use crate::wallet::descriptor::{Descriptor, ScriptKind};
+
+pub const DEFAULT_LOOKAHEAD: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Chain {
+    Receive,
+    Change,
+}
+
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub txid: [u8; 32],
+    pub received: u64,
+    pub sent: u64,
+    pub transaction: Transaction,
+}
+
+// A wallet that knows only public descriptors. Coins are read from the shared UTXO
+// store; the wallet itself keeps derived scripts, derivation indices and history.
+pub struct WatchOnlyWallet {
+    receive: Descriptor,
+    change: Descriptor,
+    next_index: HashMap<Chain, u32>,
+    derived_up_to: HashMap<Chain, u32>,
+    lookahead: u32,
+    scripts: HashMap<Vec<u8>, (Chain, u32)>,
+    history: Vec<HistoryEntry>,
+}
+
+impl WatchOnlyWallet {
+    pub fn new(receive: Descriptor, change: Descriptor, lookahead: u32) -> Result<Self, String> {
+        let mut wallet = WatchOnlyWallet {
+            receive,
+            change,
+            next_index: HashMap::from([(Chain::Receive, 0), (Chain::Change, 0)]),
+            derived_up_to: HashMap::from([(Chain::Receive, 0), (Chain::Change, 0)]),
+            lookahead,
+            scripts: HashMap::new(),
+            history: Vec::new(),
+        };
+        wallet.top_up(Chain::Receive)?;
+        wallet.top_up(Chain::Change)?;
+        Ok(wallet)
+    }
+
+    pub fn from_descriptors(receive: &str, change: &str) -> Result<Self, String> {
+        WatchOnlyWallet::new(receive.parse()?, change.parse()?, DEFAULT_LOOKAHEAD)
+    }
+
+    // Standard layout: external addresses on branch 0, change on branch 1.
+    pub fn from_xpub(xpub: &str, kind: ScriptKind) -> Result<Self, String> {
+        let xpub: ExtendedPubKey = xpub.parse()?;
+        WatchOnlyWallet::new(
+            Descriptor::from_xpub(kind, xpub.clone(), 0),
+            Descriptor::from_xpub(kind, xpub, 1),
+            DEFAULT_LOOKAHEAD,
+        )
+    }
+
+    pub fn descriptor(&self, chain: Chain) -> &Descriptor {
+        match chain {
+            Chain::Receive => &self.receive,
+            Chain::Change => &self.change,
+        }
+    }
+
+    pub fn next_index(&self, chain: Chain) -> u32 {
+        self.next_index[&chain]
+    }
+
+    pub fn lookahead(&self) -> u32 {
+        self.lookahead
+    }
+
+    // Derives scripts so that `lookahead` unused indices past the next index are always
+    // recognised.
+    fn top_up(&mut self, chain: Chain) -> Result<(), String> {
+        let descriptor = self.descriptor(chain).clone();
+        let target = if descriptor.is_ranged() {
+            self.next_index[&chain] + self.lookahead.max(1)
+        } else {
+            1
+        };
+        for index in self.derived_up_to[&chain]..target {
+            self.scripts.insert(descriptor.script_pubkey(index)?, (chain, index));
+        }
+        let derived = self.derived_up_to.get_mut(&chain).unwrap();
+        *derived = (*derived).max(target);
+        Ok(())
+    }
+
+    pub fn mark_used(&mut self, chain: Chain, index: u32) -> Result<(), String> {
+        if !self.descriptor(chain).is_ranged() {
+            return Ok(());
+        }
+        let next = self.next_index.get_mut(&chain).unwrap();
+        if index >= *next {
+            *next = index + 1;
+        }
+        self.top_up(chain)
+    }
+
+    fn next_script(&mut self, chain: Chain) -> Result<Vec<u8>, String> {
+        let index = self.next_index[&chain];
+        let script_pubkey = self.descriptor(chain).script_pubkey(index)?;
+        self.mark_used(chain, index)?;
+        Ok(script_pubkey)
+    }
+
+    pub fn next_receive_script(&mut self) -> Result<Vec<u8>, String> {
+        self.next_script(Chain::Receive)
+    }
+
+    pub fn script_info(&self, script_pubkey: &[u8]) -> Option<(Chain, u32)> {
+        self.scripts.get(script_pubkey).copied()
+    }
+
+    pub fn is_mine(&self, script_pubkey: &[u8]) -> bool {
+        self.scripts.contains_key(script_pubkey)
+    }
+
+    // One lookup per derived script, so stores with a script index never scan the set.
+    pub fn utxos<S: UtxoStore>(&self, utxo_db: &S) -> Result<Vec<(UtxoRef, TxOutput)>, String> {
+        let mut utxos = Vec::new();
+        for script_pubkey in self.scripts.keys() {
+            utxos.extend(utxo_db.utxos_for_script(script_pubkey)?
+                .into_iter()
+                .map(|(utxo_ref, coin)| (utxo_ref, coin.output)));
+        }
+        Ok(utxos)
+    }
+
+    pub fn balance<S: UtxoStore>(&self, utxo_db: &S) -> Result<u64, String> {
+        Ok(self.utxos(utxo_db)?.iter()
+            .map(|(_, output)| output.amount)
+            .sum())
+    }
+
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    // Reinstates derivation indices and history saved from an earlier session.
+    pub fn restore_state(
+        &mut self,
+        next_receive: u32,
+        next_change: u32,
+        history: Vec<HistoryEntry>,
+    ) -> Result<(), String> {
+        if next_receive > 0 {
+            self.mark_used(Chain::Receive, next_receive - 1)?;
+        }
+        if next_change > 0 {
+            self.mark_used(Chain::Change, next_change - 1)?;
+        }
+        self.history = history;
+        Ok(())
+    }
+
+    // Records a transaction that touches the wallet. Must be called before the transaction
+    // is applied to `utxo_db`, while the coins it spends can still be looked up.
+    pub fn record_transaction<S: UtxoStore>(
+        &mut self,
+        tx: &Transaction,
+        utxo_db: &S,
+    ) -> Result<Option<&HistoryEntry>, String> {
+        let mut sent = 0;
+        for input in &tx.inputs {
+            if let Some(coin) = utxo_db.get(&input.utxo_ref)?
+                && self.is_mine(&coin.output.script_pubkey)
+            {
+                sent += coin.output.amount;
+            }
+        }
+
+        let mut received = 0;
+        let mut used = Vec::new();
+        for output in &tx.outputs {
+            if let Some(info) = self.script_info(&output.script_pubkey) {
+                received += output.amount;
+                used.push(info);
+            }
+        }
+        for (chain, index) in used {
+            self.mark_used(chain, index)?;
+        }
+
+        if sent == 0 && received == 0 {
+            return Ok(None);
+        }
+        self.history.push(HistoryEntry {
+            txid: generate_txid(tx),
+            received,
+            sent,
+            transaction: tx.clone(),
+        });
+        Ok(self.history.last())
+    }
+
+    pub fn create_transaction<S: UtxoStore>(
+        &mut self,
+        utxo_db: &S,
+        payments: &[Payment],
+        fee_per_vbyte: u64,
+    ) -> Result<Transaction, String> {
+        validate_payments(payments)?;
+        let mut coins: Vec<(UtxoRef, u64)> = self.utxos(utxo_db)?.into_iter()
+            .map(|(utxo_ref, output)| (utxo_ref, output.amount))
+            .collect();
+        sort_largest_first(&mut coins);
+
+        let change_index = self.next_index(Chain::Change);
+        let change_script = self.descriptor(Chain::Change).script_pubkey(change_index)?;
+        let tx = build_funded_transaction(&coins, payments, &change_script, fee_per_vbyte)?;
+        if tx.outputs.iter().any(|output| output.script_pubkey == change_script) {
+            self.mark_used(Chain::Change, change_index)?;
+        }
+        Ok(tx)
+    }
+
+    // Builds an unsigned transaction and wraps it in a PSBT carrying the UTXOs and key
+    // origins an offline signer needs.
+    pub fn create_psbt<S: UtxoStore>(
+        &mut self,
+        utxo_db: &S,
+        payments: &[Payment],
+        fee_per_vbyte: u64,
+    ) -> Result<Psbt, String> {
+        let tx = self.create_transaction(utxo_db, payments, fee_per_vbyte)?;
+        let mut psbt = Psbt::new_v0(&tx)?;
+
+        for chain in [Chain::Receive, Chain::Change] {
+            if let Some((xpub, fingerprint, path)) = self.descriptor(chain).xpub_with_origin() {
+                psbt.add_global_xpub(&xpub.encode(), fingerprint, &path);
+            }
+        }
+
+        for (index, input) in tx.inputs.iter().enumerate() {
+            let spent = utxo_db.get(&input.utxo_ref)?
+                .ok_or_else(|| format!("Input {index} spends an unknown coin"))?;
+            let (chain, derivation_index) = self.script_info(&spent.output.script_pubkey)
+                .ok_or_else(|| format!("Input {index} spends a coin the wallet does not own"))?;
+            let descriptor = self.descriptor(chain);
+
+            psbt.set_witness_utxo(index, &spent.output)?;
+            if descriptor.kind == ScriptKind::Pkh
+                && let Some(entry) = self.history.iter().find(|entry| entry.txid == input.utxo_ref.txid)
+            {
+                psbt.set_non_witness_utxo(index, &entry.transaction)?;
+            }
+            let pubkey = descriptor.derive_public_key(derivation_index)?.serialize();
+            let (fingerprint, path) = descriptor.key_source(derivation_index);
+            psbt.add_input_bip32_derivation(index, &pubkey, fingerprint, &path)?;
+        }
+
+        for (index, output) in tx.outputs.iter().enumerate() {
+            if let Some((chain, derivation_index)) = self.script_info(&output.script_pubkey) {
+                let descriptor = self.descriptor(chain);
+                let pubkey = descriptor.derive_public_key(derivation_index)?.serialize();
+                let (fingerprint, path) = descriptor.key_source(derivation_index);
+                psbt.add_output_bip32_derivation(index, &pubkey, fingerprint, &path)?;
+            }
+        }
+        Ok(psbt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::coin::Coin;
+    use crate::transaction::database::UtxoDatabase;
+    use crate::transaction::psbt::{PSBT_GLOBAL_XPUB, PSBT_IN_BIP32_DERIVATION, PSBT_IN_WITNESS_UTXO};
+    use crate::transaction::script;
+    use crate::transaction::script_index::IndexedUtxoStore;
+    use crate::transaction::transaction::TxInput;
+
+    const XPUB: &str = "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL";
+
+    fn fund(utxo_db: &mut UtxoDatabase, wallet: &mut WatchOnlyWallet, script_pubkey: Vec<u8>, amount: u64, seed: u8) {
+        let tx = Transaction {
+            version: 1,
+            inputs: vec![TxInput::new(UtxoRef { txid: [seed; 32], vout: 0 })],
+            outputs: vec![TxOutput::new(script_pubkey, amount)],
+            locktime: 0,
+        };
+        wallet.record_transaction(&tx, utxo_db).unwrap();
+        utxo_db.add_utxo(UtxoRef { txid: generate_txid(&tx), vout: 0 }, Coin::new(tx.outputs[0].clone(), 1, false));
+    }
+
+    #[test]
+    fn test_balance_and_history_from_utxo_db() {
+        let mut wallet = WatchOnlyWallet::from_xpub(XPUB, ScriptKind::Wpkh).unwrap();
+        let mut utxo_db = UtxoDatabase::new();
+
+        let first = wallet.next_receive_script().unwrap();
+        let second = wallet.next_receive_script().unwrap();
+        fund(&mut utxo_db, &mut wallet, first, 30_000, 1);
+        fund(&mut utxo_db, &mut wallet, second, 20_000, 2);
+        fund(&mut utxo_db, &mut wallet, script::p2wpkh(&[4u8; 20]), 99_000, 3);
+
+        assert_eq!(wallet.balance(&utxo_db).unwrap(), 50_000);
+        assert_eq!(wallet.utxos(&utxo_db).unwrap().len(), 2);
+        assert_eq!(wallet.history().len(), 2);
+        assert_eq!(wallet.history()[0].received, 30_000);
+
+        let indexed = IndexedUtxoStore::new(utxo_db).unwrap();
+        assert_eq!(wallet.balance(&indexed).unwrap(), 50_000);
+    }
+
+    #[test]
+    fn test_lookahead_recognises_unissued_addresses() {
+        let mut wallet = WatchOnlyWallet::from_xpub(XPUB, ScriptKind::Wpkh).unwrap();
+        let mut utxo_db = UtxoDatabase::new();
+        let script_at_10 = wallet.descriptor(Chain::Receive).script_pubkey(10).unwrap();
+
+        fund(&mut utxo_db, &mut wallet, script_at_10, 5_000, 1);
+
+        assert_eq!(wallet.next_index(Chain::Receive), 11);
+        let script_at_30 = wallet.descriptor(Chain::Receive).script_pubkey(30).unwrap();
+        assert!(wallet.is_mine(&script_at_30));
+        assert_eq!(wallet.balance(&utxo_db).unwrap(), 5_000);
+    }
+
+    #[test]
+    fn test_create_psbt_for_offline_signer() {
+        let receive = format!("wpkh([d34db33f/84h/0h/0h]{XPUB}/0/*)");
+        let change = format!("wpkh([d34db33f/84h/0h/0h]{XPUB}/1/*)");
+        let mut wallet = WatchOnlyWallet::from_descriptors(&receive, &change).unwrap();
+        let mut utxo_db = UtxoDatabase::new();
+        let script_pubkey = wallet.next_receive_script().unwrap();
+        fund(&mut utxo_db, &mut wallet, script_pubkey, 80_000, 1);
+
+        let payments = vec![Payment::to_address(&[6u8; 20], 30_000)];
+        let psbt = wallet.create_psbt(&utxo_db, &payments, 2).unwrap();
+
+        let tx = psbt.unsigned_tx().unwrap();
+        assert_eq!(tx.outputs.len(), 2);
+        assert!(tx.inputs.iter().all(|input| input.script_sig.is_empty()));
+        assert!(psbt.global.contains(PSBT_GLOBAL_XPUB));
+        assert!(psbt.inputs[0].contains(PSBT_IN_WITNESS_UTXO));
+        let (_, origin) = psbt.inputs[0].keys_of_type(PSBT_IN_BIP32_DERIVATION).next().unwrap();
+        assert_eq!(&origin[..4], &[0xd3, 0x4d, 0xb3, 0x3f]);
+        assert_eq!(wallet.next_index(Chain::Change), 1);
+    }
+
+    #[test]
+    fn test_rejects_private_descriptors() {
+        let xprv = "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi";
+        assert!(WatchOnlyWallet::from_xpub(xprv, ScriptKind::Wpkh).is_err());
+        let descriptor = format!("wpkh({xprv}/0/*)");
+        assert!(WatchOnlyWallet::from_descriptors(&descriptor, &descriptor).is_err());
+    }
+}